@@ -0,0 +1,14 @@
+ALTER TABLE items ADD COLUMN IF NOT EXISTS expense_date DATE NOT NULL DEFAULT CURRENT_DATE;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS receipt_hash VARCHAR(64);
+
+CREATE INDEX IF NOT EXISTS items_receipt_hash_idx ON items (receipt_hash);
+
+CREATE TABLE IF NOT EXISTS claim_flags (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  claim_id INTEGER NOT NULL REFERENCES claims (id),
+  item_id INTEGER REFERENCES items (id),
+  kind VARCHAR(50) NOT NULL,
+  reason TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS claim_flags_claim_id_idx ON claim_flags (claim_id);
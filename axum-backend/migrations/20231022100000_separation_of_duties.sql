@@ -0,0 +1,13 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS manager_id INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS users_manager_id_idx ON users (manager_id);
+
+CREATE TABLE IF NOT EXISTS claim_decisions (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  claim_id INTEGER NOT NULL REFERENCES claims (id),
+  approver_id INTEGER NOT NULL REFERENCES users (id),
+  accepted BOOLEAN NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS claim_decisions_claim_id_idx ON claim_decisions (claim_id);
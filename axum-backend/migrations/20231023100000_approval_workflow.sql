@@ -0,0 +1,14 @@
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS current_step INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS claim_steps (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  claim_id INTEGER NOT NULL REFERENCES claims (id),
+  position INTEGER NOT NULL,
+  kind VARCHAR(50) NOT NULL,
+  UNIQUE (claim_id, position)
+);
+
+-- Existing claims were approved by a single manager
+INSERT INTO claim_steps ( claim_id, position, kind ) SELECT id, 0, 'LineManager' FROM claims;
+
+ALTER TABLE claim_decisions ADD COLUMN IF NOT EXISTS step INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS delegations (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  delegator_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  delegate_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  starts_on DATE NOT NULL,
+  ends_on DATE NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CHECK (delegator_id <> delegate_id),
+  CHECK (starts_on <= ends_on)
+);
+
+CREATE INDEX IF NOT EXISTS delegations_delegate_id_idx ON delegations (delegate_id);
+CREATE INDEX IF NOT EXISTS delegations_delegator_id_idx ON delegations (delegator_id);
+
+ALTER TABLE claim_decisions ADD COLUMN IF NOT EXISTS on_behalf_of INTEGER REFERENCES users (id);
@@ -0,0 +1,11 @@
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS step_started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+CREATE TABLE IF NOT EXISTS claim_reminders (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  claim_id INTEGER NOT NULL REFERENCES claims (id),
+  step INTEGER NOT NULL,
+  level VARCHAR(30) NOT NULL,
+  recipient_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  sent_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (claim_id, step, level, recipient_id)
+);
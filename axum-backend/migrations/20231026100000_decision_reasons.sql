@@ -0,0 +1 @@
+ALTER TABLE claim_decisions ADD COLUMN IF NOT EXISTS reason TEXT;
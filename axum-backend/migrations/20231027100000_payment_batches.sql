@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS payment_batches (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  status VARCHAR(30) NOT NULL DEFAULT 'Open',
+  total NUMERIC NOT NULL,
+  created_by INTEGER NOT NULL REFERENCES users (id),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  exported_at TIMESTAMP,
+  paid_at TIMESTAMP,
+  reference VARCHAR(35)
+);
+
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS payment_batch_id INTEGER REFERENCES payment_batches (id);
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS paid_at TIMESTAMP;
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS payment_reference VARCHAR(35);
+
+CREATE INDEX IF NOT EXISTS claims_payment_batch_id_idx ON claims (payment_batch_id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS bank_details (
+  user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+  account_holder VARCHAR(70) NOT NULL,
+  iban VARCHAR(34) NOT NULL,
+  bic VARCHAR(11),
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
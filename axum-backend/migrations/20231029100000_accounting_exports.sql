@@ -0,0 +1,33 @@
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS gl_account VARCHAR(20);
+
+CREATE TABLE IF NOT EXISTS accounting_exports (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  period_start DATE NOT NULL,
+  period_end DATE NOT NULL,
+  created_by INTEGER NOT NULL REFERENCES users (id),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (period_start, period_end)
+);
+
+-- A claim is booked once when it is accepted and once when it is paid
+CREATE TABLE IF NOT EXISTS accounting_export_entries (
+  claim_id INTEGER NOT NULL REFERENCES claims (id) ON DELETE CASCADE,
+  kind VARCHAR(30) NOT NULL,
+  export_id INTEGER NOT NULL REFERENCES accounting_exports (id) ON DELETE CASCADE,
+  entry_date DATE NOT NULL,
+  description VARCHAR(255) NOT NULL,
+  PRIMARY KEY (claim_id, kind)
+);
+
+CREATE INDEX IF NOT EXISTS accounting_export_entries_export_id_idx ON accounting_export_entries (export_id);
+
+CREATE TABLE IF NOT EXISTS accounting_export_lines (
+  claim_id INTEGER NOT NULL,
+  kind VARCHAR(30) NOT NULL,
+  position INTEGER NOT NULL,
+  account VARCHAR(20) NOT NULL,
+  debit NUMERIC NOT NULL,
+  credit NUMERIC NOT NULL,
+  PRIMARY KEY (claim_id, kind, position),
+  FOREIGN KEY (claim_id, kind) REFERENCES accounting_export_entries (claim_id, kind) ON DELETE CASCADE
+);
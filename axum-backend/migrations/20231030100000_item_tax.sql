@@ -0,0 +1,13 @@
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS default_tax_rate DECIMAL(5,2) NOT NULL DEFAULT 0;
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS reimbursement_base VARCHAR(10) NOT NULL DEFAULT 'Gross';
+
+-- Existing items are treated as untaxed
+ALTER TABLE items ADD COLUMN IF NOT EXISTS net_cost NUMERIC;
+UPDATE items SET net_cost = cost WHERE net_cost IS NULL;
+ALTER TABLE items ALTER COLUMN net_cost SET NOT NULL;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS tax_rate DECIMAL(5,2) NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS tax_amount NUMERIC NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS reimbursed_tax NUMERIC NOT NULL DEFAULT 0;
+
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS total_tax NUMERIC;
+UPDATE claims SET total_tax = 0 WHERE total_tax IS NULL AND total_cost IS NOT NULL;
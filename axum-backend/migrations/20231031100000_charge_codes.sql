@@ -0,0 +1,26 @@
+-- Cost centers and project codes, archived instead of deleted so old claims keep them
+CREATE TABLE IF NOT EXISTS charge_codes (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  kind VARCHAR(30) NOT NULL,
+  code VARCHAR(20) NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  archived BOOLEAN NOT NULL DEFAULT FALSE,
+  UNIQUE (kind, code)
+);
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS default_cost_center_id INTEGER REFERENCES charge_codes (id);
+
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS cost_center_id INTEGER REFERENCES charge_codes (id);
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS project_id INTEGER REFERENCES charge_codes (id);
+
+-- Item codes override the ones of the claim
+ALTER TABLE items ADD COLUMN IF NOT EXISTS cost_center_id INTEGER REFERENCES charge_codes (id);
+ALTER TABLE items ADD COLUMN IF NOT EXISTS project_id INTEGER REFERENCES charge_codes (id);
+
+CREATE INDEX IF NOT EXISTS claims_cost_center_id_idx ON claims (cost_center_id);
+CREATE INDEX IF NOT EXISTS claims_project_id_idx ON claims (project_id);
+CREATE INDEX IF NOT EXISTS items_cost_center_id_idx ON items (cost_center_id);
+CREATE INDEX IF NOT EXISTS items_project_id_idx ON items (project_id);
+
+ALTER TABLE accounting_export_lines ADD COLUMN IF NOT EXISTS cost_center VARCHAR(20);
+ALTER TABLE accounting_export_lines ADD COLUMN IF NOT EXISTS project VARCHAR(20);
@@ -0,0 +1,16 @@
+-- Budgets per cost center and period, optionally limited to one category
+CREATE TABLE IF NOT EXISTS budgets (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  cost_center_id INTEGER NOT NULL REFERENCES charge_codes (id),
+  category_id INTEGER REFERENCES categories (id) ON DELETE CASCADE,
+  period_start DATE NOT NULL,
+  period_end DATE NOT NULL,
+  amount NUMERIC NOT NULL CHECK (amount > 0),
+  -- What happens when an approval would exceed the budget
+  policy VARCHAR(30) NOT NULL DEFAULT 'Warn',
+  created_by INTEGER NOT NULL REFERENCES users (id),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CHECK (period_start <= period_end)
+);
+
+CREATE INDEX IF NOT EXISTS budgets_cost_center_id_idx ON budgets (cost_center_id);
@@ -0,0 +1,41 @@
+-- Lifecycle timestamps, existing rows are backfilled from the approval history
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS created_at TIMESTAMP;
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS submitted_at TIMESTAMP;
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS decided_at TIMESTAMP;
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+
+-- The first step starts on submission, earlier decisions mean it was submitted before them
+UPDATE claims SET created_at = LEAST(
+  claims.step_started_at,
+  (SELECT MIN(created_at) FROM claim_decisions WHERE claim_decisions.claim_id = claims.id)
+)
+WHERE claims.created_at IS NULL;
+
+UPDATE claims SET submitted_at = created_at WHERE submitted_at IS NULL;
+
+UPDATE claims SET decided_at = (
+  SELECT MAX(created_at) FROM claim_decisions WHERE claim_decisions.claim_id = claims.id
+)
+WHERE claims.decided_at IS NULL AND claims.status <> 'Pending';
+
+UPDATE claims SET updated_at = GREATEST(created_at, step_started_at, decided_at, paid_at)
+WHERE updated_at IS NULL;
+
+ALTER TABLE claims ALTER COLUMN created_at SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE claims ALTER COLUMN created_at SET NOT NULL;
+ALTER TABLE claims ALTER COLUMN updated_at SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE claims ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS claims_created_at_idx ON claims (created_at);
+
+ALTER TABLE items ADD COLUMN IF NOT EXISTS created_at TIMESTAMP;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP;
+
+UPDATE items SET created_at = claims.created_at, updated_at = claims.created_at
+FROM claims
+WHERE claims.id = items.claim_id AND items.created_at IS NULL;
+
+ALTER TABLE items ALTER COLUMN created_at SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE items ALTER COLUMN created_at SET NOT NULL;
+ALTER TABLE items ALTER COLUMN updated_at SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE items ALTER COLUMN updated_at SET NOT NULL;
@@ -0,0 +1,5 @@
+-- Free text shown in claim lists and matched by the claim search
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS description VARCHAR(500);
+
+CREATE INDEX IF NOT EXISTS claims_status_idx ON claims (status);
+CREATE INDEX IF NOT EXISTS claims_updated_at_idx ON claims (updated_at);
@@ -0,0 +1,14 @@
+-- Tokens carry the version they were issued for, bumping it logs the user out everywhere
+ALTER TABLE users ADD COLUMN IF NOT EXISTS session_version INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS role_changes (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  -- Empty when changed from the admin CLI
+  changed_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  old_role VARCHAR(30) NOT NULL,
+  new_role VARCHAR(30) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS role_changes_user_id_idx ON role_changes (user_id);
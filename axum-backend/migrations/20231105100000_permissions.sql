@@ -0,0 +1,52 @@
+-- Roles grant named permissions, the built-in ones mirror the former role hierarchy
+CREATE TABLE IF NOT EXISTS roles (
+  name VARCHAR(50) PRIMARY KEY,
+  -- Built-in roles are referenced by the code and cannot be changed
+  builtin BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+  role VARCHAR(50) NOT NULL REFERENCES roles (name) ON DELETE CASCADE,
+  permission VARCHAR(50) NOT NULL,
+  PRIMARY KEY (role, permission)
+);
+
+INSERT INTO roles ( name, builtin ) VALUES
+  ('User', TRUE),
+  ('Manager', TRUE),
+  ('Finance', TRUE),
+  ('Admin', TRUE)
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions ( role, permission ) VALUES
+  ('Manager', 'claims.approve'),
+  ('Manager', 'reports.view'),
+  ('Finance', 'claims.approve'),
+  ('Finance', 'claims.approve_finance'),
+  ('Finance', 'claims.view_all'),
+  ('Finance', 'reports.view'),
+  ('Finance', 'budgets.manage'),
+  ('Finance', 'payments.manage'),
+  ('Finance', 'accounting.export'),
+  ('Finance', 'bank_details.manage'),
+  ('Admin', 'claims.approve'),
+  ('Admin', 'claims.approve_any'),
+  ('Admin', 'claims.approve_finance'),
+  ('Admin', 'claims.view_all'),
+  ('Admin', 'reports.view'),
+  ('Admin', 'budgets.manage'),
+  ('Admin', 'payments.manage'),
+  ('Admin', 'accounting.export'),
+  ('Admin', 'bank_details.manage'),
+  ('Admin', 'categories.manage'),
+  ('Admin', 'charge_codes.manage'),
+  ('Admin', 'users.manage'),
+  ('Admin', 'delegations.manage'),
+  ('Admin', 'roles.manage')
+ON CONFLICT (role, permission) DO NOTHING;
+
+-- Unknown roles used to fall back to User
+UPDATE users SET role = 'User' WHERE role NOT IN (SELECT name FROM roles);
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_role_fkey;
+ALTER TABLE users ADD CONSTRAINT users_role_fkey FOREIGN KEY (role) REFERENCES roles (name);
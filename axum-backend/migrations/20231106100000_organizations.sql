@@ -0,0 +1,195 @@
+CREATE TABLE IF NOT EXISTS organizations (
+  id INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+  slug VARCHAR(50) NOT NULL UNIQUE,
+  name VARCHAR(100) NOT NULL,
+  -- Overrides of the server configuration, see `OrganizationSettings`
+  settings JSONB NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Existing data belongs to the first organisation
+INSERT INTO organizations ( slug, name ) SELECT 'default', 'Default'
+WHERE NOT EXISTS (SELECT 1 FROM organizations);
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE users SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE users ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS users_organization_id_idx ON users (organization_id);
+
+ALTER TABLE categories ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE categories SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE categories ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS categories_organization_id_idx ON categories (organization_id);
+
+ALTER TABLE charge_codes ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE charge_codes SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE charge_codes ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS charge_codes_organization_id_idx ON charge_codes (organization_id);
+
+ALTER TABLE claims ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE claims SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE claims ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS claims_organization_id_idx ON claims (organization_id);
+
+ALTER TABLE budgets ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE budgets SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE budgets ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS budgets_organization_id_idx ON budgets (organization_id);
+
+ALTER TABLE payment_batches ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE payment_batches SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE payment_batches ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS payment_batches_organization_id_idx ON payment_batches (organization_id);
+
+ALTER TABLE accounting_exports ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE accounting_exports SET organization_id = (SELECT MIN(id) FROM organizations) WHERE organization_id IS NULL;
+ALTER TABLE accounting_exports ALTER COLUMN organization_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS accounting_exports_organization_id_idx ON accounting_exports (organization_id);
+
+-- Built-in roles are shared, custom roles belong to the organisation that created them
+ALTER TABLE roles ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE roles SET organization_id = (SELECT MIN(id) FROM organizations) WHERE NOT builtin AND organization_id IS NULL;
+
+-- Names only have to be unique within an organisation
+ALTER TABLE categories DROP CONSTRAINT IF EXISTS categories_name_key;
+ALTER TABLE categories DROP CONSTRAINT IF EXISTS categories_organization_id_name_key;
+ALTER TABLE categories ADD CONSTRAINT categories_organization_id_name_key UNIQUE (organization_id, name);
+ALTER TABLE charge_codes DROP CONSTRAINT IF EXISTS charge_codes_kind_code_key;
+ALTER TABLE charge_codes DROP CONSTRAINT IF EXISTS charge_codes_organization_id_kind_code_key;
+ALTER TABLE charge_codes ADD CONSTRAINT charge_codes_organization_id_kind_code_key UNIQUE (organization_id, kind, code);
+ALTER TABLE accounting_exports DROP CONSTRAINT IF EXISTS accounting_exports_period_start_period_end_key;
+ALTER TABLE accounting_exports DROP CONSTRAINT IF EXISTS accounting_exports_organization_id_period_key;
+ALTER TABLE accounting_exports ADD CONSTRAINT accounting_exports_organization_id_period_key UNIQUE (organization_id, period_start, period_end);
+
+-- Admins change the settings of their own organisation
+INSERT INTO role_permissions ( role, permission ) VALUES ('Admin', 'settings.manage')
+ON CONFLICT (role, permission) DO NOTHING;
+
+-- Super-admins manage the organisations on top of everything admins can do
+INSERT INTO roles ( name, builtin ) VALUES ('SuperAdmin', TRUE) ON CONFLICT (name) DO NOTHING;
+INSERT INTO role_permissions ( role, permission )
+SELECT 'SuperAdmin', permission FROM role_permissions WHERE role = 'Admin'
+UNION SELECT 'SuperAdmin', 'organizations.manage'
+ON CONFLICT (role, permission) DO NOTHING;
+
+-- Organisation of the current request, empty outside of requests
+-- (migrations, background jobs and the admin CLI) where every row is visible
+CREATE OR REPLACE FUNCTION current_organization_id() RETURNS INTEGER AS $$
+  SELECT NULLIF(current_setting('app.organization_id', true), '')::INTEGER
+$$ LANGUAGE SQL STABLE;
+
+-- Row level security only applies to roles that are neither superusers nor have BYPASSRLS
+
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON users;
+CREATE POLICY tenant_isolation ON users
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE categories ENABLE ROW LEVEL SECURITY;
+ALTER TABLE categories FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON categories;
+CREATE POLICY tenant_isolation ON categories
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE charge_codes ENABLE ROW LEVEL SECURITY;
+ALTER TABLE charge_codes FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON charge_codes;
+CREATE POLICY tenant_isolation ON charge_codes
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE claims ENABLE ROW LEVEL SECURITY;
+ALTER TABLE claims FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON claims;
+CREATE POLICY tenant_isolation ON claims
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE budgets ENABLE ROW LEVEL SECURITY;
+ALTER TABLE budgets FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON budgets;
+CREATE POLICY tenant_isolation ON budgets
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE payment_batches ENABLE ROW LEVEL SECURITY;
+ALTER TABLE payment_batches FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON payment_batches;
+CREATE POLICY tenant_isolation ON payment_batches
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE accounting_exports ENABLE ROW LEVEL SECURITY;
+ALTER TABLE accounting_exports FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON accounting_exports;
+CREATE POLICY tenant_isolation ON accounting_exports
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE roles ENABLE ROW LEVEL SECURITY;
+ALTER TABLE roles FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON roles;
+CREATE POLICY tenant_isolation ON roles
+  USING (current_organization_id() IS NULL OR organization_id IS NULL OR organization_id = current_organization_id());
+
+ALTER TABLE role_permissions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE role_permissions FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON role_permissions;
+CREATE POLICY tenant_isolation ON role_permissions
+  USING (current_organization_id() IS NULL OR role IN (SELECT name FROM roles));
+
+ALTER TABLE items ENABLE ROW LEVEL SECURITY;
+ALTER TABLE items FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON items;
+CREATE POLICY tenant_isolation ON items
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE claim_flags ENABLE ROW LEVEL SECURITY;
+ALTER TABLE claim_flags FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON claim_flags;
+CREATE POLICY tenant_isolation ON claim_flags
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE claim_decisions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE claim_decisions FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON claim_decisions;
+CREATE POLICY tenant_isolation ON claim_decisions
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE claim_steps ENABLE ROW LEVEL SECURITY;
+ALTER TABLE claim_steps FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON claim_steps;
+CREATE POLICY tenant_isolation ON claim_steps
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE claim_reminders ENABLE ROW LEVEL SECURITY;
+ALTER TABLE claim_reminders FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON claim_reminders;
+CREATE POLICY tenant_isolation ON claim_reminders
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE accounting_export_lines ENABLE ROW LEVEL SECURITY;
+ALTER TABLE accounting_export_lines FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON accounting_export_lines;
+CREATE POLICY tenant_isolation ON accounting_export_lines
+  USING (current_organization_id() IS NULL OR claim_id IN (SELECT id FROM claims));
+
+ALTER TABLE accounting_export_entries ENABLE ROW LEVEL SECURITY;
+ALTER TABLE accounting_export_entries FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON accounting_export_entries;
+CREATE POLICY tenant_isolation ON accounting_export_entries
+  USING (current_organization_id() IS NULL OR export_id IN (SELECT id FROM accounting_exports));
+
+ALTER TABLE bank_details ENABLE ROW LEVEL SECURITY;
+ALTER TABLE bank_details FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON bank_details;
+CREATE POLICY tenant_isolation ON bank_details
+  USING (current_organization_id() IS NULL OR user_id IN (SELECT id FROM users));
+
+ALTER TABLE delegations ENABLE ROW LEVEL SECURITY;
+ALTER TABLE delegations FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON delegations;
+CREATE POLICY tenant_isolation ON delegations
+  USING (current_organization_id() IS NULL OR delegator_id IN (SELECT id FROM users));
+
+ALTER TABLE role_changes ENABLE ROW LEVEL SECURITY;
+ALTER TABLE role_changes FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON role_changes;
+CREATE POLICY tenant_isolation ON role_changes
+  USING (current_organization_id() IS NULL OR user_id IN (SELECT id FROM users));
@@ -0,0 +1,16 @@
+-- Receipt files, items refer to them by the SHA-256 of their content
+CREATE TABLE IF NOT EXISTS receipts (
+  organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+  hash VARCHAR(64) NOT NULL,
+  content_type VARCHAR(50) NOT NULL,
+  data BYTEA NOT NULL,
+  uploaded_by INTEGER NOT NULL REFERENCES users (id),
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (organization_id, hash)
+);
+
+ALTER TABLE receipts ENABLE ROW LEVEL SECURITY;
+ALTER TABLE receipts FORCE ROW LEVEL SECURITY;
+DROP POLICY IF EXISTS tenant_isolation ON receipts;
+CREATE POLICY tenant_isolation ON receipts
+  USING (current_organization_id() IS NULL OR organization_id = current_organization_id());
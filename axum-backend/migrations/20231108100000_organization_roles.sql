@@ -0,0 +1,39 @@
+-- Custom roles are keyed by their organisation and name, so one organisation's role names
+-- neither block nor reveal another's. Built-in roles have no organisation.
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_role_fkey;
+ALTER TABLE role_permissions DROP CONSTRAINT IF EXISTS role_permissions_role_fkey;
+ALTER TABLE role_permissions DROP CONSTRAINT IF EXISTS role_permissions_pkey;
+ALTER TABLE roles DROP CONSTRAINT IF EXISTS roles_pkey;
+ALTER TABLE roles DROP CONSTRAINT IF EXISTS roles_organization_id_name_key;
+ALTER TABLE roles ADD CONSTRAINT roles_organization_id_name_key UNIQUE NULLS NOT DISTINCT (organization_id, name);
+
+ALTER TABLE role_permissions ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+UPDATE role_permissions SET organization_id = roles.organization_id
+FROM roles WHERE roles.name = role_permissions.role AND roles.organization_id IS NOT NULL;
+ALTER TABLE role_permissions DROP CONSTRAINT IF EXISTS role_permissions_organization_id_role_permission_key;
+ALTER TABLE role_permissions ADD CONSTRAINT role_permissions_organization_id_role_permission_key
+  UNIQUE NULLS NOT DISTINCT (organization_id, role, permission);
+ALTER TABLE role_permissions ADD CONSTRAINT role_permissions_role_fkey
+  FOREIGN KEY (organization_id, role) REFERENCES roles (organization_id, name) ON DELETE CASCADE;
+
+-- Users hold a built-in role or a custom role of their organisation. The role is locked
+-- like a foreign key would, so it cannot be deleted while it is being assigned.
+CREATE OR REPLACE FUNCTION check_user_role() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM 1 FROM roles
+  WHERE name = NEW.role AND (organization_id IS NULL OR organization_id = NEW.organization_id)
+  FOR KEY SHARE;
+  IF NOT FOUND THEN
+    RAISE foreign_key_violation USING MESSAGE = format('Role %s does not exist', NEW.role);
+  END IF;
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS users_role_check ON users;
+CREATE TRIGGER users_role_check BEFORE INSERT OR UPDATE OF role, organization_id ON users
+  FOR EACH ROW EXECUTE FUNCTION check_user_role();
+
+DROP POLICY IF EXISTS tenant_isolation ON role_permissions;
+CREATE POLICY tenant_isolation ON role_permissions
+  USING (current_organization_id() IS NULL OR organization_id IS NULL OR organization_id = current_organization_id());
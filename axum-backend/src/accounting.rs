@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::models::JournalEntryKind;
+
+/// Reimbursement of one claim to book, per general ledger account of its categories
+#[derive(Debug, Clone)]
+pub struct Booking {
+    pub claim_id: i32,
+    pub user_id: i32,
+    pub kind: JournalEntryKind,
+    pub date: NaiveDate,
+    pub amounts: Vec<BookingAmount>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookingAmount {
+    /// Expense account of the category, `None` for categories without an account
+    pub account: Option<String>,
+    pub amount: Decimal,
+    /// Reclaimable tax included in the amount
+    pub tax: Decimal,
+    pub cost_center: Option<String>,
+    pub project: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalLine {
+    pub account: String,
+    pub debit: Decimal,
+    pub credit: Decimal,
+    /// Only set on expense lines
+    #[serde(rename = "costCenter")]
+    pub cost_center: Option<String>,
+    pub project: Option<String>,
+}
+
+/// Balanced double-entry journal entry
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub date: NaiveDate,
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    pub description: String,
+    pub lines: Vec<JournalLine>,
+}
+
+/// Rounds to cents and always keeps two decimals, so the output does not depend on the stored scale
+pub fn cents(value: Decimal) -> Decimal {
+    let mut value = value.round_dp(2);
+    value.rescale(2);
+    value
+}
+
+fn debit(account: &str, amount: Decimal) -> JournalLine {
+    JournalLine {
+        account: account.to_owned(),
+        debit: amount,
+        credit: cents(Decimal::from(0)),
+        cost_center: None,
+        project: None,
+    }
+}
+
+fn credit(account: &str, amount: Decimal) -> JournalLine {
+    JournalLine {
+        account: account.to_owned(),
+        debit: cents(Decimal::from(0)),
+        credit: amount,
+        cost_center: None,
+        project: None,
+    }
+}
+
+pub fn entry_id(claim_id: i32, kind: JournalEntryKind) -> String {
+    format!("CLAIM-{}-{}", claim_id, kind.to_string().to_uppercase())
+}
+
+pub fn journal_entry(config: &Config, booking: &Booking) -> JournalEntry {
+    let mut expenses: BTreeMap<(&str, Option<&str>, Option<&str>), Decimal> = BTreeMap::new();
+    let mut tax = Decimal::from(0);
+    for amount in &booking.amounts {
+        let account = amount
+            .account
+            .as_deref()
+            .unwrap_or(&config.accounting_expense_account);
+        let key = (
+            account,
+            amount.cost_center.as_deref(),
+            amount.project.as_deref(),
+        );
+        *expenses.entry(key).or_default() += amount.amount - amount.tax;
+        tax += amount.tax;
+    }
+    // Rounding per line keeps the entry balanced
+    let mut expenses: Vec<JournalLine> = expenses
+        .into_iter()
+        .map(|((account, cost_center, project), amount)| JournalLine {
+            cost_center: cost_center.map(str::to_owned),
+            project: project.map(str::to_owned),
+            ..debit(account, cents(amount))
+        })
+        .collect();
+    if !tax.is_zero() {
+        expenses.push(debit(&config.accounting_tax_account, cents(tax)));
+    }
+    let total = cents(expenses.iter().map(|line| line.debit).sum());
+
+    let (description, lines) = match booking.kind {
+        JournalEntryKind::Accrual => (
+            format!(
+                "Expense claim {} of employee {}",
+                booking.claim_id, booking.user_id
+            ),
+            expenses
+                .into_iter()
+                .chain([credit(&config.accounting_payable_account, total)])
+                .collect(),
+        ),
+        JournalEntryKind::Payment => (
+            format!(
+                "Payment of expense claim {} to employee {}",
+                booking.claim_id, booking.user_id
+            ),
+            vec![
+                debit(&config.accounting_payable_account, total),
+                credit(&config.accounting_bank_account, total),
+            ],
+        ),
+    };
+
+    JournalEntry {
+        id: entry_id(booking.claim_id, booking.kind),
+        date: booking.date,
+        claim_id: booking.claim_id,
+        description,
+        lines,
+    }
+}
+
+/// One row per journal line, rows of the same entry share the entry id
+pub fn to_csv(entries: &[JournalEntry]) -> Result<String, csv::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record([
+        "entry_id",
+        "date",
+        "claim_id",
+        "description",
+        "account",
+        "debit",
+        "credit",
+        "cost_center",
+        "project",
+    ])?;
+    for entry in entries {
+        for line in &entry.lines {
+            writer.write_record([
+                entry.id.clone(),
+                entry.date.to_string(),
+                entry.claim_id.to_string(),
+                entry.description.clone(),
+                line.account.clone(),
+                line.debit.to_string(),
+                line.credit.to_string(),
+                line.cost_center.clone().unwrap_or_default(),
+                line.project.clone().unwrap_or_default(),
+            ])?;
+        }
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn booking(kind: JournalEntryKind) -> Booking {
+        Booking {
+            claim_id: 7,
+            user_id: 3,
+            kind,
+            date: NaiveDate::from_ymd_opt(2023, 10, 2).unwrap(),
+            amounts: vec![
+                BookingAmount {
+                    account: Some("6650".to_string()),
+                    amount: Decimal::new(10005, 3),
+                    tax: Decimal::from(0),
+                    cost_center: Some("CC-10".to_string()),
+                    project: None,
+                },
+                BookingAmount {
+                    account: None,
+                    amount: Decimal::new(2, 0),
+                    tax: Decimal::from(0),
+                    cost_center: None,
+                    project: None,
+                },
+                BookingAmount {
+                    account: Some("6650".to_string()),
+                    amount: Decimal::new(5, 0),
+                    tax: Decimal::new(80, 2),
+                    cost_center: Some("CC-10".to_string()),
+                    project: None,
+                },
+                BookingAmount {
+                    account: Some("6650".to_string()),
+                    amount: Decimal::new(3, 0),
+                    tax: Decimal::from(0),
+                    cost_center: Some("CC-10".to_string()),
+                    project: Some("P-1".to_string()),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_accrual_entry() {
+        let config = Config::for_tests();
+        let entry = journal_entry(&config, &booking(JournalEntryKind::Accrual));
+        assert_eq!(entry.id, "CLAIM-7-ACCRUAL");
+        assert_eq!(
+            entry.lines,
+            vec![
+                debit("6000", Decimal::new(200, 2)),
+                JournalLine {
+                    cost_center: Some("CC-10".to_string()),
+                    ..debit("6650", Decimal::new(1420, 2))
+                },
+                JournalLine {
+                    cost_center: Some("CC-10".to_string()),
+                    project: Some("P-1".to_string()),
+                    ..debit("6650", Decimal::new(300, 2))
+                },
+                debit("1400", Decimal::new(80, 2)),
+                credit("2100", Decimal::new(2000, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_payment_entry() {
+        let config = Config::for_tests();
+        let entry = journal_entry(&config, &booking(JournalEntryKind::Payment));
+        assert_eq!(
+            entry.lines,
+            vec![
+                debit("2100", Decimal::new(2000, 2)),
+                credit("1000", Decimal::new(2000, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let config = Config::for_tests();
+        let csv = to_csv(&[journal_entry(&config, &booking(JournalEntryKind::Payment))]).unwrap();
+        assert_eq!(
+            csv,
+            "entry_id,date,claim_id,description,account,debit,credit,cost_center,project\n\
+            CLAIM-7-PAYMENT,2023-10-02,7,Payment of expense claim 7 to employee 3,2100,20.00,0.00,,\n\
+            CLAIM-7-PAYMENT,2023-10-02,7,Payment of expense claim 7 to employee 3,1000,0.00,20.00,,\n"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::config::Config;
+use crate::models::StepKind;
+use crate::permissions::{Permission, Permissions};
+
+/// Step of the configured approval workflow, required when the reimbursement exceeds `threshold`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowStep {
+    pub kind: StepKind,
+    pub threshold: Decimal,
+}
+
+impl FromStr for WorkflowStep {
+    type Err = anyhow::Error;
+
+    /// Parses `Kind:threshold`, e.g. `Finance:500`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (kind, threshold) = value
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Missing threshold in workflow step {}", value))?;
+        Ok(WorkflowStep {
+            kind: StepKind::from_str(kind.trim())?,
+            threshold: Decimal::from_str(threshold.trim())?,
+        })
+    }
+}
+
+/// Everything the separation of duties rules need to know about a pending claim
+#[derive(Debug, Clone)]
+pub struct ApprovalContext {
+    pub claimant_id: i32,
+    /// The claimant's managers up the reporting hierarchy, nearest first. The fallback approver
+    /// for claimants without a manager.
+    pub claimant_managers: Vec<i32>,
+    pub step: StepKind,
+    /// Users who already accepted the claim, oldest first
+    pub previous_approvers: Vec<i32>,
+}
+
+/// Steps of the workflow a claim with this reimbursement has to go through, in order.
+/// A claim always needs at least the first configured step.
+pub fn required_steps(config: &Config, reimbursement: Decimal) -> Vec<StepKind> {
+    let steps: Vec<StepKind> = config
+        .approval_workflow
+        .iter()
+        .filter(|step| reimbursement > step.threshold)
+        .map(|step| step.kind)
+        .collect();
+    if steps.is_empty() {
+        config
+            .approval_workflow
+            .first()
+            .map(|step| vec![step.kind])
+            .unwrap_or_default()
+    } else {
+        steps
+    }
+}
+
+/// Step kinds the permissions allow to act on for any claim, line manager steps are scoped separately
+pub fn unscoped_step_kinds(permissions: &Permissions) -> Vec<StepKind> {
+    [
+        (StepKind::LineManager, Permission::ClaimsApproveAny),
+        (StepKind::Manager, Permission::ClaimsApprove),
+        (StepKind::Finance, Permission::ClaimsApproveFinance),
+    ]
+    .into_iter()
+    .filter(|(_, permission)| permissions.has(*permission))
+    .map(|(kind, _)| kind)
+    .collect()
+}
+
+/// Whether the permissions allow to decide on any kind of step
+pub fn is_approver(permissions: &Permissions) -> bool {
+    permissions.has(Permission::ClaimsApprove) || !unscoped_step_kinds(permissions).is_empty()
+}
+
+/// Returns the reason why the approver is not allowed to decide on the current step
+pub fn check_approver(
+    config: &Config,
+    context: &ApprovalContext,
+    approver_id: i32,
+    permissions: &Permissions,
+) -> Result<(), &'static str> {
+    if context.claimant_id == approver_id {
+        return Err("You cannot approve your own claim");
+    }
+    if context.previous_approvers.contains(&approver_id) {
+        return Err("Each approval step must come from a different approver");
+    }
+    match context.step {
+        StepKind::LineManager
+            if !permissions.has(Permission::ClaimsApprove)
+                && !permissions.has(Permission::ClaimsApproveAny) =>
+        {
+            Err("You must be a manager to perform this action")
+        }
+        StepKind::LineManager
+            if config.require_assigned_manager
+                && !permissions.has(Permission::ClaimsApproveAny)
+                && context.claimant_managers.first() != Some(&approver_id) =>
+        {
+            Err("Only the claimant's manager can approve this claim")
+        }
+        // Same scope as the approval queue, the claimant must report to the approver
+        StepKind::LineManager
+            if !permissions.has(Permission::ClaimsApproveAny)
+                && !context.claimant_managers.contains(&approver_id) =>
+        {
+            Err("Only the claimant's managers can approve this claim")
+        }
+        StepKind::Manager if !permissions.has(Permission::ClaimsApprove) => {
+            Err("You must be a manager to perform this action")
+        }
+        StepKind::Finance if !permissions.has(Permission::ClaimsApproveFinance) => {
+            Err("This step must be approved by finance")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks the approver first and then the users who delegated their approval rights to them.
+/// Returns the delegator when the decision is made on their behalf.
+pub fn resolve_approver(
+    config: &Config,
+    context: &ApprovalContext,
+    approver: (i32, &Permissions),
+    delegators: &[(i32, Permissions)],
+) -> Result<Option<i32>, &'static str> {
+    let (approver_id, permissions) = approver;
+    let message = match check_approver(config, context, approver_id, permissions) {
+        Ok(()) => return Ok(None),
+        Err(message) => message,
+    };
+    // Separation of duties applies to the delegate as well
+    if context.claimant_id == approver_id || context.previous_approvers.contains(&approver_id) {
+        return Err(message);
+    }
+    delegators
+        .iter()
+        .find(|(delegator_id, delegator_permissions)| {
+            check_approver(config, context, *delegator_id, delegator_permissions).is_ok()
+        })
+        .map(|(delegator_id, _)| Some(*delegator_id))
+        .ok_or(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> Permissions {
+        Permissions::default()
+    }
+
+    fn manager() -> Permissions {
+        [Permission::ClaimsApprove].into_iter().collect()
+    }
+
+    fn finance() -> Permissions {
+        [Permission::ClaimsApprove, Permission::ClaimsApproveFinance]
+            .into_iter()
+            .collect()
+    }
+
+    fn admin() -> Permissions {
+        [
+            Permission::ClaimsApprove,
+            Permission::ClaimsApproveAny,
+            Permission::ClaimsApproveFinance,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_workflow_step_from_str() {
+        assert_eq!(
+            WorkflowStep::from_str("Finance:500").unwrap(),
+            WorkflowStep {
+                kind: StepKind::Finance,
+                threshold: Decimal::from(500)
+            }
+        );
+        WorkflowStep::from_str("Finance").unwrap_err();
+        WorkflowStep::from_str("Nobody:10").unwrap_err();
+    }
+
+    #[test]
+    fn test_required_steps() {
+        let config = Config::for_tests();
+        assert_eq!(
+            required_steps(&config, Decimal::from(500)),
+            vec![StepKind::LineManager]
+        );
+        assert_eq!(
+            required_steps(&config, Decimal::from(501)),
+            vec![StepKind::LineManager, StepKind::Finance]
+        );
+    }
+
+    #[test]
+    fn test_check_approver() {
+        let config = Config::for_tests();
+        let context = ApprovalContext {
+            claimant_id: 1,
+            claimant_managers: vec![2, 5],
+            step: StepKind::LineManager,
+            previous_approvers: vec![],
+        };
+        check_approver(&config, &context, 1, &manager()).unwrap_err();
+        check_approver(&config, &context, 3, &manager()).unwrap_err();
+        check_approver(&config, &context, 4, &user()).unwrap_err();
+        check_approver(&config, &context, 2, &manager()).unwrap();
+        check_approver(&config, &context, 3, &admin()).unwrap();
+
+        let context = ApprovalContext {
+            step: StepKind::Finance,
+            previous_approvers: vec![2],
+            ..context
+        };
+        check_approver(&config, &context, 2, &admin()).unwrap_err();
+        check_approver(&config, &context, 3, &manager()).unwrap_err();
+        check_approver(&config, &context, 3, &finance()).unwrap();
+    }
+
+    #[test]
+    fn test_check_approver_hierarchy() {
+        let config = Config {
+            require_assigned_manager: false,
+            ..Config::for_tests()
+        };
+        let context = ApprovalContext {
+            claimant_id: 1,
+            claimant_managers: vec![2, 5],
+            step: StepKind::LineManager,
+            previous_approvers: vec![],
+        };
+        check_approver(&config, &context, 2, &manager()).unwrap();
+        check_approver(&config, &context, 5, &manager()).unwrap();
+        assert_eq!(
+            check_approver(&config, &context, 3, &manager()),
+            Err("Only the claimant's managers can approve this claim")
+        );
+        check_approver(&config, &context, 3, &admin()).unwrap();
+
+        let unmanaged = ApprovalContext {
+            claimant_managers: vec![],
+            ..context
+        };
+        check_approver(&config, &unmanaged, 3, &manager()).unwrap_err();
+        check_approver(&config, &unmanaged, 3, &admin()).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_approver() {
+        let config = Config::for_tests();
+        let context = ApprovalContext {
+            claimant_id: 1,
+            claimant_managers: vec![2, 5],
+            step: StepKind::LineManager,
+            previous_approvers: vec![],
+        };
+        assert_eq!(
+            resolve_approver(&config, &context, (2, &manager()), &[]),
+            Ok(None)
+        );
+        assert_eq!(
+            resolve_approver(&config, &context, (3, &user()), &[(2, manager())]),
+            Ok(Some(2))
+        );
+        resolve_approver(&config, &context, (3, &user()), &[(4, manager())]).unwrap_err();
+        resolve_approver(&config, &context, (1, &user()), &[(2, manager())]).unwrap_err();
+    }
+
+    #[test]
+    fn test_unscoped_step_kinds() {
+        assert_eq!(unscoped_step_kinds(&user()), vec![]);
+        assert_eq!(unscoped_step_kinds(&manager()), vec![StepKind::Manager]);
+        assert_eq!(
+            unscoped_step_kinds(&admin()),
+            vec![StepKind::LineManager, StepKind::Manager, StepKind::Finance]
+        );
+        assert!(!is_approver(&user()));
+        assert!(is_approver(&manager()));
+        let finance_only: Permissions = [Permission::ClaimsApproveFinance].into_iter().collect();
+        assert!(is_approver(&finance_only));
+    }
+}
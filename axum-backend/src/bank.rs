@@ -0,0 +1,65 @@
+/// Removes the spaces of the printed format, e.g. `DE89 3704 0044 0532 0130 00`
+pub fn normalize_iban(iban: &str) -> String {
+    iban.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Checks the structure and the ISO 7064 mod 97-10 check digits of a normalized IBAN
+pub fn is_valid_iban(iban: &str) -> bool {
+    let bytes = iban.as_bytes();
+    if !(15..=34).contains(&bytes.len())
+        || !bytes[..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..4].iter().all(u8::is_ascii_digit)
+        || !bytes[4..]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return false;
+    }
+
+    // Country code and check digits move to the end, letters count as 10 to 35
+    let remainder = bytes[4..]
+        .iter()
+        .chain(&bytes[..4])
+        .fold(0u32, |remainder, c| match c {
+            b'0'..=b'9' => (remainder * 10 + u32::from(c - b'0')) % 97,
+            _ => (remainder * 100 + u32::from(c - b'A') + 10) % 97,
+        });
+    remainder == 1
+}
+
+/// Keeps the country code, check digits and last four characters, e.g. `DE89 **** 3000`
+pub fn mask_iban(iban: &str) -> String {
+    let length = iban.chars().count();
+    if length <= 8 {
+        return "*".repeat(length);
+    }
+    let start: String = iban.chars().take(4).collect();
+    let end: String = iban.chars().skip(length - 4).collect();
+    format!("{} **** {}", start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_iban() {
+        assert!(is_valid_iban("DE89370400440532013000"));
+        assert!(is_valid_iban("GB82WEST12345698765432"));
+        assert!(is_valid_iban("FR1420041010050500013M02606"));
+        assert!(is_valid_iban(&normalize_iban("nl91 abna 0417 1643 00")));
+        assert!(!is_valid_iban("DE89370400440532013001"));
+        assert!(!is_valid_iban("DE8937040044"));
+        assert!(!is_valid_iban("1E89370400440532013000"));
+        assert!(!is_valid_iban("DE89-370400440532013000"));
+    }
+
+    #[test]
+    fn test_mask_iban() {
+        assert_eq!(mask_iban("DE89370400440532013000"), "DE89 **** 3000");
+        assert_eq!(mask_iban("DE89"), "****");
+    }
+}
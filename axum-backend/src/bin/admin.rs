@@ -0,0 +1,362 @@
+use std::path::PathBuf;
+
+use axum_backend::config::Config;
+use axum_backend::forms::{validate_password_strength, OrganizationForm, SignupForm};
+use axum_backend::import;
+use axum_backend::models::Role;
+use axum_backend::roles::{self, RoleChangeError};
+use axum_backend::utils::{generate_random_string, hash_password};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+use validator::Validate;
+
+/// Maintenance commands for the ReCalc backend, using the same configuration as the server
+#[derive(Debug, Parser)]
+#[command(name = "admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Slug of the organisation to work in, the first organisation when missing
+    #[arg(long, global = true)]
+    organization: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Import rows from a CSV file, nothing is written if any row is invalid
+    Import {
+        #[command(subcommand)]
+        kind: ImportKind,
+        /// Only check the file and show what would be created
+        #[arg(long, global = true)]
+        dry_run: bool,
+    },
+    /// Create a verified admin, the first one can only be created this way
+    CreateAdmin {
+        #[arg(long)]
+        mail: String,
+        #[arg(long)]
+        username: String,
+        /// Read the password from the first line of stdin instead of generating one
+        #[arg(long)]
+        password_stdin: bool,
+        /// Make the admin a super-admin, who manages every organisation
+        #[arg(long)]
+        super_admin: bool,
+    },
+    /// Create an organisation, its first admin is created with create-admin
+    CreateOrganization { slug: String, name: String },
+    /// Set a new password, generated and printed unless read from stdin
+    ResetPassword {
+        username: String,
+        /// Read the password from the first line of stdin instead of generating one
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Mark the mail address of a user as verified
+    VerifyUser { username: String },
+    /// Change the role of a user (User, Manager, Finance, Admin or SuperAdmin)
+    SetRole { username: String, role: String },
+    /// Apply the pending database migrations
+    Migrate,
+    /// Add demo categories and users to a database without users
+    SeedDemo,
+    /// Remove unused mail verification codes
+    PurgeTokens {
+        /// Codes of unverified users older than this are removed as well
+        #[arg(long, default_value_t = 7)]
+        older_than_days: i32,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ImportKind {
+    /// Categories with name,percentage,cap columns
+    Categories { file: PathBuf },
+    /// Users with mail,username,role,manager columns
+    Users { file: PathBuf },
+}
+
+fn print_report<T: Serialize>(report: &import::ImportReport<T>) -> Result<(), anyhow::Error> {
+    println!("{}", serde_json::to_string_pretty(report)?);
+    for row in &report.errors {
+        for error in &row.errors {
+            eprintln!("line {}: {}", row.line, error);
+        }
+    }
+    if !report.errors.is_empty() {
+        eprintln!("{} invalid rows, nothing was imported", report.errors.len());
+        std::process::exit(1);
+    }
+    if !report.applied {
+        eprintln!("Dry run, nothing was imported");
+    }
+    Ok(())
+}
+
+/// Meets the password rules
+fn generated_password() -> String {
+    format!("{}-Aa1", generate_random_string(16))
+}
+
+/// The password on the first line of stdin if it meets the rules, otherwise a generated one
+/// that is printed. Passwords are not taken as arguments, which end up in the shell history.
+fn new_password(from_stdin: bool) -> Result<String, anyhow::Error> {
+    if !from_stdin {
+        let password = generated_password();
+        println!("Password: {}", password);
+        return Ok(password);
+    }
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    validate_password_strength(&password)
+        .map_err(|e| anyhow::anyhow!("Invalid password: {}", e.code))?;
+    Ok(password)
+}
+
+/// Organisation with the slug, the first one when no slug is given
+async fn organization_id(pool: &PgPool, slug: Option<&str>) -> Result<i32, anyhow::Error> {
+    sqlx::query_scalar!(
+        "SELECT id FROM organizations WHERE slug = $1 OR $1 IS NULL ORDER BY id LIMIT 1",
+        slug
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Organization {} does not exist", slug.unwrap_or_default()))
+}
+
+async fn user_id(pool: &PgPool, username: &str) -> Result<i32, anyhow::Error> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE username = $1", username)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} does not exist", username))
+}
+
+async fn create_admin(
+    pool: &PgPool,
+    organization_id: i32,
+    signup: SignupForm,
+    role: Role,
+) -> Result<(), anyhow::Error> {
+    signup.validate()?;
+    let id = sqlx::query_scalar!(
+        "INSERT INTO users ( mail, username, password_hash, role, verified, organization_id )
+        VALUES ($1, $2, $3, $4, true, $5)
+        ON CONFLICT (username) DO NOTHING RETURNING id",
+        signup.mail.to_ascii_lowercase(),
+        signup.username,
+        hash_password(&signup.password).map_err(|e| anyhow::anyhow!(e))?,
+        role.to_string(),
+        organization_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("User {} already exists", signup.username))?;
+    println!("Created {} {} with id {}", role, signup.username, id);
+    Ok(())
+}
+
+async fn set_role(pool: &PgPool, username: &str, role: &str) -> Result<(), anyhow::Error> {
+    let role = Role::from(role.to_string());
+    let id = user_id(pool, username).await?;
+    let organization_id =
+        sqlx::query_scalar!("SELECT organization_id FROM users WHERE id = $1", id)
+            .fetch_one(pool)
+            .await?;
+    let mut transaction = pool.begin().await?;
+    match roles::change_role(&mut transaction, organization_id, id, &role, None).await {
+        Ok(_) => {}
+        Err(RoleChangeError::UserNotFound) => anyhow::bail!("User {} does not exist", username),
+        Err(RoleChangeError::UnknownRole) => anyhow::bail!("Unknown role {}", role),
+        Err(RoleChangeError::NotPermitted) => unreachable!("the CLI is not limited"),
+        Err(RoleChangeError::Unchanged) => anyhow::bail!("{} already is {}", username, role),
+        Err(RoleChangeError::LastAdmin) => anyhow::bail!("{} is the last admin", username),
+        Err(RoleChangeError::Database(e)) => return Err(e.into()),
+    }
+    transaction.commit().await?;
+    println!("{} is now {}", username, role);
+    Ok(())
+}
+
+/// Demo users share a generated password, so only databases without users are seeded
+async fn seed_demo(pool: &PgPool, organization_id: i32) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    // Keeps users from being created until the demo is seeded
+    sqlx::query!("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+    // Besides the placeholder of the start data, which cannot log in
+    let has_users = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE password_hash <> 'impossible') AS "exists!""#
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if has_users {
+        anyhow::bail!("The database already has users, demo data is only added to a new one");
+    }
+
+    for (name, percentage, cap) in [
+        ("Travel", 100, 1000),
+        ("Accommodation", 100, 500),
+        ("Meals", 80, 60),
+        ("Office supplies", 100, 200),
+    ] {
+        sqlx::query!(
+            "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, organization_id )
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (organization_id, name) DO NOTHING",
+            name,
+            rust_decimal::Decimal::from(percentage),
+            rust_decimal::Decimal::from(cap),
+            organization_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    let password = generated_password();
+    let password_hash = hash_password(&password).map_err(|e| anyhow::anyhow!(e))?;
+    let mut manager_id: Option<i32> = None;
+    for (username, role) in [
+        ("demoadmin", Role::Admin),
+        ("demofinance", Role::Finance),
+        ("demomanager", Role::Manager),
+        ("demoemployee", Role::User),
+    ] {
+        let reports_to = if role == Role::User { manager_id } else { None };
+        let id = sqlx::query_scalar!(
+            "INSERT INTO users ( mail, username, password_hash, role, verified, manager_id, organization_id )
+            VALUES ($1, $2, $3, $4, true, $5, $6)
+            ON CONFLICT (username) DO NOTHING RETURNING id",
+            format!("{}@example.com", username),
+            username,
+            password_hash,
+            role.to_string(),
+            reports_to,
+            organization_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} already exists", username))?;
+        if role == Role::Manager {
+            manager_id = Some(id);
+        }
+    }
+    transaction.commit().await?;
+    println!(
+        "Demo users demoadmin, demofinance, demomanager and demoemployee have the password {}",
+        password
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let _ = dotenv::from_filename(".env.priv");
+    let cli = Cli::parse();
+    let config = Config::init()?;
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await?;
+
+    let organization = cli.organization.as_deref();
+    match cli.command {
+        Command::Import { kind, dry_run } => {
+            let organization_id = organization_id(&pool, organization).await?;
+            match kind {
+                ImportKind::Categories { file } => {
+                    let data = std::fs::read_to_string(file)?;
+                    print_report(
+                        &import::import_categories(&pool, organization_id, &data, dry_run).await?,
+                    )?;
+                }
+                ImportKind::Users { file } => {
+                    let data = std::fs::read_to_string(file)?;
+                    print_report(
+                        &import::import_users(&pool, organization_id, None, &data, dry_run).await?,
+                    )?;
+                }
+            }
+        }
+        Command::CreateAdmin {
+            mail,
+            username,
+            password_stdin,
+            super_admin,
+        } => {
+            let organization_id = organization_id(&pool, organization).await?;
+            let signup = SignupForm {
+                mail,
+                username,
+                password: new_password(password_stdin)?,
+                organization: None,
+            };
+            let role = if super_admin {
+                Role::SuperAdmin
+            } else {
+                Role::Admin
+            };
+            create_admin(&pool, organization_id, signup, role).await?
+        }
+        Command::CreateOrganization { slug, name } => {
+            let form = OrganizationForm { slug, name };
+            form.validate()?;
+            let id = sqlx::query_scalar!(
+                "INSERT INTO organizations ( slug, name ) VALUES ($1, $2)
+                ON CONFLICT (slug) DO NOTHING RETURNING id",
+                form.slug,
+                form.name
+            )
+            .fetch_optional(&pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Organization {} already exists", form.slug))?;
+            println!("Created organization {} with id {}", form.slug, id);
+        }
+        Command::ResetPassword {
+            username,
+            password_stdin,
+        } => {
+            let id = user_id(&pool, &username).await?;
+            let password = new_password(password_stdin)?;
+            sqlx::query!(
+                "UPDATE users SET password_hash = $1, session_version = session_version + 1 WHERE id = $2",
+                hash_password(&password).map_err(|e| anyhow::anyhow!(e))?,
+                id
+            )
+            .execute(&pool)
+            .await?;
+            println!("Password of {} changed", username);
+        }
+        Command::VerifyUser { username } => {
+            let id = user_id(&pool, &username).await?;
+            sqlx::query!("UPDATE users SET verified = true WHERE id = $1", id)
+                .execute(&pool)
+                .await?;
+            println!("{} is verified", username);
+        }
+        Command::SetRole { username, role } => set_role(&pool, &username, &role).await?,
+        Command::Migrate => {
+            sqlx::migrate!("./migrations").run(&pool).await?;
+            println!("Migrations applied");
+        }
+        Command::SeedDemo => seed_demo(&pool, organization_id(&pool, organization).await?).await?,
+        Command::PurgeTokens { older_than_days } => {
+            // Codes are sent when signing up, so their age is the age of the account
+            let purged = sqlx::query!(
+                "UPDATE users SET verification_code = NULL
+                WHERE verification_code IS NOT NULL
+                AND (verified OR created_at < CURRENT_TIMESTAMP - make_interval(days => $1))",
+                older_than_days
+            )
+            .execute(&pool)
+            .await?
+            .rows_affected();
+            println!("Removed {} verification codes", purged);
+        }
+    }
+    Ok(())
+}
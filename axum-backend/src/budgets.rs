@@ -0,0 +1,122 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::{Budget, BudgetPolicy};
+
+/// Budget with the reimbursements charged to it
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    #[serde(flatten)]
+    pub budget: Budget,
+    /// Reimbursements of accepted and paid claims
+    pub approved: Decimal,
+    /// Reimbursements of claims still waiting for approval
+    pub pending: Decimal,
+    pub remaining: Decimal,
+    pub exceeded: bool,
+}
+
+impl BudgetStatus {
+    pub fn new(budget: Budget, approved: Decimal, pending: Decimal) -> Self {
+        let remaining = budget.amount - approved - pending;
+        BudgetStatus {
+            budget,
+            approved,
+            pending,
+            remaining,
+            exceeded: remaining < Decimal::from(0),
+        }
+    }
+}
+
+/// Share of a claim in a budget it is charged to
+#[derive(Debug, Clone)]
+pub struct ClaimCharge {
+    pub budget: Budget,
+    /// Reimbursements of other claims that are already accepted or paid
+    pub approved: Decimal,
+    pub amount: Decimal,
+}
+
+/// Budgets the claim would push over their amount once approved, split into the ones
+/// refusing the approval and the ones only warning about it. Other pending claims are
+/// left out, they may still be rejected.
+pub fn overruns(charges: &[ClaimCharge]) -> (Vec<&Budget>, Vec<&Budget>) {
+    charges
+        .iter()
+        .filter(|charge| charge.approved + charge.amount > charge.budget.amount)
+        .map(|charge| &charge.budget)
+        .partition(|budget| budget.policy == BudgetPolicy::Block)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn budget(id: i32, policy: BudgetPolicy) -> Budget {
+        let start = NaiveDate::from_ymd_opt(2023, 10, 1).unwrap();
+        Budget {
+            id,
+            cost_center_id: 1,
+            category_id: None,
+            period_start: start,
+            period_end: NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+            amount: Decimal::from(1000),
+            policy,
+            created_by: 1,
+            created_at: start.and_hms_opt(0, 0, 0).unwrap(),
+            organization_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_budget_status() {
+        let status = BudgetStatus::new(
+            budget(1, BudgetPolicy::Warn),
+            Decimal::from(600),
+            Decimal::from(300),
+        );
+        assert_eq!(status.remaining, Decimal::from(100));
+        assert!(!status.exceeded);
+
+        let status = BudgetStatus::new(
+            budget(1, BudgetPolicy::Warn),
+            Decimal::from(600),
+            Decimal::from(500),
+        );
+        assert_eq!(status.remaining, Decimal::from(-100));
+        assert!(status.exceeded);
+    }
+
+    #[test]
+    fn test_overruns() {
+        let charge = |id, policy, approved, amount| ClaimCharge {
+            budget: budget(id, policy),
+            approved: Decimal::from(approved),
+            amount: Decimal::from(amount),
+        };
+        let charges = [
+            charge(1, BudgetPolicy::Warn, 900, 100),
+            charge(2, BudgetPolicy::Warn, 900, 101),
+            charge(3, BudgetPolicy::Block, 950, 100),
+            charge(4, BudgetPolicy::Block, 0, 50),
+        ];
+        let (blocking, warnings) = overruns(&charges);
+        assert_eq!(
+            blocking
+                .iter()
+                .map(|budget| budget.id)
+                .collect::<Vec<i32>>(),
+            vec![3]
+        );
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|budget| budget.id)
+                .collect::<Vec<i32>>(),
+            vec![2]
+        );
+    }
+}
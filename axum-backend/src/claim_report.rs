@@ -0,0 +1,506 @@
+//! PDF expense report of a claim. The layouts in `templates/` are HTML for emails, which
+//! printpdf cannot lay out, so the report is drawn here and only shares their structure of
+//! a company header above the content.
+
+use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
+use printpdf::{
+    BuiltinFont, ColorBits, ColorSpace, Image, ImageFilter, ImageTransform, ImageXObject,
+    IndirectFontRef, Line, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Point, Px,
+};
+use rust_decimal::Decimal;
+
+use crate::models::{ClaimDetails, StepKind};
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const FONT_SIZE: f32 = 9.0;
+const LINE_HEIGHT: f32 = 5.0;
+/// Characters of a description line at the body font size
+const WRAP_WIDTH: usize = 90;
+
+pub struct ClaimReport<'a> {
+    pub company: &'a str,
+    pub employee: &'a str,
+    pub currency: &'a str,
+    pub details: &'a ClaimDetails,
+    /// JPEG receipts of the items by their hash
+    pub receipts: &'a HashMap<String, Vec<u8>>,
+    pub generated_at: NaiveDateTime,
+}
+
+/// Left edge of the text columns and right edge of the amount columns of the items table
+const DATE_COLUMN: f32 = MARGIN;
+const CATEGORY_COLUMN: f32 = 38.0;
+const RECEIPT_COLUMN: f32 = 85.0;
+const NET_COLUMN: f32 = 130.0;
+const TAX_COLUMN: f32 = 150.0;
+const COST_COLUMN: f32 = 172.0;
+const REIMBURSEMENT_COLUMN: f32 = PAGE_WIDTH - MARGIN;
+
+const THUMBNAILS_PER_ROW: usize = 4;
+const THUMBNAIL_GAP: f32 = 5.0;
+/// Side of the square each receipt is scaled into
+const THUMBNAIL_SIZE: f32 =
+    (PAGE_WIDTH - 2.0 * MARGIN - (THUMBNAILS_PER_ROW - 1) as f32 * THUMBNAIL_GAP)
+        / THUMBNAILS_PER_ROW as f32;
+
+/// Width of Helvetica glyphs in thousandths of the font size. Only used to right align
+/// amounts, so everything but digits and separators gets the digit width.
+fn text_width(text: &str, font_size: f32) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c {
+            '.' | ',' | ' ' => 278,
+            '-' => 333,
+            _ => 556,
+        })
+        .sum();
+    // Points to millimeters
+    units as f32 / 1000.0 * font_size * 0.3528
+}
+
+fn amount(value: Decimal) -> String {
+    format!("{:.2}", value)
+}
+
+/// Splits the text into lines of at most `width` characters, breaking at spaces where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let rest = word.split_off(word.char_indices().nth(width).unwrap().0);
+                lines.push(word);
+                word = rest;
+            }
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        text.to_string()
+    } else {
+        let mut truncated: String = text.chars().take(width - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+/// Width, height and color space of a JPEG file
+type JpegFrame = (usize, usize, ColorSpace);
+
+/// Reads the frame header of a JPEG file, PDF viewers decode the rest themselves. CMYK
+/// files are left out since Adobe stores them inverted.
+fn jpeg_frame(data: &[u8]) -> Option<JpegFrame> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut position = 2;
+    while position + 4 <= data.len() {
+        if data[position] != 0xFF {
+            return None;
+        }
+        let marker = data[position + 1];
+        // Markers may be preceded by fill bytes
+        if marker == 0xFF {
+            position += 1;
+            continue;
+        }
+        // Start of frame, except the huffman table, extension and arithmetic coding markers
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let frame = data.get(position + 4..position + 10)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+            let width = u16::from_be_bytes([frame[3], frame[4]]) as usize;
+            let color_space = match frame[5] {
+                1 => ColorSpace::Greyscale,
+                3 => ColorSpace::Rgb,
+                _ => return None,
+            };
+            if width == 0 || height == 0 {
+                return None;
+            }
+            return Some((width, height, color_space));
+        }
+        let length = u16::from_be_bytes([data[position + 2], data[position + 3]]) as usize;
+        position += 2 + length;
+    }
+    None
+}
+
+fn step_name(kind: &StepKind) -> &'static str {
+    match kind {
+        StepKind::LineManager => "Line manager",
+        StepKind::Manager => "Manager",
+        StepKind::Finance => "Finance",
+    }
+}
+
+/// Writes from the top of the page down and starts a new page when it is full
+struct Writer {
+    document: PdfDocumentReference,
+    layer: PdfLayerReference,
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    y: f32,
+}
+
+impl Writer {
+    fn new(title: &str) -> Result<Self, printpdf::Error> {
+        let (document, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+        let regular = document.add_builtin_font(BuiltinFont::Helvetica)?;
+        let bold = document.add_builtin_font(BuiltinFont::HelveticaBold)?;
+        let layer = document.get_page(page).get_layer(layer);
+        Ok(Writer {
+            document,
+            layer,
+            regular,
+            bold,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    /// Makes room for `height` millimeters, returns whether a new page was started
+    fn reserve(&mut self, height: f32) -> bool {
+        if self.y - height >= MARGIN {
+            return false;
+        }
+        let (page, layer) = self
+            .document
+            .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+        self.layer = self.document.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT - MARGIN;
+        true
+    }
+
+    fn text(&self, text: &str, size: f32, x: f32, bold: bool) {
+        let font = if bold { &self.bold } else { &self.regular };
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    fn right_aligned(&self, text: &str, right: f32, bold: bool) {
+        self.text(text, FONT_SIZE, right - text_width(text, FONT_SIZE), bold);
+    }
+
+    fn rule(&self) {
+        let y = self.y + LINE_HEIGHT - 1.5;
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGIN), Mm(y)), false),
+                (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(y)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    fn line(&mut self, label: &str, value: &str) {
+        self.reserve(LINE_HEIGHT);
+        if !label.is_empty() {
+            self.text(label, FONT_SIZE, MARGIN, true);
+        }
+        self.text(value, FONT_SIZE, MARGIN + 30.0, false);
+        self.y -= LINE_HEIGHT;
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.y -= LINE_HEIGHT;
+        // Keep the heading on the same page as the first lines below it
+        self.reserve(LINE_HEIGHT * 3.0);
+        self.text(text, 11.0, MARGIN, true);
+        self.y -= LINE_HEIGHT * 1.5;
+    }
+
+    /// Draws the JPEG scaled to fit a thumbnail below the current line
+    fn thumbnail(&self, data: &[u8], (width, height, color_space): JpegFrame, x: f32) {
+        // Dots per inch at which the longer side fills the thumbnail
+        let dpi = width.max(height) as f32 * 25.4 / THUMBNAIL_SIZE;
+        let drawn_width = width as f32 * 25.4 / dpi;
+        let drawn_height = height as f32 * 25.4 / dpi;
+        let image = Image::from(ImageXObject {
+            width: Px(width),
+            height: Px(height),
+            color_space,
+            bits_per_component: ColorBits::Bit8,
+            interpolate: true,
+            image_data: data.to_vec(),
+            image_filter: Some(ImageFilter::DCT),
+            smask: None,
+            clipping_bbox: None,
+        });
+        image.add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(x + (THUMBNAIL_SIZE - drawn_width) / 2.0)),
+                translate_y: Some(Mm(
+                    self.y - THUMBNAIL_SIZE + (THUMBNAIL_SIZE - drawn_height) / 2.0
+                )),
+                dpi: Some(dpi),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn items_header(&mut self) {
+        self.text("Date", FONT_SIZE, DATE_COLUMN, true);
+        self.text("Category", FONT_SIZE, CATEGORY_COLUMN, true);
+        self.text("Receipt", FONT_SIZE, RECEIPT_COLUMN, true);
+        self.right_aligned("Net", NET_COLUMN, true);
+        self.right_aligned("Tax", TAX_COLUMN, true);
+        self.right_aligned("Cost", COST_COLUMN, true);
+        self.right_aligned("Reimbursed", REIMBURSEMENT_COLUMN, true);
+        self.y -= LINE_HEIGHT;
+        self.rule();
+    }
+}
+
+/// A4 report of a claim with its items, totals and approval trail, followed by
+/// lines for the signatures of the employee and the approver
+pub fn render(report: &ClaimReport) -> Result<Vec<u8>, printpdf::Error> {
+    let claim = &report.details.claim;
+    let mut writer = Writer::new(&format!("Expense report - claim {}", claim.id))?;
+
+    writer.text(report.company, 16.0, MARGIN, true);
+    writer.y -= LINE_HEIGHT * 1.5;
+    writer.text("Expense report", 12.0, MARGIN, false);
+    writer.y -= LINE_HEIGHT * 2.5;
+
+    writer.line("Claim", &claim.id.to_string());
+    writer.line("Employee", report.employee);
+    writer.line("Status", &claim.status.to_string());
+    writer.line(
+        "Submitted",
+        &claim
+            .submitted_at
+            .unwrap_or(claim.created_at)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+    );
+    if let Some(decided_at) = claim.decided_at {
+        writer.line("Decided", &decided_at.format("%Y-%m-%d %H:%M").to_string());
+    }
+    if let Some(description) = claim.description.as_deref().filter(|d| !d.is_empty()) {
+        for (index, line) in wrap(description, WRAP_WIDTH).iter().enumerate() {
+            writer.line(if index == 0 { "Description" } else { "" }, line);
+        }
+    }
+
+    writer.heading(&format!("Items ({})", report.currency));
+    writer.items_header();
+    let (mut net, mut tax, mut cost, mut reimbursement) =
+        (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+    for details in &report.details.items {
+        let item = &details.item;
+        if writer.reserve(LINE_HEIGHT) {
+            writer.items_header();
+        }
+        writer.text(
+            &item.expense_date.to_string(),
+            FONT_SIZE,
+            DATE_COLUMN,
+            false,
+        );
+        writer.text(
+            &truncate(
+                details
+                    .category
+                    .as_ref()
+                    .map_or("-", |category| category.name.as_str()),
+                26,
+            ),
+            FONT_SIZE,
+            CATEGORY_COLUMN,
+            false,
+        );
+        // Receipts are identified by their hash, the start is enough to match them
+        let receipt = item
+            .receipt_hash
+            .as_deref()
+            .map(|hash| truncate(hash, 13))
+            .unwrap_or_else(|| "-".to_string());
+        writer.text(&receipt, FONT_SIZE, RECEIPT_COLUMN, false);
+        writer.right_aligned(&amount(item.net_cost), NET_COLUMN, false);
+        writer.right_aligned(&amount(item.tax_amount), TAX_COLUMN, false);
+        writer.right_aligned(&amount(item.cost), COST_COLUMN, false);
+        writer.right_aligned(&amount(item.reimbursement), REIMBURSEMENT_COLUMN, false);
+        writer.y -= LINE_HEIGHT;
+        net += item.net_cost;
+        tax += item.tax_amount;
+        cost += item.cost;
+        reimbursement += item.reimbursement;
+    }
+    writer.reserve(LINE_HEIGHT);
+    writer.rule();
+    writer.text("Total", FONT_SIZE, DATE_COLUMN, true);
+    writer.right_aligned(&amount(net), NET_COLUMN, true);
+    writer.right_aligned(&amount(tax), TAX_COLUMN, true);
+    writer.right_aligned(&amount(cost), COST_COLUMN, true);
+    writer.right_aligned(&amount(reimbursement), REIMBURSEMENT_COLUMN, true);
+    writer.y -= LINE_HEIGHT;
+
+    let receipts: Vec<_> = report
+        .details
+        .items
+        .iter()
+        .filter_map(|details| {
+            let hash = details.item.receipt_hash.as_ref()?;
+            let data = report.receipts.get(hash)?;
+            Some((hash, details.item.expense_date, data, jpeg_frame(data)?))
+        })
+        .collect();
+    if !receipts.is_empty() {
+        writer.heading("Receipts");
+        for row in receipts.chunks(THUMBNAILS_PER_ROW) {
+            writer.reserve(THUMBNAIL_SIZE + LINE_HEIGHT * 2.0);
+            let top = writer.y;
+            for (column, (hash, expense_date, data, frame)) in row.iter().enumerate() {
+                let x = MARGIN + column as f32 * (THUMBNAIL_SIZE + THUMBNAIL_GAP);
+                writer.y = top;
+                writer.thumbnail(data, *frame, x);
+                writer.y = top - THUMBNAIL_SIZE - LINE_HEIGHT;
+                writer.text(
+                    &format!("{} {}", expense_date, truncate(hash, 13)),
+                    7.0,
+                    x,
+                    false,
+                );
+            }
+            writer.y = top - THUMBNAIL_SIZE - LINE_HEIGHT * 2.0;
+        }
+    }
+
+    writer.heading("Approval trail");
+    for step in &report.details.steps {
+        let decision = report
+            .details
+            .decisions
+            .iter()
+            .find(|decision| decision.decision.step == step.position);
+        let outcome = match decision {
+            Some(decision) => {
+                let mut outcome = format!(
+                    "{} by {}",
+                    if decision.decision.accepted {
+                        "Accepted"
+                    } else {
+                        "Rejected"
+                    },
+                    decision.approver_name
+                );
+                if let Some(delegator) = &decision.on_behalf_of_name {
+                    outcome.push_str(&format!(" on behalf of {}", delegator));
+                }
+                outcome.push_str(&format!(
+                    " on {}",
+                    decision.decision.created_at.format("%Y-%m-%d %H:%M")
+                ));
+                outcome
+            }
+            None if step.position == claim.current_step => "Waiting for approval".to_string(),
+            None => "-".to_string(),
+        };
+        writer.line(
+            &format!("{}. {}", step.position + 1, step_name(&step.kind)),
+            &outcome,
+        );
+        if let Some(reason) = decision.and_then(|decision| decision.decision.reason.as_deref()) {
+            for line in wrap(reason, WRAP_WIDTH) {
+                writer.line("", &line);
+            }
+        }
+    }
+
+    // Signature lines
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.reserve(LINE_HEIGHT * 6.0);
+    writer.y -= LINE_HEIGHT * 3.0;
+    for x in [MARGIN, PAGE_WIDTH / 2.0 + 5.0] {
+        writer.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(x), Mm(writer.y + LINE_HEIGHT)), false),
+                (Point::new(Mm(x + 80.0), Mm(writer.y + LINE_HEIGHT)), false),
+            ],
+            is_closed: false,
+        });
+    }
+    writer.text("Date, signature of the employee", FONT_SIZE, MARGIN, false);
+    writer.text(
+        "Date, signature of the approver",
+        FONT_SIZE,
+        PAGE_WIDTH / 2.0 + 5.0,
+        false,
+    );
+    writer.y -= LINE_HEIGHT * 2.0;
+    writer.text(
+        &format!(
+            "Generated on {}",
+            report.generated_at.format("%Y-%m-%d %H:%M")
+        ),
+        7.0,
+        MARGIN,
+        false,
+    );
+
+    writer.document.save_to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("a bb ccc dddd", 6), vec!["a bb", "ccc", "dddd"]);
+        assert_eq!(wrap("abcdefgh ij", 3), vec!["abc", "def", "gh", "ij"]);
+        assert_eq!(wrap("one\ntwo", 10), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("Travel", 6), "Travel");
+        assert_eq!(truncate("Accommodation", 6), "Accom…");
+    }
+
+    /// Start of a JFIF file with the frame header of a 640x480 color image
+    const JPEG: &[u8] = &[
+        0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0,
+        0xFF, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03, 0x01, 0x22, 0x00, 0x02,
+        0x11, 0x01, 0x03, 0x11, 0x01,
+    ];
+
+    #[test]
+    fn test_jpeg_frame() {
+        assert!(matches!(
+            jpeg_frame(JPEG),
+            Some((640, 480, ColorSpace::Rgb))
+        ));
+        assert!(jpeg_frame(&JPEG[..24]).is_none());
+        assert!(jpeg_frame(b"\x89PNG\r\n\x1a\n").is_none());
+        let mut cmyk = JPEG.to_vec();
+        cmyk[30] = 4;
+        assert!(jpeg_frame(&cmyk).is_none());
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert!(text_width("1,000.00", FONT_SIZE) < text_width("10000000", FONT_SIZE));
+        assert_eq!(text_width("", FONT_SIZE), 0.0);
+    }
+}
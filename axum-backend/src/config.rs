@@ -1,6 +1,12 @@
+use std::str::FromStr;
+
 use dotenv_codegen::dotenv;
+use rust_decimal::Decimal;
 use validator::Validate;
 
+use crate::approvals::WorkflowStep;
+use crate::models::{FlagKind, OrganizationSettings};
+
 #[derive(Debug, Clone, Validate)]
 pub struct Config {
     #[validate(range(min = 1, max = 65535, message = "port must be between 1 and 65535"))]
@@ -27,6 +33,62 @@ pub struct Config {
     pub smtp_port: u16,
     #[validate(length(min = 1, message = "frontend_origin cannot be empty"))]
     pub frontend_origin: String,
+    pub flag_heuristics: Vec<FlagKind>,
+    pub blocking_flags: Vec<FlagKind>,
+    #[validate(range(
+        min = 0,
+        max = 365,
+        message = "duplicate_window_days must be between 0 and 365"
+    ))]
+    pub duplicate_window_days: i64,
+    pub round_amount_unit: Decimal,
+    pub approval_workflow: Vec<WorkflowStep>,
+    pub require_assigned_manager: bool,
+    pub fallback_approver_id: Option<i32>,
+    #[validate(range(min = 1, message = "sla_check_interval_minutes must be positive"))]
+    pub sla_check_interval_minutes: u64,
+    #[validate(range(min = 1, message = "sla_reminder_hours must be positive"))]
+    pub sla_reminder_hours: i32,
+    #[validate(range(min = 1, message = "sla_escalation_hours must be positive"))]
+    pub sla_escalation_hours: i32,
+    #[validate(length(min = 1, max = 70, message = "debtor_name must be 1 to 70 characters"))]
+    pub debtor_name: String,
+    #[validate(length(min = 5, max = 34, message = "debtor_iban must be 5 to 34 characters"))]
+    pub debtor_iban: String,
+    pub debtor_bic: Option<String>,
+    #[validate(length(equal = 3, message = "payment_currency must be an ISO 4217 code"))]
+    pub payment_currency: String,
+    #[validate(length(
+        min = 1,
+        max = 20,
+        message = "accounting_expense_account must be 1 to 20 characters"
+    ))]
+    pub accounting_expense_account: String,
+    #[validate(length(
+        min = 1,
+        max = 20,
+        message = "accounting_payable_account must be 1 to 20 characters"
+    ))]
+    pub accounting_payable_account: String,
+    #[validate(length(
+        min = 1,
+        max = 20,
+        message = "accounting_bank_account must be 1 to 20 characters"
+    ))]
+    pub accounting_bank_account: String,
+    #[validate(length(
+        min = 1,
+        max = 20,
+        message = "accounting_tax_account must be 1 to 20 characters"
+    ))]
+    pub accounting_tax_account: String,
+    /// Each running export holds one database connection until it is done
+    #[validate(range(
+        min = 1,
+        max = 9,
+        message = "max_concurrent_exports must be between 1 and 9"
+    ))]
+    pub max_concurrent_exports: usize,
 }
 
 // AT COMPILE TIME (common)
@@ -35,6 +97,49 @@ const JWT_EXPIRES_IN: &str = dotenv!("JWT_EXPIRES_IN");
 const JWT_MAXAGE: &str = dotenv!("JWT_MAXAGE");
 const SMTP_HOST: &str = dotenv!("SMTP_HOST");
 const SMTP_PORT: &str = dotenv!("SMTP_PORT");
+const FLAG_HEURISTICS: &str = dotenv!("FLAG_HEURISTICS");
+const BLOCKING_FLAGS: &str = dotenv!("BLOCKING_FLAGS");
+const DUPLICATE_WINDOW_DAYS: &str = dotenv!("DUPLICATE_WINDOW_DAYS");
+const ROUND_AMOUNT_UNIT: &str = dotenv!("ROUND_AMOUNT_UNIT");
+const APPROVAL_WORKFLOW: &str = dotenv!("APPROVAL_WORKFLOW");
+const REQUIRE_ASSIGNED_MANAGER: &str = dotenv!("REQUIRE_ASSIGNED_MANAGER");
+const FALLBACK_APPROVER_ID: &str = dotenv!("FALLBACK_APPROVER_ID");
+const SLA_CHECK_INTERVAL_MINUTES: &str = dotenv!("SLA_CHECK_INTERVAL_MINUTES");
+const SLA_REMINDER_HOURS: &str = dotenv!("SLA_REMINDER_HOURS");
+const SLA_ESCALATION_HOURS: &str = dotenv!("SLA_ESCALATION_HOURS");
+const DEBTOR_NAME: &str = dotenv!("DEBTOR_NAME");
+const DEBTOR_IBAN: &str = dotenv!("DEBTOR_IBAN");
+const DEBTOR_BIC: &str = dotenv!("DEBTOR_BIC");
+const PAYMENT_CURRENCY: &str = dotenv!("PAYMENT_CURRENCY");
+const ACCOUNTING_EXPENSE_ACCOUNT: &str = dotenv!("ACCOUNTING_EXPENSE_ACCOUNT");
+const ACCOUNTING_PAYABLE_ACCOUNT: &str = dotenv!("ACCOUNTING_PAYABLE_ACCOUNT");
+const ACCOUNTING_BANK_ACCOUNT: &str = dotenv!("ACCOUNTING_BANK_ACCOUNT");
+const ACCOUNTING_TAX_ACCOUNT: &str = dotenv!("ACCOUNTING_TAX_ACCOUNT");
+const MAX_CONCURRENT_EXPORTS: &str = dotenv!("MAX_CONCURRENT_EXPORTS");
+
+fn parse_optional<T: FromStr>(value: &str) -> Option<T> {
+    match value.trim() {
+        "" => None,
+        value => Some(
+            value
+                .parse::<T>()
+                .unwrap_or_else(|_| panic!("Invalid value: {}", value)),
+        ),
+    }
+}
+
+fn parse_list<T: FromStr>(value: &str) -> Vec<T> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<T>()
+                .unwrap_or_else(|_| panic!("Invalid list entry: {}", entry))
+        })
+        .collect()
+}
 
 impl Config {
     pub fn init() -> Result<Config, validator::ValidationErrors> {
@@ -57,8 +162,177 @@ impl Config {
             smtp_host: SMTP_HOST.to_owned(),
             smtp_port: SMTP_PORT.parse::<u16>().expect("Invalid SMTP_PORT"),
             frontend_origin,
+            flag_heuristics: parse_list(FLAG_HEURISTICS),
+            blocking_flags: parse_list(BLOCKING_FLAGS),
+            duplicate_window_days: DUPLICATE_WINDOW_DAYS
+                .parse::<i64>()
+                .expect("Invalid DUPLICATE_WINDOW_DAYS"),
+            round_amount_unit: ROUND_AMOUNT_UNIT
+                .parse::<Decimal>()
+                .expect("Invalid ROUND_AMOUNT_UNIT"),
+            approval_workflow: check_workflow(APPROVAL_WORKFLOW)
+                .expect("Invalid APPROVAL_WORKFLOW"),
+            require_assigned_manager: REQUIRE_ASSIGNED_MANAGER
+                .parse::<bool>()
+                .expect("Invalid REQUIRE_ASSIGNED_MANAGER"),
+            fallback_approver_id: parse_optional(FALLBACK_APPROVER_ID),
+            sla_check_interval_minutes: SLA_CHECK_INTERVAL_MINUTES
+                .parse::<u64>()
+                .expect("Invalid SLA_CHECK_INTERVAL_MINUTES"),
+            sla_reminder_hours: SLA_REMINDER_HOURS
+                .parse::<i32>()
+                .expect("Invalid SLA_REMINDER_HOURS"),
+            sla_escalation_hours: SLA_ESCALATION_HOURS
+                .parse::<i32>()
+                .expect("Invalid SLA_ESCALATION_HOURS"),
+            debtor_name: DEBTOR_NAME.to_owned(),
+            debtor_iban: DEBTOR_IBAN.to_owned(),
+            debtor_bic: parse_optional(DEBTOR_BIC),
+            payment_currency: PAYMENT_CURRENCY.to_owned(),
+            accounting_expense_account: ACCOUNTING_EXPENSE_ACCOUNT.to_owned(),
+            accounting_payable_account: ACCOUNTING_PAYABLE_ACCOUNT.to_owned(),
+            accounting_bank_account: ACCOUNTING_BANK_ACCOUNT.to_owned(),
+            accounting_tax_account: ACCOUNTING_TAX_ACCOUNT.to_owned(),
+            max_concurrent_exports: MAX_CONCURRENT_EXPORTS
+                .parse::<usize>()
+                .expect("Invalid MAX_CONCURRENT_EXPORTS"),
         };
         config.validate()?;
-        return Ok(config);
+        Ok(config)
+    }
+
+    /// Configuration as seen by one organisation
+    pub fn for_organization(&self, settings: &OrganizationSettings) -> Config {
+        let mut config = self.clone();
+        let strings = [
+            (&settings.debtor_name, &mut config.debtor_name),
+            (&settings.debtor_iban, &mut config.debtor_iban),
+            (&settings.payment_currency, &mut config.payment_currency),
+            (
+                &settings.accounting_expense_account,
+                &mut config.accounting_expense_account,
+            ),
+            (
+                &settings.accounting_payable_account,
+                &mut config.accounting_payable_account,
+            ),
+            (
+                &settings.accounting_bank_account,
+                &mut config.accounting_bank_account,
+            ),
+            (
+                &settings.accounting_tax_account,
+                &mut config.accounting_tax_account,
+            ),
+        ];
+        for (value, field) in strings {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        }
+        if settings.debtor_bic.is_some() {
+            config.debtor_bic = settings.debtor_bic.clone();
+        }
+        // Settings are checked when they are saved, see `check_workflow`
+        if let Some(Ok(workflow)) = settings.approval_workflow.as_deref().map(check_workflow) {
+            config.approval_workflow = workflow;
+        }
+        if let Some(require_assigned_manager) = settings.require_assigned_manager {
+            config.require_assigned_manager = require_assigned_manager;
+        }
+        if settings.fallback_approver_id.is_some() {
+            config.fallback_approver_id = settings.fallback_approver_id;
+        }
+        config
+    }
+}
+
+/// Parses a workflow in the `APPROVAL_WORKFLOW` format, it needs at least one step
+pub fn check_workflow(value: &str) -> Result<Vec<WorkflowStep>, anyhow::Error> {
+    let steps = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(WorkflowStep::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    if steps.is_empty() {
+        anyhow::bail!("The approval workflow needs at least one step");
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+impl Config {
+    pub fn for_tests() -> Config {
+        Config {
+            port: 8080,
+            database_url: "postgres://localhost".to_string(),
+            jwt_secret: "secret".to_string(),
+            jwt_expires_in: "60m".to_string(),
+            jwt_maxage: 60,
+            smtp_username: "user".to_string(),
+            smtp_password: "password".to_string(),
+            smtp_host: "localhost".to_string(),
+            smtp_port: 587,
+            frontend_origin: "http://localhost".to_string(),
+            flag_heuristics: parse_list(FLAG_HEURISTICS),
+            blocking_flags: vec![],
+            duplicate_window_days: 3,
+            round_amount_unit: Decimal::from(50),
+            approval_workflow: parse_list("LineManager:0,Finance:500"),
+            require_assigned_manager: true,
+            fallback_approver_id: None,
+            sla_check_interval_minutes: 15,
+            sla_reminder_hours: 48,
+            sla_escalation_hours: 120,
+            debtor_name: "ReCalc".to_string(),
+            debtor_iban: "DE89370400440532013000".to_string(),
+            debtor_bic: Some("COBADEFFXXX".to_string()),
+            payment_currency: "EUR".to_string(),
+            accounting_expense_account: "6000".to_string(),
+            accounting_payable_account: "2100".to_string(),
+            accounting_bank_account: "1000".to_string(),
+            accounting_tax_account: "1400".to_string(),
+            max_concurrent_exports: 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::StepKind;
+
+    #[test]
+    fn test_for_organization() {
+        let config = Config::for_tests();
+        assert_eq!(
+            config
+                .for_organization(&OrganizationSettings::default())
+                .debtor_name,
+            "ReCalc"
+        );
+
+        let settings = OrganizationSettings {
+            debtor_name: Some("Sister GmbH".to_string()),
+            approval_workflow: Some("Finance:0".to_string()),
+            fallback_approver_id: Some(7),
+            ..Default::default()
+        };
+        let organization = config.for_organization(&settings);
+        assert_eq!(organization.debtor_name, "Sister GmbH");
+        assert_eq!(organization.debtor_iban, config.debtor_iban);
+        assert_eq!(organization.approval_workflow[0].kind, StepKind::Finance);
+        assert_eq!(organization.fallback_approver_id, Some(7));
+    }
+
+    #[test]
+    fn test_check_workflow() {
+        assert_eq!(
+            check_workflow("LineManager:0, Finance:500").unwrap().len(),
+            2
+        );
+        check_workflow("").unwrap_err();
+        check_workflow("Finance").unwrap_err();
     }
 }
@@ -47,10 +47,8 @@ impl Email {
         subject: &str,
     ) -> Result<String, handlebars::RenderError> {
         let mut handlebars = Handlebars::new();
-        handlebars.register_template_file(
-            template_name,
-            &format!("./templates/{}.html", template_name),
-        )?;
+        handlebars
+            .register_template_file(template_name, format!("./templates/{}.html", template_name))?;
         handlebars.register_template_file("styles", "./templates/partials/styles.html")?;
         handlebars.register_template_file("base", "./templates/layouts/base.html")?;
 
@@ -94,4 +92,25 @@ impl Email {
         self.send_email("verification_code", "Your account verification code")
             .await
     }
+
+    pub async fn send_claim_reminder(
+        &self,
+        claim_id: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let subject = format!("Claim #{} is waiting for your approval", claim_id);
+        self.send_email("claim_reminder", &subject).await
+    }
+
+    pub async fn send_claim_escalation(
+        &self,
+        claim_id: i32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let subject = format!("Claim #{} is overdue and needs your attention", claim_id);
+        self.send_email("claim_reminder", &subject).await
+    }
+
+    pub async fn send_bank_details_changed(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_email("bank_details_changed", "Your bank details were changed")
+            .await
+    }
 }
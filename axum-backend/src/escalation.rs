@@ -0,0 +1,273 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::types::Json;
+use sqlx::PgConnection;
+
+use crate::email::Email;
+use crate::models::{ClaimStatus, OrganizationSettings, ReminderLevel, StepKind, User};
+use crate::permissions::Permission;
+use crate::AppState;
+
+#[derive(Debug, Clone)]
+struct StaleClaim {
+    id: i32,
+    user_id: i32,
+    current_step: i32,
+    kind: StepKind,
+    manager_id: Option<i32>,
+    organization_id: i32,
+    /// Fallback approver of the claim's organisation
+    fallback_approver_id: Option<i32>,
+}
+
+/// Periodically reminds approvers about claims pending longer than the SLA
+pub async fn run(app_state: Arc<AppState>) {
+    let period = Duration::from_secs(app_state.config.sla_check_interval_minutes * 60);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        for (level, hours) in [
+            (ReminderLevel::Reminder, app_state.config.sla_reminder_hours),
+            (
+                ReminderLevel::Escalation,
+                app_state.config.sla_escalation_hours,
+            ),
+        ] {
+            if let Err(e) = send_notices(&app_state, level, hours).await {
+                println!("SLA check failed: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn send_notices(
+    app_state: &AppState,
+    level: ReminderLevel,
+    hours: i32,
+) -> Result<(), sqlx::Error> {
+    let claims = sqlx::query!(
+        r#"SELECT claims.id, claims.user_id, claims.current_step, claim_steps.kind AS "kind: StepKind", users.manager_id,
+        claims.organization_id, organizations.settings AS "settings: Json<OrganizationSettings>"
+        FROM claims
+        JOIN claim_steps ON claim_steps.claim_id = claims.id AND claim_steps.position = claims.current_step
+        JOIN users ON users.id = claims.user_id
+        JOIN organizations ON organizations.id = claims.organization_id
+        WHERE claims.status = $1 AND claims.step_started_at < LOCALTIMESTAMP - make_interval(hours => $2)
+        ORDER BY claims.id ASC"#,
+        ClaimStatus::Pending.to_string(),
+        hours
+    )
+    .fetch_all(&app_state.pool)
+    .await?
+    .into_iter()
+    .map(|row| StaleClaim {
+        id: row.id,
+        user_id: row.user_id,
+        current_step: row.current_step,
+        kind: row.kind,
+        manager_id: row.manager_id,
+        organization_id: row.organization_id,
+        fallback_approver_id: app_state
+            .config
+            .for_organization(&row.settings)
+            .fallback_approver_id,
+    });
+
+    for claim in claims {
+        // The connection goes back to the pool before any mail is sent
+        let recipients = {
+            let mut conn = app_state.pool.acquire().await?;
+            let approvers = responsible_approvers(&mut conn, &claim).await?;
+            match level {
+                ReminderLevel::Reminder => approvers,
+                ReminderLevel::Escalation => {
+                    escalation_targets(&mut conn, claim.organization_id, &approvers).await?
+                }
+            }
+        };
+
+        for recipient in recipients
+            .into_iter()
+            .filter(|recipient| recipient.id != claim.user_id)
+        {
+            notify(app_state, &claim, level, recipient).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Records the notice and sends it, unless it was already sent for this step. The record
+/// is committed first so that no connection is held while talking to the mail server.
+async fn notify(
+    app_state: &AppState,
+    claim: &StaleClaim,
+    level: ReminderLevel,
+    recipient: User,
+) -> Result<(), sqlx::Error> {
+    let reminder_id = sqlx::query_scalar!(
+        "INSERT INTO claim_reminders ( claim_id, step, level, recipient_id ) VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING RETURNING id",
+        claim.id,
+        claim.current_step,
+        level.to_string(),
+        recipient.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await?;
+    let Some(reminder_id) = reminder_id else {
+        return Ok(());
+    };
+
+    let url = format!("{}/requests", app_state.config.frontend_origin);
+    let email = Email::new(recipient, url, app_state.config.clone());
+    let sent = match level {
+        ReminderLevel::Reminder => email.send_claim_reminder(claim.id).await,
+        ReminderLevel::Escalation => email.send_claim_escalation(claim.id).await,
+    }
+    .map_err(|e| e.to_string());
+    if let Err(e) = sent {
+        println!("Could not send {} for claim {}: {:?}", level, claim.id, e);
+        // Forgetting the notice lets the next run retry it
+        sqlx::query!("DELETE FROM claim_reminders WHERE id = $1", reminder_id)
+            .execute(&app_state.pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Who is expected to act on the current step of the claim
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Responsible {
+    User(i32),
+    /// Everyone of the claim's organisation with the permission
+    Permission(Permission),
+}
+
+/// Line manager steps belong to the claimant's manager, or the fallback approver for
+/// claimants without one, and to whoever approves any claim when neither is set
+fn responsible_for(claim: &StaleClaim) -> Responsible {
+    match claim.kind {
+        StepKind::LineManager => match claim.manager_id.or(claim.fallback_approver_id) {
+            Some(manager_id) => Responsible::User(manager_id),
+            None => Responsible::Permission(Permission::ClaimsApproveAny),
+        },
+        StepKind::Manager => Responsible::Permission(Permission::ClaimsApprove),
+        StepKind::Finance => Responsible::Permission(Permission::ClaimsApproveFinance),
+    }
+}
+
+/// Managers to escalate to, and whether the user managers of the organisation are
+/// needed for approvers without a manager
+fn escalation_scope(manager_ids: impl IntoIterator<Item = Option<i32>>) -> (Vec<i32>, bool) {
+    let mut include_admins = false;
+    let mut ids = vec![];
+    for manager_id in manager_ids {
+        match manager_id {
+            Some(id) if !ids.contains(&id) => ids.push(id),
+            Some(_) => {}
+            None => include_admins = true,
+        }
+    }
+    (ids, include_admins)
+}
+
+/// Users expected to act on the current step of the claim, everyone of the claim's
+/// organisation allowed to approve the step unless it belongs to a line manager
+async fn responsible_approvers(
+    conn: &mut PgConnection,
+    claim: &StaleClaim,
+) -> Result<Vec<User>, sqlx::Error> {
+    let permission = match responsible_for(claim) {
+        Responsible::User(user_id) => {
+            return sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
+                .fetch_all(&mut *conn)
+                .await
+        }
+        Responsible::Permission(permission) => permission,
+    };
+    sqlx::query_as!(
+        User,
+        "SELECT users.* FROM users JOIN role_permissions ON role_permissions.role = users.role
+        AND (role_permissions.organization_id IS NULL OR role_permissions.organization_id = users.organization_id)
+        WHERE role_permissions.permission = $1 AND users.verified AND users.organization_id = $2",
+        permission.name(),
+        claim.organization_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// Managers of the approvers, user managers of the organisation for approvers without one
+async fn escalation_targets(
+    conn: &mut PgConnection,
+    organization_id: i32,
+    approvers: &[User],
+) -> Result<Vec<User>, sqlx::Error> {
+    let (manager_ids, include_admins) =
+        escalation_scope(approvers.iter().map(|approver| approver.manager_id));
+    sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE id = ANY($1) OR ($2 AND verified AND organization_id = $4 AND role IN (
+            SELECT role FROM role_permissions
+            WHERE permission = $3 AND (organization_id IS NULL OR organization_id = $4)
+        )) ORDER BY id ASC",
+        &manager_ids,
+        include_admins,
+        Permission::UsersManage.name(),
+        organization_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(kind: StepKind, manager_id: Option<i32>, fallback: Option<i32>) -> StaleClaim {
+        StaleClaim {
+            id: 1,
+            user_id: 2,
+            current_step: 0,
+            kind,
+            manager_id,
+            organization_id: 1,
+            fallback_approver_id: fallback,
+        }
+    }
+
+    #[test]
+    fn test_responsible_for() {
+        assert_eq!(
+            responsible_for(&claim(StepKind::LineManager, Some(3), Some(4))),
+            Responsible::User(3)
+        );
+        assert_eq!(
+            responsible_for(&claim(StepKind::LineManager, None, Some(4))),
+            Responsible::User(4)
+        );
+        assert_eq!(
+            responsible_for(&claim(StepKind::LineManager, None, None)),
+            Responsible::Permission(Permission::ClaimsApproveAny)
+        );
+        assert_eq!(
+            responsible_for(&claim(StepKind::Manager, Some(3), None)),
+            Responsible::Permission(Permission::ClaimsApprove)
+        );
+        assert_eq!(
+            responsible_for(&claim(StepKind::Finance, Some(3), None)),
+            Responsible::Permission(Permission::ClaimsApproveFinance)
+        );
+    }
+
+    #[test]
+    fn test_escalation_scope() {
+        assert_eq!(escalation_scope([]), (vec![], false));
+        assert_eq!(
+            escalation_scope([Some(5), Some(6), Some(5)]),
+            (vec![5, 6], false)
+        );
+        assert_eq!(escalation_scope([Some(5), None]), (vec![5], true));
+    }
+}
@@ -0,0 +1,214 @@
+use std::io;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::xlsx::{Cell, SheetWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// One item of an exported claim
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ExportRow {
+    pub claim_id: i32,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub description: Option<String>,
+    pub user_id: i32,
+    pub username: String,
+    pub item_id: i32,
+    pub expense_date: NaiveDate,
+    pub category: String,
+    pub cost: Decimal,
+    pub reimbursement: Decimal,
+    pub net_cost: Decimal,
+    pub tax_amount: Decimal,
+    pub cost_center: Option<String>,
+    pub project: Option<String>,
+}
+
+pub const HEADER: [&str; 15] = [
+    "claim_id",
+    "status",
+    "created_at",
+    "description",
+    "user_id",
+    "username",
+    "item_id",
+    "expense_date",
+    "category",
+    "cost",
+    "reimbursement",
+    "net_cost",
+    "tax_amount",
+    "cost_center",
+    "project",
+];
+
+impl ExportRow {
+    fn cells(&self) -> [Cell; 15] {
+        let text = |value: &Option<String>| match value {
+            Some(value) => Cell::Text(value.clone()),
+            None => Cell::Empty,
+        };
+        [
+            Cell::Integer(self.claim_id.into()),
+            Cell::Text(self.status.clone()),
+            Cell::Text(self.created_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            text(&self.description),
+            Cell::Integer(self.user_id.into()),
+            Cell::Text(self.username.clone()),
+            Cell::Integer(self.item_id.into()),
+            Cell::Text(self.expense_date.to_string()),
+            Cell::Text(self.category.clone()),
+            Cell::Number(self.cost),
+            Cell::Number(self.reimbursement),
+            Cell::Number(self.net_cost),
+            Cell::Number(self.tax_amount),
+            text(&self.cost_center),
+            text(&self.project),
+        ]
+    }
+}
+
+fn csv_record<I, T>(record: I) -> io::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(record)?;
+    writer
+        .into_inner()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// Turns rows into chunks of the exported file as they are read
+pub enum ExportWriter {
+    Csv,
+    Xlsx(SheetWriter),
+}
+
+impl ExportWriter {
+    /// The writer and the start of the file including the header row
+    pub fn start(format: ExportFormat) -> io::Result<(Self, Vec<u8>)> {
+        match format {
+            ExportFormat::Csv => Ok((ExportWriter::Csv, csv_record(HEADER)?)),
+            ExportFormat::Xlsx => {
+                let (mut sheet, mut bytes) = SheetWriter::start("Claims")?;
+                let header: Vec<Cell> = HEADER
+                    .iter()
+                    .map(|name| Cell::Text(name.to_string()))
+                    .collect();
+                bytes.extend(sheet.row(&header)?);
+                Ok((ExportWriter::Xlsx(sheet), bytes))
+            }
+        }
+    }
+
+    pub fn row(&mut self, row: &ExportRow) -> io::Result<Vec<u8>> {
+        match self {
+            ExportWriter::Csv => csv_record(row.cells().iter().map(|cell| match cell {
+                Cell::Text(value) => value.clone(),
+                Cell::Number(value) => value.to_string(),
+                Cell::Integer(value) => value.to_string(),
+                Cell::Empty => String::new(),
+            })),
+            ExportWriter::Xlsx(sheet) => sheet.row(&row.cells()),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            ExportWriter::Csv => Ok(vec![]),
+            ExportWriter::Xlsx(sheet) => sheet.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn row() -> ExportRow {
+        ExportRow {
+            claim_id: 7,
+            status: "Accepted".to_string(),
+            created_at: NaiveDate::from_ymd_opt(2023, 10, 2)
+                .unwrap()
+                .and_hms_opt(9, 30, 0)
+                .unwrap(),
+            description: Some("Trip to Berlin, \"client\" visit".to_string()),
+            user_id: 3,
+            username: "alice".to_string(),
+            item_id: 12,
+            expense_date: NaiveDate::from_ymd_opt(2023, 9, 28).unwrap(),
+            category: "Travel".to_string(),
+            cost: Decimal::new(11900, 2),
+            reimbursement: Decimal::new(10000, 2),
+            net_cost: Decimal::new(10000, 2),
+            tax_amount: Decimal::new(1900, 2),
+            cost_center: Some("CC-100".to_string()),
+            project: None,
+        }
+    }
+
+    fn export(format: ExportFormat) -> Vec<u8> {
+        let (mut writer, mut bytes) = ExportWriter::start(format).unwrap();
+        bytes.extend(writer.row(&row()).unwrap());
+        bytes.extend(writer.finish().unwrap());
+        bytes
+    }
+
+    #[test]
+    fn test_csv_export() {
+        assert_eq!(
+            String::from_utf8(export(ExportFormat::Csv)).unwrap(),
+            "claim_id,status,created_at,description,user_id,username,item_id,expense_date,category,cost,reimbursement,net_cost,tax_amount,cost_center,project\n\
+            7,Accepted,2023-10-02 09:30:00,\"Trip to Berlin, \"\"client\"\" visit\",3,alice,12,2023-09-28,Travel,119.00,100.00,100.00,19.00,CC-100,\n"
+        );
+    }
+
+    #[test]
+    fn test_xlsx_export() {
+        let bytes = export(ExportFormat::Xlsx);
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        assert!(sheet
+            .contains(r#"<row r="1"><c t="inlineStr"><is><t xml:space="preserve">claim_id</t>"#));
+        assert!(sheet.contains(r#"<row r="2"><c><v>7</v></c>"#));
+        assert!(sheet.contains("&quot;client&quot;"));
+        assert!(sheet.contains("<c><v>119.00</v></c>"));
+    }
+}
@@ -0,0 +1,117 @@
+use chrono::{Duration, NaiveDate};
+use rust_decimal::Decimal;
+use sqlx::PgConnection;
+
+use crate::config::Config;
+use crate::forms::ItemForm;
+use crate::models::{ClaimStatus, FlagKind};
+
+/// Flag raised for a single item before it is stored
+#[derive(Debug, Clone)]
+pub struct DetectedFlag {
+    pub kind: FlagKind,
+    pub reason: String,
+}
+
+#[inline]
+pub fn is_round_amount(cost: Decimal, unit: Decimal) -> bool {
+    unit > Decimal::from(0) && (cost % unit).is_zero()
+}
+
+/// Runs the enabled heuristics against the items already stored in the database.
+/// Rejected claims are ignored, so an expense can be submitted again after a rejection.
+/// Receipts are only compared within the organisation of the claimant.
+pub async fn detect_item_flags(
+    conn: &mut PgConnection,
+    config: &Config,
+    organization_id: i32,
+    user_id: i32,
+    item: &ItemForm,
+    expense_date: NaiveDate,
+) -> Result<Vec<DetectedFlag>, sqlx::Error> {
+    let mut flags = Vec::new();
+
+    if config.flag_heuristics.contains(&FlagKind::DuplicateExpense) {
+        let window = Duration::days(config.duplicate_window_days);
+        let duplicate = sqlx::query!(
+            "SELECT items.id, items.claim_id FROM items
+            JOIN claims ON claims.id = items.claim_id
+            WHERE claims.user_id = $1 AND claims.status <> $2
+            AND items.category_id = $3 AND items.cost = $4
+            AND items.expense_date BETWEEN $5 AND $6
+            LIMIT 1",
+            user_id,
+            ClaimStatus::Rejected.to_string(),
+            item.category_id,
+            item.cost,
+            expense_date - window,
+            expense_date + window
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if let Some(duplicate) = duplicate {
+            flags.push(DetectedFlag {
+                kind: FlagKind::DuplicateExpense,
+                reason: format!(
+                    "Same amount, category and date as item {} in claim {}",
+                    duplicate.id, duplicate.claim_id
+                ),
+            });
+        }
+    }
+
+    if config.flag_heuristics.contains(&FlagKind::ReusedReceipt) {
+        if let Some(receipt_hash) = &item.receipt_hash {
+            let reused = sqlx::query!(
+                "SELECT items.id, items.claim_id FROM items
+                JOIN claims ON claims.id = items.claim_id
+                WHERE items.receipt_hash = $1 AND claims.status <> $2 AND claims.organization_id = $3
+                LIMIT 1",
+                receipt_hash,
+                ClaimStatus::Rejected.to_string(),
+                organization_id
+            )
+            .fetch_optional(&mut *conn)
+            .await?;
+
+            if let Some(reused) = reused {
+                flags.push(DetectedFlag {
+                    kind: FlagKind::ReusedReceipt,
+                    reason: format!(
+                        "Receipt already used by item {} in claim {}",
+                        reused.id, reused.claim_id
+                    ),
+                });
+            }
+        }
+    }
+
+    if config.flag_heuristics.contains(&FlagKind::RoundAmount)
+        && is_round_amount(item.cost, config.round_amount_unit)
+    {
+        flags.push(DetectedFlag {
+            kind: FlagKind::RoundAmount,
+            reason: format!(
+                "Amount {} is a multiple of {}",
+                item.cost, config.round_amount_unit
+            ),
+        });
+    }
+
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_amount() {
+        assert!(is_round_amount(Decimal::from(100), Decimal::from(50)));
+        assert!(is_round_amount(Decimal::new(15000, 2), Decimal::from(50)));
+        assert!(!is_round_amount(Decimal::new(9999, 2), Decimal::from(50)));
+        assert!(!is_round_amount(Decimal::from(120), Decimal::from(50)));
+        assert!(!is_round_amount(Decimal::from(100), Decimal::from(0)));
+    }
+}
@@ -1,9 +1,14 @@
+use chrono::NaiveDate;
 use lazy_static::lazy_static;
 use regex::Regex;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
+use crate::bank;
+use crate::models::{BudgetPolicy, ChargeCodeKind, ReimbursementBase, Role};
+use crate::permissions::Permission;
+
 lazy_static! {
     // HACK: This is just for now
     static ref USERNAME_REGEX: Regex = Regex::new(r"^[a-zA-Z0-9]+$").unwrap();
@@ -12,9 +17,15 @@ lazy_static! {
     static ref UPPERCASE_REGEX: Regex = Regex::new(r"[A-Z]").unwrap();
     static ref NUMBER_REGEX: Regex = Regex::new(r"[0-9]").unwrap();
     static ref SPECIAL_CHARACTERS_REGEX: Regex = Regex::new(r"[\!\@\#\$\%\^\&\*\(\)\{\}\[\]\_\-\+\=]").unwrap();
+
+    static ref RECEIPT_HASH_REGEX: Regex = Regex::new(r"^[0-9a-f]{64}$").unwrap();
+    static ref ROLE_NAME_REGEX: Regex = Regex::new(r"^[a-zA-Z][a-zA-Z0-9 ]*$").unwrap();
+    static ref SLUG_REGEX: Regex = Regex::new(r"^[a-z0-9][a-z0-9-]*$").unwrap();
+    static ref CHARGE_CODE_REGEX: Regex = Regex::new(r"^[A-Za-z0-9_.-]+$").unwrap();
+    static ref BIC_REGEX: Regex = Regex::new(r"^[A-Z]{6}[A-Z2-9][A-NP-Z0-9]([A-Z0-9]{3})?$").unwrap();
 }
 
-fn validate_password_strength(password: &str) -> Result<(), ValidationError> {
+pub fn validate_password_strength(password: &str) -> Result<(), ValidationError> {
     if password.len() < 8 {
         return Err(ValidationError::new("Password too short"));
     }
@@ -52,6 +63,10 @@ pub struct SignupForm {
     pub username: String,
     #[validate(custom = "validate_password_strength")]
     pub password: String,
+    /// Slug of the organisation to join, its settings have to allow open registration.
+    /// Imports and the admin tool create users in a given organisation and leave it empty.
+    #[serde(default)]
+    pub organization: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -75,12 +90,41 @@ fn validate_cost(cost: &Decimal) -> Result<(), ValidationError> {
     }
 }
 
+fn validate_tax_amount(tax: &Decimal) -> Result<(), ValidationError> {
+    if *tax < Decimal::from(0) {
+        Err(ValidationError::new("Tax cannot be negative"))
+    } else {
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct ItemForm {
     #[serde(rename = "categoryId")]
     pub category_id: i32,
     #[validate(custom = "validate_cost")]
     pub cost: Decimal,
+    pub date: Option<NaiveDate>,
+    /// Hex encoded SHA-256 of the receipt file
+    #[validate(regex = "RECEIPT_HASH_REGEX")]
+    #[serde(rename = "receiptHash")]
+    pub receipt_hash: Option<String>,
+    /// Tax rate in percent, the category default applies when no tax is given
+    #[validate(custom = "validate_percentage")]
+    #[serde(rename = "taxRate")]
+    pub tax_rate: Option<Decimal>,
+    #[validate(custom = "validate_tax_amount")]
+    #[serde(rename = "taxAmount")]
+    pub tax_amount: Option<Decimal>,
+    #[validate(custom = "validate_cost")]
+    #[serde(rename = "netAmount")]
+    pub net_amount: Option<Decimal>,
+    /// Overrides the cost center of the claim
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: Option<i32>,
+    /// Overrides the project of the claim
+    #[serde(rename = "projectId")]
+    pub project_id: Option<i32>,
 }
 
 fn validate_items(items: &[ItemForm]) -> Result<(), ValidationError> {
@@ -96,6 +140,21 @@ pub struct ClaimForm {
     pub user_id: i32,
     #[validate(custom = "validate_items")]
     pub items: Vec<ItemForm>,
+    /// Defaults to the cost center of the user
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: Option<i32>,
+    #[serde(rename = "projectId")]
+    pub project_id: Option<i32>,
+    #[validate(length(max = 500, message = "Description is too long"))]
+    pub description: Option<String>,
+}
+
+fn validate_decisions(decisions: &[DecisionForm]) -> Result<(), ValidationError> {
+    decisions.iter().try_for_each(|decision| {
+        decision
+            .validate()
+            .map_err(|_| ValidationError::new("Could not validate provided decisions"))
+    })
 }
 
 fn validate_percentage(value: &Decimal) -> Result<(), ValidationError> {
@@ -126,6 +185,223 @@ pub struct CategoryForm {
     #[validate(custom = "validate_max_reimbursement")]
     #[serde(rename = "maxReimburstment")]
     pub max_reimbursement: Decimal,
+    #[validate(length(min = 1, max = 20, message = "GL account must have 1 to 20 characters"))]
+    #[serde(rename = "glAccount")]
+    pub gl_account: Option<String>,
+    #[validate(custom = "validate_percentage")]
+    #[serde(rename = "defaultTaxRate")]
+    pub default_tax_rate: Option<Decimal>,
+    #[serde(rename = "reimbursementBase")]
+    pub reimbursement_base: Option<ReimbursementBase>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoleForm {
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct CustomRoleForm {
+    #[validate(
+        length(min = 1, max = 50, message = "Role name must have 1 to 50 characters"),
+        regex = "ROLE_NAME_REGEX"
+    )]
+    pub name: String,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RolePermissionsForm {
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct OrganizationForm {
+    #[validate(
+        length(min = 1, max = 50, message = "Slug must have 1 to 50 characters"),
+        regex = "SLUG_REGEX"
+    )]
+    pub slug: String,
+    #[validate(length(min = 1, max = 100, message = "Name must have 1 to 100 characters"))]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct OrganizationNameForm {
+    #[validate(length(min = 1, max = 100, message = "Name must have 1 to 100 characters"))]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManagerForm {
+    #[serde(rename = "managerId")]
+    pub manager_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CostCenterForm {
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct ChargeCodeForm {
+    pub kind: ChargeCodeKind,
+    #[validate(
+        length(min = 1, max = 20, message = "Code must have 1 to 20 characters"),
+        regex = "CHARGE_CODE_REGEX"
+    )]
+    pub code: String,
+    #[validate(length(min = 1, max = 100, message = "Name must have 1 to 100 characters"))]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct ChargeCodeUpdateForm {
+    #[validate(length(min = 1, max = 100, message = "Name must have 1 to 100 characters"))]
+    pub name: Option<String>,
+    pub archived: Option<bool>,
+}
+
+fn validate_budget_period(form: &BudgetForm) -> Result<(), ValidationError> {
+    if form.period_start > form.period_end {
+        Err(ValidationError::new("Budget cannot end before it starts"))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[validate(schema(function = "validate_budget_period"))]
+pub struct BudgetForm {
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: i32,
+    #[serde(rename = "categoryId")]
+    pub category_id: Option<i32>,
+    #[serde(rename = "periodStart")]
+    pub period_start: NaiveDate,
+    #[serde(rename = "periodEnd")]
+    pub period_end: NaiveDate,
+    #[validate(custom = "validate_cost")]
+    pub amount: Decimal,
+    /// Defaults to warning
+    pub policy: Option<BudgetPolicy>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct BudgetUpdateForm {
+    #[validate(custom = "validate_cost")]
+    pub amount: Option<Decimal>,
+    pub policy: Option<BudgetPolicy>,
+}
+
+fn validate_delegation_period(form: &DelegationForm) -> Result<(), ValidationError> {
+    if form.starts_on > form.ends_on {
+        Err(ValidationError::new(
+            "Delegation cannot end before it starts",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[validate(schema(function = "validate_delegation_period"))]
+pub struct DelegationForm {
+    #[serde(rename = "delegateId")]
+    pub delegate_id: i32,
+    #[serde(rename = "startsOn")]
+    pub starts_on: NaiveDate,
+    #[serde(rename = "endsOn")]
+    pub ends_on: NaiveDate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct DecisionForm {
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    pub accept: bool,
+    #[validate(length(max = 500, message = "Reason is too long"))]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct BulkDecisionForm {
+    /// Apply all decisions in one transaction, or none of them
+    #[serde(default)]
+    pub atomic: bool,
+    #[validate(length(min = 1, max = 200), custom = "validate_decisions")]
+    pub decisions: Vec<DecisionForm>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct PaymentBatchForm {
+    #[validate(length(min = 1, max = 1000))]
+    #[serde(rename = "claimIds")]
+    pub claim_ids: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct PaymentForm {
+    /// Reference of the bank transfer, at most 35 characters as in SEPA
+    #[validate(length(min = 1, max = 35, message = "Reference must have 1 to 35 characters"))]
+    pub reference: String,
+}
+
+fn validate_iban(iban: &str) -> Result<(), ValidationError> {
+    if bank::is_valid_iban(&bank::normalize_iban(iban)) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Invalid IBAN"))
+    }
+}
+
+/// The name is stored trimmed and has to fit the 70 characters of a SEPA name
+fn validate_account_holder(account_holder: &str) -> Result<(), ValidationError> {
+    let length = account_holder.trim().chars().count();
+    if (1..=70).contains(&length) {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            "Account holder must have 1 to 70 characters",
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct BankDetailsForm {
+    #[serde(rename = "accountHolder")]
+    #[validate(custom = "validate_account_holder")]
+    pub account_holder: String,
+    #[validate(custom = "validate_iban")]
+    pub iban: String,
+    #[validate(regex = "BIC_REGEX")]
+    pub bic: Option<String>,
+    /// Current password of the user making the change
+    pub password: String,
+}
+
+fn validate_export_period(form: &AccountingExportForm) -> Result<(), ValidationError> {
+    if form.from > form.to {
+        Err(ValidationError::new("Period cannot end before it starts"))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountingFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, Validate)]
+#[validate(schema(function = "validate_export_period"))]
+pub struct AccountingExportForm {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub format: AccountingFormat,
 }
 
 #[cfg(test)]
@@ -141,4 +417,39 @@ mod tests {
         validate_password_strength("NoSpecialCharacter1337").unwrap_err();
         validate_password_strength("ProperP4$$word").unwrap();
     }
+
+    #[test]
+    fn test_bank_details_validation() {
+        let form = BankDetailsForm {
+            account_holder: "Jane Doe".to_string(),
+            iban: "DE89 3704 0044 0532 0130 00".to_string(),
+            bic: Some("COBADEFFXXX".to_string()),
+            password: "password".to_string(),
+        };
+        form.validate().unwrap();
+        BankDetailsForm {
+            iban: "DE89 3704 0044 0532 0130 01".to_string(),
+            ..form.clone()
+        }
+        .validate()
+        .unwrap_err();
+        BankDetailsForm {
+            bic: Some("cobadeff".to_string()),
+            ..form.clone()
+        }
+        .validate()
+        .unwrap_err();
+        BankDetailsForm {
+            account_holder: "   ".to_string(),
+            ..form.clone()
+        }
+        .validate()
+        .unwrap_err();
+        BankDetailsForm {
+            account_holder: format!(" {} ", "a".repeat(70)),
+            ..form
+        }
+        .validate()
+        .unwrap();
+    }
 }
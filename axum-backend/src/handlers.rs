@@ -1,21 +1,35 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use axum::http::{header, Response, StatusCode};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::http::{header, HeaderMap, Response, StatusCode};
 use axum::response::IntoResponse;
 use axum::{extract, Json};
-use rand::rngs::OsRng;
+use chrono::{NaiveDate, Utc};
+use futures::TryStreamExt;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor, Postgres, QueryBuilder};
 use validator::Validate;
 
+use crate::config::{self, Config};
 use crate::forms::{ItemForm, LoginForm};
 use crate::jwt_auth::{create_cookie_with_token, create_empty_cookie, create_new_auth_token};
-use crate::models::{BasicUserInfo, Category, Claim, ClaimStatus, Role, User};
-use crate::utils::generate_random_string;
-use crate::{email, forms, AppState};
+use crate::models::{
+    AccountingExport, BankDetails, BankDetailsInfo, BasicUserInfo, Budget, BudgetPolicy, Category,
+    ChargeCode, ChargeCodeKind, Claim, ClaimDecision, ClaimDetails, ClaimFlag, ClaimStatus,
+    ClaimStep, DecisionDetails, Delegation, FlagKind, Item, ItemDetails, JournalEntryKind,
+    Organization, OrganizationSettings, PaymentBatch, PaymentBatchStatus, PendingClaim,
+    ReimbursementBase, Role, StepKind, User,
+};
+use crate::permissions::{self, Permission, Permissions};
+use crate::utils::{generate_random_string, hash_password};
+use crate::{
+    accounting, approvals, bank, budgets, claim_report, email, export, flags, forms, import,
+    reporting, roles, search, sepa, tax, tenant, AppState,
+};
 
 type ErrorResponse = (StatusCode, &'static str);
 
@@ -54,11 +68,18 @@ const DATABASE_ERROR: ErrorResponse = error_response!(
 
 pub async fn list_categories(
     extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
 ) -> Result<Json<Vec<Category>>, ErrorResponse> {
-    let categories = sqlx::query_as!(Category, "SELECT * FROM categories")
-        .fetch_all(&app_state.pool)
-        .await
-        .map_err(|_| DATABASE_ERROR)?;
+    let categories = sqlx::query_as!(
+        Category,
+        r#"SELECT id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id
+        FROM categories WHERE organization_id = $1"#,
+        organization.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
     Ok(Json(categories))
 }
 
@@ -67,14 +88,19 @@ pub struct UsersCount {
     count: i64,
 }
 
+/// Number of users of the caller's organisation
 pub async fn users_count(
     extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
 ) -> Result<Json<UsersCount>, ErrorResponse> {
-    let users_count = sqlx::query_scalar!("SELECT COUNT(*) FROM users")
-        .fetch_one(&app_state.pool)
-        .await
-        .map_err(|_| DATABASE_ERROR)?
-        .unwrap_or(0);
+    let users_count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM users WHERE organization_id = $1",
+        organization.id
+    )
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .unwrap_or(0);
     Ok(Json(UsersCount { count: users_count }))
 }
 
@@ -85,12 +111,15 @@ pub struct StartswithPayload {
 
 pub async fn users_startswith(
     extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
     extract::Query(query): extract::Query<StartswithPayload>,
 ) -> Result<Json<Vec<BasicUserInfo>>, ErrorResponse> {
     let start = query.start.unwrap_or("".to_string());
     let users = sqlx::query_as!(
         BasicUserInfo,
-        "SELECT id, username, role, verified, created_at FROM users WHERE username LIKE $1 LIMIT 10",
+        "SELECT id, username, role, verified, created_at, manager_id, default_cost_center_id FROM users
+        WHERE organization_id = $1 AND username LIKE $2 LIMIT 10",
+        organization.id,
         format!("{}%", start)
     )
     .fetch_all(&app_state.pool)
@@ -107,6 +136,7 @@ pub struct FetchUsersPayload {
 
 pub async fn users_list(
     extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
     extract::Query(query): extract::Query<FetchUsersPayload>,
 ) -> Result<Json<Vec<BasicUserInfo>>, ErrorResponse> {
     let limit = query.limit.unwrap_or(10);
@@ -119,7 +149,9 @@ pub async fn users_list(
     }
     let users = sqlx::query_as!(
         BasicUserInfo,
-        "SELECT id, username, role, verified, created_at FROM users LIMIT $1 OFFSET $2",
+        "SELECT id, username, role, verified, created_at, manager_id, default_cost_center_id FROM users
+        WHERE organization_id = $1 ORDER BY id LIMIT $2 OFFSET $3",
+        organization.id,
         limit,
         offset
     )
@@ -136,6 +168,20 @@ pub async fn register_user(
     body.validate()
         .map_err(|_| bad_request!("Form failed validation"))?;
 
+    // Unknown and closed organisations look the same
+    let organization_id = sqlx::query_scalar!(
+        "SELECT id FROM organizations WHERE slug = $1
+        AND (settings ->> 'openRegistration')::BOOLEAN IS TRUE",
+        body.organization
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::FORBIDDEN,
+        "This organization does not allow registration"
+    ))?;
+
     let user_exists = sqlx::query_scalar!(
         "SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)",
         body.username.to_owned().to_ascii_lowercase()
@@ -151,18 +197,16 @@ pub async fn register_user(
 
     let mut transation = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
 
-    let salt = SaltString::generate(&mut OsRng);
-    let hashed_password = Argon2::default()
-        .hash_password(body.password.as_bytes(), &salt)
-        .map_err(|_| error_response!(StatusCode::INTERNAL_SERVER_ERROR, "Hash method failed"))
-        .map(|hash| hash.to_string())?;
+    let hashed_password = hash_password(&body.password)
+        .map_err(|_| error_response!(StatusCode::INTERNAL_SERVER_ERROR, "Hash method failed"))?;
 
     let user = sqlx::query_as!(
         User,
-        "INSERT INTO users ( mail, username, password_hash ) VALUES ($1, $2, $3) RETURNING *",
+        "INSERT INTO users ( mail, username, password_hash, organization_id ) VALUES ($1, $2, $3, $4) RETURNING *",
         body.mail.to_owned().to_ascii_lowercase(),
         body.username,
         hashed_password,
+        organization_id,
     )
     .fetch_one(&mut *transation)
     .await
@@ -198,6 +242,15 @@ pub async fn register_user(
     })))
 }
 
+fn is_password_correct(user: &User, password: &str) -> bool {
+    match PasswordHash::new(&user.password_hash) {
+        Ok(parsed_hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
 pub async fn login_user(
     extract::State(app_state): extract::State<Arc<AppState>>,
     extract::Json(body): extract::Json<LoginForm>,
@@ -219,19 +272,17 @@ pub async fn login_user(
         ));
     }
 
-    let is_password_correct = match PasswordHash::new(&user.password_hash) {
-        Ok(parsed_hash) => Argon2::default()
-            .verify_password(body.password.as_bytes(), &parsed_hash)
-            .map_or(false, |_| true),
-        Err(_) => false,
-    };
-
-    if !is_password_correct {
+    if !is_password_correct(&user, &body.password) {
         return Err(bad_request!("Username or password dont match"));
     }
 
-    let token = create_new_auth_token(app_state.config.jwt_secret.clone(), user.id)
-        .map_err(|status_code| error_response!(status_code))?;
+    let token = create_new_auth_token(
+        app_state.config.jwt_secret.clone(),
+        user.id,
+        user.organization_id,
+        user.session_version,
+    )
+    .map_err(|status_code| error_response!(status_code))?;
     // Create a response with the access token and set it as a cookie
     let cookie = create_cookie_with_token(token.clone());
 
@@ -278,25 +329,45 @@ pub async fn logout_user() -> Result<impl IntoResponse, ErrorResponse> {
     Ok(response)
 }
 
+#[derive(Debug, Serialize)]
+pub struct CurrentUser {
+    #[serde(flatten)]
+    user: BasicUserInfo,
+    permissions: Vec<Permission>,
+    /// Organisation the caller acts in
+    organization: Organization,
+}
+
 pub async fn users_me(
     extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let user_info: BasicUserInfo = user.into();
-    Ok(Json(user_info))
+    Ok(Json(CurrentUser {
+        user: user.into(),
+        permissions: permissions.sorted(),
+        organization,
+    }))
 }
 
 pub async fn get_user_by_id(
     extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
     extract::Path(user_id): extract::Path<i32>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
-        .fetch_optional(&app_state.pool)
-        .await
-        .map_err(|_| DATABASE_ERROR)?
-        .ok_or(error_response!(
-            StatusCode::NOT_FOUND,
-            "User with this id does not exist"
-        ))?;
+    let user = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE id = $1 AND organization_id = $2",
+        user_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "User with this id does not exist"
+    ))?;
     Ok(Json(user))
 }
 
@@ -311,329 +382,4277 @@ pub async fn users_delete_account(
     Ok(success_response!())
 }
 
-pub async fn make_manager(
+/// Any role of the organisation can be set, except that the last admin cannot be demoted
+/// and that admins cannot grant more than they have
+pub async fn set_role(
     extract::State(app_state): extract::State<Arc<AppState>>,
     extract::Extension(admin): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
     extract::Path(user_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::RoleForm>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    if admin.role < Role::Admin {
-        return Err(error_response!(
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+    roles::change_role(
+        &mut transaction,
+        organization.id,
+        user_id,
+        &body.role,
+        Some((admin.id, &permissions)),
+    )
+    .await
+    .map_err(|e| match e {
+        roles::RoleChangeError::UserNotFound => {
+            error_response!(StatusCode::NOT_FOUND, "User with this id does not exist")
+        }
+        roles::RoleChangeError::UnknownRole => bad_request!("Role does not exist"),
+        roles::RoleChangeError::NotPermitted => error_response!(
             StatusCode::FORBIDDEN,
-            "You must be an admin to perform this action"
-        ));
-    }
-    let user = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
-        .fetch_optional(&app_state.pool)
+            "You cannot grant or revoke permissions you do not have"
+        ),
+        roles::RoleChangeError::Unchanged => {
+            error_response!(StatusCode::CONFLICT, "User already has this role")
+        }
+        roles::RoleChangeError::LastAdmin => {
+            error_response!(StatusCode::CONFLICT, "The last admin cannot be demoted")
+        }
+        roles::RoleChangeError::Database(_) => DATABASE_ERROR,
+    })?;
+    let user = sqlx::query_as!(
+        BasicUserInfo,
+        "SELECT id, username, role, verified, created_at, manager_id, default_cost_center_id FROM users
+        WHERE id = $1",
+        user_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(user))
+}
+
+/// Every role of the organisation with its permissions, for the role pickers
+pub async fn list_roles(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+) -> Result<Json<Vec<permissions::RoleDefinition>>, ErrorResponse> {
+    let roles = permissions::list_roles(&app_state.pool, organization.id)
         .await
-        .map_err(|_| DATABASE_ERROR)?
+        .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(roles))
+}
+
+/// Locks a custom role of the organisation, fails for built-in and unknown roles
+async fn custom_role(
+    conn: &mut PgConnection,
+    organization_id: i32,
+    name: &str,
+) -> Result<(), ErrorResponse> {
+    let builtin = sqlx::query_scalar!(
+        "SELECT builtin FROM roles WHERE name = $1 AND (organization_id IS NULL OR organization_id = $2)
+        FOR UPDATE",
+        name,
+        organization_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
         .ok_or(error_response!(
             StatusCode::NOT_FOUND,
-            "User with this id does not exist"
+            "Role with this name does not exist"
         ))?;
-    if user.role >= Role::Manager {
+    if builtin {
         return Err(error_response!(
             StatusCode::CONFLICT,
-            "User is already a manager"
+            "Built-in roles cannot be changed"
         ));
     }
-    sqlx::query!(
-        "UPDATE users SET role = $1 WHERE id = $2",
-        Role::Manager.to_string(),
-        user_id
-    )
-    .execute(&app_state.pool)
-    .await
-    .map_err(|_| DATABASE_ERROR)?;
-
-    Ok(success_response!())
+    Ok(())
 }
 
-pub async fn create_category(
-    extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(admin): extract::Extension<User>,
-    extract::Json(body): extract::Json<forms::CategoryForm>,
-) -> Result<impl IntoResponse, ErrorResponse> {
-    if admin.role < Role::Admin {
+/// Fails when the role would allow something the caller is not allowed to do
+async fn set_role_permissions(
+    conn: &mut PgConnection,
+    granter: &Permissions,
+    organization_id: i32,
+    name: &str,
+    permissions: &[Permission],
+) -> Result<permissions::RoleDefinition, ErrorResponse> {
+    if !granter.includes(&permissions.iter().copied().collect()) {
         return Err(error_response!(
             StatusCode::FORBIDDEN,
-            "You must be an admin to perform this action"
+            "You cannot grant or revoke permissions you do not have"
         ));
     }
-
-    let category = sqlx::query_as!(
-        Category,
-        "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement ) VALUES ($1, $2, $3) RETURNING *",
-        body.name,
-        body.reimbursement_percentage,
-        body.max_reimbursement
+    let names: Vec<String> = permissions.iter().map(|p| p.to_string()).collect();
+    sqlx::query!(
+        "DELETE FROM role_permissions WHERE role = $1 AND organization_id = $2",
+        name,
+        organization_id
     )
-        .fetch_optional(&app_state.pool)
-        .await
-        .map_err(|_| DATABASE_ERROR)?
-        .ok_or(error_response!(
-            StatusCode::CONFLICT,
-            "Category with this name already exists"
-        ))?;
+    .execute(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    sqlx::query!(
+        "INSERT INTO role_permissions ( organization_id, role, permission )
+        SELECT $1, $2, UNNEST($3::VARCHAR[])
+        ON CONFLICT DO NOTHING",
+        organization_id,
+        name,
+        &names
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
 
-    Ok(success_response!(category))
+    Ok(permissions::RoleDefinition {
+        name: Role::from(name.to_string()),
+        builtin: false,
+        permissions: permissions
+            .iter()
+            .copied()
+            .collect::<Permissions>()
+            .sorted(),
+    })
 }
 
-pub async fn delete_category(
+/// Custom role of the caller's organisation, the names of built-in roles are taken
+pub async fn create_role(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(admin): extract::Extension<User>,
-    extract::Path(category_id): extract::Path<i32>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::CustomRoleForm>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    if admin.role < Role::Admin {
-        return Err(error_response!(
-            StatusCode::FORBIDDEN,
-            "You must be an admin to perform this action"
-        ));
-    }
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
 
-    let category = sqlx::query_as!(
-        Category,
-        "DELETE FROM categories WHERE id = $1 RETURNING *",
-        category_id
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+    sqlx::query_scalar!(
+        "INSERT INTO roles ( name, organization_id ) SELECT $1::VARCHAR, $2
+        WHERE NOT EXISTS (SELECT 1 FROM roles WHERE name = $1 AND organization_id IS NULL)
+        ON CONFLICT DO NOTHING RETURNING name",
+        body.name,
+        organization.id
     )
-    .fetch_optional(&app_state.pool)
+    .fetch_optional(&mut *transaction)
     .await
     .map_err(|_| DATABASE_ERROR)?
     .ok_or(error_response!(
-        StatusCode::NOT_FOUND,
-        "Category with this id does not exist"
+        StatusCode::CONFLICT,
+        "This role already exists"
     ))?;
+    let role = set_role_permissions(
+        &mut transaction,
+        &permissions,
+        organization.id,
+        &body.name,
+        &body.permissions,
+    )
+    .await?;
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
 
-    Ok(success_response!(category))
-}
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct UpdateCategory {
-    reimbursement_percentage: Option<Decimal>,
-    max_reimbursement: Option<Decimal>,
+    Ok(success_response!(role))
 }
 
-pub async fn update_category(
+/// Replaces the permissions of a custom role, they apply to the next request of its users
+pub async fn update_role(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(admin): extract::Extension<User>,
-    extract::Path(category_id): extract::Path<i32>,
-    extract::Query(updates): extract::Query<UpdateCategory>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(name): extract::Path<String>,
+    extract::Json(body): extract::Json<forms::RolePermissionsForm>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    if admin.role < Role::Admin {
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+    custom_role(&mut transaction, organization.id, &name).await?;
+    let current = permissions::for_role(&mut *transaction, organization.id, &name)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+    let role = set_role_permissions(
+        &mut transaction,
+        &permissions,
+        organization.id,
+        &name,
+        &body.permissions,
+    )
+    .await?;
+    if !permissions.includes(&current) {
         return Err(error_response!(
             StatusCode::FORBIDDEN,
-            "You must be an admin to perform this action"
+            "You cannot grant or revoke permissions you do not have"
         ));
     }
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
 
-    let category = sqlx::query_as!(
-        Category,
-        "UPDATE categories 
-        SET reimbursement_percentage = COALESCE($1, reimbursement_percentage), max_reimbursement = COALESCE($2, max_reimbursement) 
-        WHERE id = $3
-        RETURNING *",
-        updates.reimbursement_percentage,
-        updates.max_reimbursement,
-        category_id
-    )
-    .fetch_optional(&app_state.pool)
-    .await
-    .map_err(|_| DATABASE_ERROR)?
-    .ok_or(error_response!(
-        StatusCode::NOT_FOUND,
-        "Category with this id does not exist"
-    ))?;
-
-    Ok(success_response!(category))
+    Ok(success_response!(role))
 }
 
-pub async fn create_claim(
+pub async fn delete_role(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(user): extract::Extension<User>,
-    extract::Json(body): extract::Json<forms::ClaimForm>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(name): extract::Path<String>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    body.validate()
-        .map_err(|_| bad_request!("Form failed validation"))?;
-
     let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
-
-    let claim = sqlx::query_as!(
-        Claim,
-        "INSERT INTO claims ( user_id ) VALUES ($1) RETURNING *",
-        user.id
+    custom_role(&mut transaction, organization.id, &name).await?;
+    let in_use = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM users WHERE role = $1 AND organization_id = $2) AS "in_use!""#,
+        name,
+        organization.id
     )
     .fetch_one(&mut *transaction)
     .await
     .map_err(|_| DATABASE_ERROR)?;
-
-    let mut total_cost = Decimal::from(0);
-    let mut reimbursement = Decimal::from(0);
-    for item in body.items {
-        let category = sqlx::query_as!(
-            Category,
-            "SELECT * FROM categories WHERE id = $1",
-            item.category_id
-        )
-        .fetch_optional(&mut *transaction)
-        .await
-        .map_err(|_| DATABASE_ERROR)?
-        .ok_or(error_response!(
-            StatusCode::NOT_FOUND,
-            "Category with this id does not exist"
-        ))?;
-
-        let mut item_reimbursement =
-            category.reimbursement_percentage * item.cost / Decimal::from(100);
-        if item_reimbursement > category.max_reimbursement {
-            item_reimbursement = category.max_reimbursement;
-        }
-
-        sqlx::query!(
-            "INSERT INTO items ( claim_id, category_id, cost, reimbursement ) VALUES ($1, $2, $3, $4)",
-            claim.id,
-            item.category_id,
-            item.cost,
-            item_reimbursement
-        )
-        .execute(&mut *transaction)
-        .await
-        .map_err(|_| DATABASE_ERROR)?;
-
-        total_cost += item.cost;
-        reimbursement += item_reimbursement
+    if in_use {
+        return Err(error_response!(
+            StatusCode::CONFLICT,
+            "This role is still assigned to users"
+        ));
     }
-
     sqlx::query!(
-        "UPDATE claims SET total_cost = $1, reimbursement = $2 WHERE id = $3",
-        total_cost,
-        reimbursement,
-        claim.id
+        "DELETE FROM roles WHERE name = $1 AND organization_id = $2",
+        name,
+        organization.id
     )
     .execute(&mut *transaction)
     .await
     .map_err(|_| DATABASE_ERROR)?;
-
     transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
 
-    Ok(success_response!(claim))
+    Ok(success_response!())
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct EstimateResult {
-    pub reimbursement: Decimal,
+pub async fn list_organizations(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+) -> Result<Json<Vec<Organization>>, ErrorResponse> {
+    let organizations = sqlx::query_as!(
+        Organization,
+        r#"SELECT id, slug, name, settings AS "settings: sqlx::types::Json<OrganizationSettings>", created_at
+        FROM organizations ORDER BY id ASC"#
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(organizations))
 }
 
-pub async fn estimate_item(
+/// New organisations start without users, a super-admin switches to it to set it up
+pub async fn create_organization(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Json(body): extract::Json<ItemForm>,
-) -> Result<Json<EstimateResult>, ErrorResponse> {
+    extract::Json(body): extract::Json<forms::OrganizationForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
     body.validate()
         .map_err(|_| bad_request!("Form failed validation"))?;
 
-    let category = sqlx::query_as!(
-        Category,
-        "SELECT * FROM categories WHERE id = $1",
-        body.category_id
+    let organization = sqlx::query_as!(
+        Organization,
+        r#"INSERT INTO organizations ( slug, name ) VALUES ($1, $2) ON CONFLICT DO NOTHING
+        RETURNING id, slug, name, settings AS "settings: sqlx::types::Json<OrganizationSettings>", created_at"#,
+        body.slug,
+        body.name
     )
     .fetch_optional(&app_state.pool)
     .await
     .map_err(|_| DATABASE_ERROR)?
     .ok_or(error_response!(
-        StatusCode::NOT_FOUND,
-        "Invalid category for an item"
+        StatusCode::CONFLICT,
+        "Organization with this slug already exists"
     ))?;
 
-    let mut reimbursement = category.reimbursement_percentage * body.cost / Decimal::from(100);
-    if reimbursement > category.max_reimbursement {
-        reimbursement = category.max_reimbursement;
-    }
-
-    Ok(Json(EstimateResult { reimbursement }))
+    Ok(success_response!(organization))
 }
 
-pub async fn approve_claim(
+pub async fn rename_organization(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(manager): extract::Extension<User>,
-    extract::Path(claim_id): extract::Path<i32>,
-    extract::Query(accept): extract::Query<bool>,
+    extract::Path(organization_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::OrganizationNameForm>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    if manager.role < Role::Manager {
-        return Err(error_response!(
-            StatusCode::FORBIDDEN,
-            "You must be a manager to perform this action"
-        ));
-    }
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
 
-    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+    let organization = sqlx::query_as!(
+        Organization,
+        r#"UPDATE organizations SET name = $1 WHERE id = $2
+        RETURNING id, slug, name, settings AS "settings: sqlx::types::Json<OrganizationSettings>", created_at"#,
+        body.name,
+        organization_id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Organization with this id does not exist"
+    ))?;
 
-    let claim = sqlx::query_as!(Claim, "SELECT * FROM claims WHERE id = $1", claim_id)
-        .fetch_optional(&mut *transaction)
-        .await
-        .map_err(|_| DATABASE_ERROR)?
-        .ok_or(error_response!(
-            StatusCode::NOT_FOUND,
-            "Claim with this id does not exist"
-        ))?;
+    Ok(success_response!(organization))
+}
 
-    if claim.status != ClaimStatus::Pending {
+/// Replaces the settings of the caller's organisation, missing values fall back to
+/// the server configuration
+pub async fn set_organization_settings(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(settings): extract::Json<OrganizationSettings>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if let Some(workflow) = &settings.approval_workflow {
+        config::check_workflow(workflow).map_err(|_| bad_request!("Invalid approval workflow"))?;
+    }
+    app_state
+        .config
+        .for_organization(&settings)
+        .validate()
+        .map_err(|_| bad_request!("Settings failed validation"))?;
+    if let Some(fallback_approver_id) = settings.fallback_approver_id {
+        let is_member = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND organization_id = $2) AS "exists!""#,
+            fallback_approver_id,
+            organization.id
+        )
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+        if !is_member {
+            return Err(bad_request!(
+                "Fallback approver must belong to the organization"
+            ));
+        }
+    }
+
+    let organization = sqlx::query_as!(
+        Organization,
+        r#"UPDATE organizations SET settings = $1 WHERE id = $2
+        RETURNING id, slug, name, settings AS "settings: sqlx::types::Json<OrganizationSettings>", created_at"#,
+        sqlx::types::Json(settings) as _,
+        organization.id
+    )
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(organization))
+}
+
+/// New token of the caller acting in another organisation
+pub async fn switch_organization(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Path(organization_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM organizations WHERE id = $1) AS "exists!""#,
+        organization_id
+    )
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    if !exists {
         return Err(error_response!(
-            StatusCode::CONFLICT,
-            "Claim is already processed"
+            StatusCode::NOT_FOUND,
+            "Organization with this id does not exist"
         ));
     }
 
-    let status = if accept {
-        ClaimStatus::Accepted
-    } else {
-        ClaimStatus::Rejected
-    };
+    let token = create_new_auth_token(
+        app_state.config.jwt_secret.clone(),
+        user.id,
+        organization_id,
+        user.session_version,
+    )
+    .map_err(|status_code| error_response!(status_code))?;
+    let cookie = create_cookie_with_token(token.clone());
 
-    sqlx::query!(
-        "UPDATE claims SET status = $1 WHERE id = $2",
-        status.to_string(),
-        claim_id
+    let mut response = success_response!(json!({"token": token}));
+    response
+        .headers_mut()
+        .insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    Ok(response)
+}
+
+pub async fn set_manager(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(user_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::ManagerForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    if let Some(manager_id) = body.manager_id {
+        let manager = sqlx::query_as!(
+            User,
+            "SELECT * FROM users WHERE id = $1 AND organization_id = $2",
+            manager_id,
+            organization.id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?
+        .ok_or(error_response!(
+            StatusCode::NOT_FOUND,
+            "Manager with this id does not exist"
+        ))?;
+        let can_approve =
+            permissions::user_has(&mut *transaction, manager.id, Permission::ClaimsApprove)
+                .await
+                .map_err(|_| DATABASE_ERROR)?;
+        if !can_approve {
+            return Err(bad_request!("Assigned user must be a manager"));
+        }
+
+        // Walk up from the new manager, the user must not be one of its superiors
+        let creates_cycle = sqlx::query_scalar!(
+            "WITH RECURSIVE chain AS (
+                SELECT id, manager_id FROM users WHERE id = $1
+                UNION
+                SELECT users.id, users.manager_id FROM users JOIN chain ON users.id = chain.manager_id
+            )
+            SELECT EXISTS(SELECT 1 FROM chain WHERE id = $2)",
+            manager_id,
+            user_id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?
+        .unwrap_or(false);
+        if creates_cycle {
+            return Err(error_response!(
+                StatusCode::CONFLICT,
+                "User cannot report to one of their own reports"
+            ));
+        }
+    }
+
+    let user = sqlx::query_as!(
+        BasicUserInfo,
+        "UPDATE users SET manager_id = $1 WHERE id = $2 AND organization_id = $3
+        RETURNING id, username, role, verified, created_at, manager_id, default_cost_center_id",
+        body.manager_id,
+        user_id,
+        organization.id
     )
-    .execute(&mut *transaction)
+    .fetch_optional(&mut *transaction)
     .await
-    .map_err(|_| DATABASE_ERROR)?;
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "User with this id does not exist"
+    ))?;
 
     transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
 
-    Ok(success_response!())
+    Ok(success_response!(user))
 }
 
-pub async fn list_my_claims(
+pub async fn set_cost_center(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(user): extract::Extension<User>,
-) -> Result<Json<Vec<Claim>>, ErrorResponse> {
-    let claims = sqlx::query_as!(
-        Claim,
-        "SELECT * FROM claims WHERE user_id = $1 ORDER BY id DESC",
-        user.id
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(user_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::CostCenterForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let mut conn = app_state.pool.acquire().await.map_err(|_| DATABASE_ERROR)?;
+    if let Some(cost_center_id) = body.cost_center_id {
+        check_charge_codes(
+            &mut conn,
+            organization.id,
+            &[(cost_center_id, ChargeCodeKind::CostCenter)],
+        )
+        .await?;
+    }
+
+    let user = sqlx::query_as!(
+        BasicUserInfo,
+        "UPDATE users SET default_cost_center_id = $1 WHERE id = $2 AND organization_id = $3
+        RETURNING id, username, role, verified, created_at, manager_id, default_cost_center_id",
+        body.cost_center_id,
+        user_id,
+        organization.id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "User with this id does not exist"
+    ))?;
+
+    Ok(success_response!(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportsQuery {
+    indirect: Option<bool>,
+}
+
+pub async fn list_reports(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(manager): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ReportsQuery>,
+) -> Result<Json<Vec<BasicUserInfo>>, ErrorResponse> {
+    let reports = sqlx::query_as!(
+        BasicUserInfo,
+        "WITH RECURSIVE reports AS (
+            SELECT id FROM users WHERE manager_id = $1
+            UNION
+            SELECT users.id FROM users JOIN reports ON users.manager_id = reports.id WHERE $2
+        )
+        SELECT id, username, role, verified, created_at, manager_id, default_cost_center_id FROM users
+        WHERE id IN (SELECT id FROM reports) AND organization_id = $3 ORDER BY id ASC",
+        manager.id,
+        query.indirect.unwrap_or(false),
+        organization.id
     )
     .fetch_all(&app_state.pool)
     .await
     .map_err(|_| DATABASE_ERROR)?;
-    Ok(Json(claims))
+    Ok(Json(reports))
 }
 
-pub async fn list_pedning_claims(
+pub async fn create_category(
     extract::State(app_state): extract::State<Arc<AppState>>,
-    extract::Extension(manager): extract::Extension<User>,
-) -> Result<Json<Vec<Claim>>, ErrorResponse> {
-    if manager.role < Role::Manager {
-        return Err(error_response!(
-            StatusCode::FORBIDDEN,
-            "You must be a manager to perform this action"
-        ));
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::CategoryForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let category = sqlx::query_as!(
+        Category,
+        r#"INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate, reimbursement_base, organization_id )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id"#,
+        body.name,
+        body.reimbursement_percentage,
+        body.max_reimbursement,
+        body.gl_account,
+        body.default_tax_rate.unwrap_or_default(),
+        body.reimbursement_base
+            .unwrap_or(ReimbursementBase::Gross)
+            .to_string(),
+        organization.id
+    )
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(|_| DATABASE_ERROR)?
+        .ok_or(error_response!(
+            StatusCode::CONFLICT,
+            "Category with this name already exists"
+        ))?;
+
+    Ok(success_response!(category))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    dry_run: Option<bool>,
+}
+
+/// Categories from a CSV file with `name,percentage,cap` columns
+pub async fn import_categories(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ImportQuery>,
+    body: String,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let report = import::import_categories(
+        &app_state.pool,
+        organization.id,
+        &body,
+        query.dry_run.unwrap_or(false),
+    )
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(success_response!(report))
+}
+
+/// Users from a CSV file with `mail,username,role,manager` columns. Imported users are
+/// verified and get a temporary password that is only returned once.
+pub async fn import_users(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ImportQuery>,
+    body: String,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let report = import::import_users(
+        &app_state.pool,
+        organization.id,
+        Some(&permissions),
+        &body,
+        query.dry_run.unwrap_or(false),
+    )
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(success_response!(report))
+}
+
+pub async fn delete_category(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(category_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let category = sqlx::query_as!(
+        Category,
+        r#"DELETE FROM categories WHERE id = $1 AND organization_id = $2
+        RETURNING id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id"#,
+        category_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Category with this id does not exist"
+    ))?;
+
+    Ok(success_response!(category))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateCategory {
+    reimbursement_percentage: Option<Decimal>,
+    max_reimbursement: Option<Decimal>,
+    gl_account: Option<String>,
+    default_tax_rate: Option<Decimal>,
+    reimbursement_base: Option<ReimbursementBase>,
+}
+
+pub async fn update_category(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(category_id): extract::Path<i32>,
+    extract::Query(updates): extract::Query<UpdateCategory>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if updates
+        .gl_account
+        .as_ref()
+        .is_some_and(|account| account.is_empty() || account.len() > 20)
+    {
+        return Err(bad_request!("GL account must have 1 to 20 characters"));
+    }
+    if updates
+        .default_tax_rate
+        .is_some_and(|rate| rate < Decimal::from(0) || rate > Decimal::from(100))
+    {
+        return Err(bad_request!("Tax rate must be between 0 and 100"));
     }
 
-    let claims = sqlx::query_as!(
-        Claim,
-        "SELECT * FROM claims WHERE status = $1 ORDER BY id ASC",
-        ClaimStatus::Pending.to_string()
+    let category = sqlx::query_as!(
+        Category,
+        r#"UPDATE categories 
+        SET reimbursement_percentage = COALESCE($1, reimbursement_percentage), max_reimbursement = COALESCE($2, max_reimbursement),
+        gl_account = COALESCE($3, gl_account), default_tax_rate = COALESCE($4, default_tax_rate),
+        reimbursement_base = COALESCE($5, reimbursement_base)
+        WHERE id = $6 AND organization_id = $7
+        RETURNING id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id"#,
+        updates.reimbursement_percentage,
+        updates.max_reimbursement,
+        updates.gl_account,
+        updates.default_tax_rate,
+        updates.reimbursement_base.map(|base| base.to_string()),
+        category_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Category with this id does not exist"
+    ))?;
+
+    Ok(success_response!(category))
+}
+
+/// Rejects codes that do not exist in the organisation, are archived or are of the wrong kind
+async fn check_charge_codes(
+    conn: &mut PgConnection,
+    organization_id: i32,
+    codes: &[(i32, ChargeCodeKind)],
+) -> Result<(), ErrorResponse> {
+    let ids: Vec<i32> = codes.iter().map(|(id, _)| *id).collect();
+    let found = sqlx::query_as!(
+        ChargeCode,
+        r#"SELECT id, kind AS "kind: ChargeCodeKind", code, name, archived, organization_id
+        FROM charge_codes WHERE organization_id = $1 AND id = ANY($2)"#,
+        organization_id,
+        &ids
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    for (id, kind) in codes {
+        match found.iter().find(|code| code.id == *id) {
+            Some(code) if code.kind != *kind => {
+                return Err(bad_request!("Unknown cost center or project"))
+            }
+            Some(code) if code.archived => {
+                return Err(bad_request!(
+                    "Archived cost centers and projects cannot be used"
+                ))
+            }
+            Some(_) => {}
+            None => return Err(bad_request!("Unknown cost center or project")),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChargeCodesQuery {
+    kind: Option<ChargeCodeKind>,
+    archived: Option<bool>,
+}
+
+pub async fn list_charge_codes(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ChargeCodesQuery>,
+) -> Result<Json<Vec<ChargeCode>>, ErrorResponse> {
+    let codes = sqlx::query_as!(
+        ChargeCode,
+        r#"SELECT id, kind AS "kind: ChargeCodeKind", code, name, archived, organization_id FROM charge_codes
+        WHERE organization_id = $1 AND ($2::VARCHAR IS NULL OR kind = $2) AND ($3 OR NOT archived)
+        ORDER BY kind ASC, code ASC"#,
+        organization.id,
+        query.kind.map(|kind| kind.to_string()),
+        query.archived.unwrap_or(false)
     )
     .fetch_all(&app_state.pool)
     .await
     .map_err(|_| DATABASE_ERROR)?;
-    Ok(Json(claims))
+    Ok(Json(codes))
+}
+
+pub async fn create_charge_code(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::ChargeCodeForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let code = sqlx::query_as!(
+        ChargeCode,
+        r#"INSERT INTO charge_codes ( kind, code, name, organization_id ) VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING RETURNING id, kind AS "kind: ChargeCodeKind", code, name, archived, organization_id"#,
+        body.kind.to_string(),
+        body.code,
+        body.name,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::CONFLICT,
+        "This code already exists"
+    ))?;
+
+    Ok(success_response!(code))
+}
+
+pub async fn update_charge_code(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(code_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::ChargeCodeUpdateForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let code = sqlx::query_as!(
+        ChargeCode,
+        r#"UPDATE charge_codes SET name = COALESCE($1, name), archived = COALESCE($2, archived)
+        WHERE id = $3 AND organization_id = $4
+        RETURNING id, kind AS "kind: ChargeCodeKind", code, name, archived, organization_id"#,
+        body.name,
+        body.archived,
+        code_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Code with this id does not exist"
+    ))?;
+
+    Ok(success_response!(code))
+}
+
+pub async fn create_claim(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::ClaimForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+    let config = app_state.config.for_organization(&organization.settings);
+
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    // An archived default no longer applies, the claim goes without a cost center then
+    let cost_center_id = match body.cost_center_id {
+        Some(cost_center_id) => Some(cost_center_id),
+        None => sqlx::query_scalar!(
+            "SELECT id FROM charge_codes WHERE id = $1 AND NOT archived",
+            user.default_cost_center_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?,
+    };
+    let codes: Vec<(i32, ChargeCodeKind)> = body
+        .items
+        .iter()
+        .map(|item| (item.cost_center_id, item.project_id))
+        .chain([(cost_center_id, body.project_id)])
+        .flat_map(|(cost_center_id, project_id)| {
+            [
+                cost_center_id.map(|id| (id, ChargeCodeKind::CostCenter)),
+                project_id.map(|id| (id, ChargeCodeKind::Project)),
+            ]
+        })
+        .flatten()
+        .collect();
+
+    check_charge_codes(&mut transaction, organization.id, &codes).await?;
+
+    let claim = sqlx::query_as!(
+        Claim,
+        "INSERT INTO claims ( user_id, cost_center_id, project_id, description, submitted_at, organization_id )
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5) RETURNING *",
+        user.id,
+        cost_center_id,
+        body.project_id,
+        body.description,
+        organization.id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let mut total_cost = Decimal::from(0);
+    let mut total_tax = Decimal::from(0);
+    let mut reimbursement = Decimal::from(0);
+    for item in body.items {
+        let category = sqlx::query_as!(
+            Category,
+            r#"SELECT id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+            reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id
+            FROM categories WHERE id = $1 AND organization_id = $2"#,
+            item.category_id,
+            organization.id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?
+        .ok_or(error_response!(
+            StatusCode::NOT_FOUND,
+            "Category with this id does not exist"
+        ))?;
+
+        let split = tax::breakdown(
+            item.cost,
+            item.tax_rate,
+            item.tax_amount,
+            item.net_amount,
+            category.default_tax_rate,
+        )
+        .map_err(|message| bad_request!(message))?;
+        let (item_reimbursement, reimbursed_tax) = tax::reimbursement(&category, item.cost, &split);
+
+        let expense_date = item.date.unwrap_or_else(|| Utc::now().date_naive());
+        let item_flags = flags::detect_item_flags(
+            &mut transaction,
+            &config,
+            organization.id,
+            user.id,
+            &item,
+            expense_date,
+        )
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+
+        let item_id = sqlx::query_scalar!(
+            "INSERT INTO items ( claim_id, category_id, cost, reimbursement, expense_date, receipt_hash, net_cost, tax_rate, tax_amount, reimbursed_tax, cost_center_id, project_id )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id",
+            claim.id,
+            item.category_id,
+            item.cost,
+            item_reimbursement,
+            expense_date,
+            item.receipt_hash,
+            split.net,
+            split.rate,
+            split.tax,
+            reimbursed_tax,
+            item.cost_center_id,
+            item.project_id
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+
+        for flag in item_flags {
+            sqlx::query!(
+                "INSERT INTO claim_flags ( claim_id, item_id, kind, reason ) VALUES ($1, $2, $3, $4)",
+                claim.id,
+                item_id,
+                flag.kind.to_string(),
+                flag.reason
+            )
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| DATABASE_ERROR)?;
+
+            if config.blocking_flags.contains(&flag.kind) {
+                return Err(error_response!(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Claim was flagged as suspicious and cannot be submitted"
+                ));
+            }
+        }
+
+        total_cost += item.cost;
+        total_tax += split.tax;
+        reimbursement += item_reimbursement
+    }
+
+    sqlx::query!(
+        "UPDATE claims SET total_cost = $1, reimbursement = $2, total_tax = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $4",
+        total_cost,
+        reimbursement,
+        total_tax,
+        claim.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let steps = approvals::required_steps(&config, reimbursement);
+    for (position, kind) in steps.into_iter().enumerate() {
+        sqlx::query!(
+            "INSERT INTO claim_steps ( claim_id, position, kind ) VALUES ($1, $2, $3)",
+            claim.id,
+            position as i32,
+            kind.to_string()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+    }
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(claim))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EstimateResult {
+    pub reimbursement: Decimal,
+    #[serde(rename = "netCost")]
+    pub net_cost: Decimal,
+    #[serde(rename = "taxAmount")]
+    pub tax_amount: Decimal,
+    #[serde(rename = "taxRate")]
+    pub tax_rate: Decimal,
+}
+
+pub async fn estimate_item(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<ItemForm>,
+) -> Result<Json<EstimateResult>, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let category = sqlx::query_as!(
+        Category,
+        r#"SELECT id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id
+        FROM categories WHERE id = $1 AND organization_id = $2"#,
+        body.category_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Invalid category for an item"
+    ))?;
+
+    let split = tax::breakdown(
+        body.cost,
+        body.tax_rate,
+        body.tax_amount,
+        body.net_amount,
+        category.default_tax_rate,
+    )
+    .map_err(|message| bad_request!(message))?;
+    let (reimbursement, _) = tax::reimbursement(&category, body.cost, &split);
+
+    Ok(Json(EstimateResult {
+        reimbursement,
+        net_cost: split.net,
+        tax_amount: split.tax,
+        tax_rate: split.rate,
+    }))
+}
+
+/// Users who delegated their approval rights to `delegate_id` for today, with their permissions
+async fn active_delegators<'e>(
+    executor: impl PgExecutor<'e>,
+    delegate_id: i32,
+) -> Result<Vec<(i32, Permissions)>, ErrorResponse> {
+    let delegators = sqlx::query!(
+        r#"SELECT users.id,
+        ARRAY(
+            SELECT permission FROM role_permissions WHERE role_permissions.role = users.role
+            AND (role_permissions.organization_id IS NULL OR role_permissions.organization_id = users.organization_id)
+        ) AS "permissions!"
+        FROM delegations JOIN users ON users.id = delegations.delegator_id
+        WHERE delegations.delegate_id = $1 AND CURRENT_DATE BETWEEN delegations.starts_on AND delegations.ends_on"#,
+        delegate_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(delegators
+        .into_iter()
+        .map(|delegator| {
+            (
+                delegator.id,
+                Permissions::from_names(&delegator.permissions),
+            )
+        })
+        .collect())
+}
+
+pub async fn create_delegation(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(manager): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::DelegationForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+    if body.delegate_id == manager.id {
+        return Err(bad_request!("You cannot delegate to yourself"));
+    }
+
+    let delegate_exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND organization_id = $2 AND verified)",
+        body.delegate_id,
+        organization.id
+    )
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .unwrap_or(false);
+    if !delegate_exists {
+        return Err(error_response!(
+            StatusCode::NOT_FOUND,
+            "User with this id does not exist"
+        ));
+    }
+
+    let delegation = sqlx::query_as!(
+        Delegation,
+        "INSERT INTO delegations ( delegator_id, delegate_id, starts_on, ends_on ) VALUES ($1, $2, $3, $4) RETURNING *",
+        manager.id,
+        body.delegate_id,
+        body.starts_on,
+        body.ends_on
+    )
+    .fetch_one(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(delegation))
+}
+
+/// Delegations given and received by the caller
+pub async fn list_my_delegations(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+) -> Result<Json<Vec<Delegation>>, ErrorResponse> {
+    let delegations = sqlx::query_as!(
+        Delegation,
+        "SELECT * FROM delegations WHERE delegator_id = $1 OR delegate_id = $1 ORDER BY starts_on DESC",
+        user.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(delegations))
+}
+
+pub async fn delete_delegation(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(delegation_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let delegation = sqlx::query_as!(
+        Delegation,
+        "DELETE FROM delegations WHERE id = $1 AND (delegator_id = $2
+            OR ($3 AND delegator_id IN (SELECT id FROM users WHERE organization_id = $4)))
+        RETURNING *",
+        delegation_id,
+        user.id,
+        permissions.has(Permission::DelegationsManage),
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Delegation with this id does not exist"
+    ))?;
+
+    Ok(success_response!(delegation))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionOutcome {
+    #[serde(rename = "claimStatus")]
+    pub claim_status: ClaimStatus,
+    #[serde(rename = "currentStep")]
+    pub current_step: i32,
+    /// Budgets exceeded by the approval under a warning policy
+    #[serde(rename = "budgetWarnings")]
+    pub budget_warnings: Vec<i32>,
+}
+
+/// State of the current approval step of the claim, as checked against approvers
+async fn approval_context(
+    conn: &mut PgConnection,
+    config: &Config,
+    claim: &Claim,
+) -> Result<approvals::ApprovalContext, ErrorResponse> {
+    let mut claimant_managers: Vec<i32> = sqlx::query_scalar!(
+        r#"WITH RECURSIVE chain AS (
+            SELECT manager_id, 1 AS depth FROM users WHERE id = $1
+            UNION
+            SELECT users.manager_id, chain.depth + 1 FROM users JOIN chain ON users.id = chain.manager_id
+        )
+        SELECT manager_id AS "manager_id!" FROM chain WHERE manager_id IS NOT NULL ORDER BY depth ASC"#,
+        claim.user_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    if claimant_managers.is_empty() {
+        claimant_managers.extend(config.fallback_approver_id);
+    }
+
+    let step = sqlx::query_scalar!(
+        r#"SELECT kind AS "kind: StepKind" FROM claim_steps WHERE claim_id = $1 AND position = $2"#,
+        claim.id,
+        claim.current_step
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::CONFLICT,
+        "Claim has no approval step to decide on"
+    ))?;
+
+    let previous_approvers: Vec<i32> = sqlx::query!(
+        "SELECT approver_id, on_behalf_of FROM claim_decisions WHERE claim_id = $1 AND accepted ORDER BY id ASC",
+        claim.id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .into_iter()
+    .flat_map(|decision| [Some(decision.approver_id), decision.on_behalf_of])
+    .flatten()
+    .collect();
+
+    Ok(approvals::ApprovalContext {
+        claimant_id: claim.user_id,
+        claimant_managers,
+        step,
+        previous_approvers,
+    })
+}
+
+/// Records the caller's decision on the current step of the claim,
+/// shared by the single and the bulk decision endpoints
+async fn decide_claim(
+    conn: &mut PgConnection,
+    config: &Config,
+    organization_id: i32,
+    manager: (&User, &Permissions),
+    delegators: &[(i32, Permissions)],
+    decision: &forms::DecisionForm,
+) -> Result<DecisionOutcome, ErrorResponse> {
+    let claim = sqlx::query_as!(
+        Claim,
+        "SELECT * FROM claims WHERE id = $1 AND organization_id = $2 FOR UPDATE",
+        decision.claim_id,
+        organization_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Claim with this id does not exist"
+    ))?;
+
+    if claim.status != ClaimStatus::Pending {
+        return Err(error_response!(
+            StatusCode::CONFLICT,
+            "Claim is already processed"
+        ));
+    }
+
+    let step_count = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM claim_steps WHERE claim_id = $1",
+        claim.id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .unwrap_or(0);
+
+    let (manager, permissions) = manager;
+    let context = approval_context(&mut *conn, config, &claim).await?;
+    let on_behalf_of =
+        approvals::resolve_approver(config, &context, (manager.id, permissions), delegators)
+            .map_err(|message| error_response!(StatusCode::FORBIDDEN, message))?;
+
+    let budget_warnings = if decision.accept {
+        let charges = claim_budget_charges(&mut *conn, claim.id).await?;
+        let (blocking, warnings) = budgets::overruns(&charges);
+        if !blocking.is_empty() {
+            return Err(error_response!(
+                StatusCode::CONFLICT,
+                "Approval would exceed the budget"
+            ));
+        }
+        warnings.iter().map(|budget| budget.id).collect()
+    } else {
+        vec![]
+    };
+
+    sqlx::query!(
+        "INSERT INTO claim_decisions ( claim_id, approver_id, accepted, step, on_behalf_of, reason ) VALUES ($1, $2, $3, $4, $5, $6)",
+        claim.id,
+        manager.id,
+        decision.accept,
+        claim.current_step,
+        on_behalf_of,
+        decision.reason
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let is_final_step = i64::from(claim.current_step) + 1 >= step_count;
+    let (claim_status, current_step) = if !decision.accept {
+        (ClaimStatus::Rejected, claim.current_step)
+    } else if is_final_step {
+        (ClaimStatus::Accepted, claim.current_step)
+    } else {
+        (ClaimStatus::Pending, claim.current_step + 1)
+    };
+
+    sqlx::query!(
+        "UPDATE claims SET status = $1, current_step = $2,
+        step_started_at = CASE WHEN current_step = $2 THEN step_started_at ELSE CURRENT_TIMESTAMP END,
+        decided_at = CASE WHEN $4 THEN CURRENT_TIMESTAMP END,
+        updated_at = CURRENT_TIMESTAMP
+        WHERE id = $3",
+        claim_status.to_string(),
+        current_step,
+        claim.id,
+        claim_status != ClaimStatus::Pending
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    Ok(DecisionOutcome {
+        claim_status,
+        current_step,
+        budget_warnings,
+    })
+}
+
+/// Whether the caller reviews the claim: whoever can view all claims, the claimant's managers,
+/// everyone who decided on it and whoever can approve its current step
+async fn is_claim_reviewer(
+    conn: &mut PgConnection,
+    config: &Config,
+    user: &User,
+    permissions: &Permissions,
+    claim: &Claim,
+) -> Result<bool, ErrorResponse> {
+    if permissions.has(Permission::ClaimsViewAll) {
+        return Ok(true);
+    }
+    let involved = sqlx::query_scalar!(
+        r#"WITH RECURSIVE chain AS (
+            SELECT id, manager_id FROM users WHERE id = $1
+            UNION
+            SELECT users.id, users.manager_id FROM users JOIN chain ON users.id = chain.manager_id
+        )
+        SELECT EXISTS (SELECT 1 FROM chain WHERE manager_id = $2)
+        OR EXISTS (
+            SELECT 1 FROM claim_decisions WHERE claim_id = $3 AND $2 IN (approver_id, on_behalf_of)
+        ) AS "involved!""#,
+        claim.user_id,
+        user.id,
+        claim.id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    if involved || claim.status != ClaimStatus::Pending {
+        return Ok(involved);
+    }
+
+    let delegators = active_delegators(&mut *conn, user.id).await?;
+    let context = approval_context(conn, config, claim).await?;
+    Ok(approvals::resolve_approver(config, &context, (user.id, permissions), &delegators).is_ok())
+}
+
+/// Claim with everything shown on its detail page, for the owner and its reviewers. Claims the
+/// caller cannot read are reported as missing so that their ids do not leak.
+async fn claim_details(
+    conn: &mut PgConnection,
+    config: &Config,
+    organization_id: i32,
+    (user, permissions): (&User, &Permissions),
+    claim_id: i32,
+) -> Result<ClaimDetails, ErrorResponse> {
+    let not_found = error_response!(StatusCode::NOT_FOUND, "Claim with this id does not exist");
+
+    let claim = sqlx::query_as!(
+        Claim,
+        "SELECT * FROM claims WHERE id = $1 AND organization_id = $2",
+        claim_id,
+        organization_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(not_found)?;
+    let is_owner = claim.user_id == user.id;
+    if !is_owner && !is_claim_reviewer(conn, config, user, permissions, &claim).await? {
+        return Err(not_found);
+    }
+
+    let items = sqlx::query_as!(
+        Item,
+        "SELECT * FROM items WHERE claim_id = $1 ORDER BY id ASC",
+        claim.id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    let category_ids: Vec<i32> = items.iter().map(|item| item.category_id).collect();
+    let categories = sqlx::query_as!(
+        Category,
+        r#"SELECT id, name, reimbursement_percentage, max_reimbursement, gl_account, default_tax_rate,
+        reimbursement_base AS "reimbursement_base: ReimbursementBase", organization_id
+        FROM categories WHERE id = ANY($1)"#,
+        &category_ids
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    let items = items
+        .into_iter()
+        .map(|item| ItemDetails {
+            category: categories
+                .iter()
+                .find(|category| category.id == item.category_id)
+                .cloned(),
+            item,
+        })
+        .collect();
+
+    let reviewer = sqlx::query_as!(
+        BasicUserInfo,
+        "SELECT id, username, role, verified, created_at, manager_id, default_cost_center_id FROM users
+        WHERE id = (SELECT COALESCE(manager_id, $2) FROM users WHERE id = $1)",
+        claim.user_id,
+        config.fallback_approver_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let steps = sqlx::query_as!(
+        ClaimStep,
+        r#"SELECT id, claim_id, position, kind AS "kind: StepKind" FROM claim_steps
+        WHERE claim_id = $1 ORDER BY position ASC"#,
+        claim.id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let decisions = sqlx::query!(
+        r#"SELECT claim_decisions.*, approvers.username AS approver_name,
+        delegators.username AS "on_behalf_of_name?"
+        FROM claim_decisions
+        JOIN users approvers ON approvers.id = claim_decisions.approver_id
+        LEFT JOIN users delegators ON delegators.id = claim_decisions.on_behalf_of
+        WHERE claim_decisions.claim_id = $1
+        ORDER BY claim_decisions.id ASC"#,
+        claim.id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .into_iter()
+    .map(|row| DecisionDetails {
+        decision: ClaimDecision {
+            id: row.id,
+            claim_id: row.claim_id,
+            approver_id: row.approver_id,
+            accepted: row.accepted,
+            step: row.step,
+            on_behalf_of: row.on_behalf_of,
+            reason: row.reason,
+            created_at: row.created_at,
+        },
+        approver_name: row.approver_name,
+        on_behalf_of_name: row.on_behalf_of_name,
+    })
+    .collect();
+
+    // Heuristic flags are meant for reviewers, not for the claimant
+    let flags = if is_owner {
+        None
+    } else {
+        Some(
+            sqlx::query_as!(
+                ClaimFlag,
+                r#"SELECT id, claim_id, item_id, kind AS "kind: FlagKind", reason FROM claim_flags
+                WHERE claim_id = $1 ORDER BY id ASC"#,
+                claim.id
+            )
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|_| DATABASE_ERROR)?,
+        )
+    };
+
+    Ok(ClaimDetails {
+        claim,
+        items,
+        reviewer,
+        steps,
+        decisions,
+        flags,
+    })
+}
+
+pub async fn get_claim(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(claim_id): extract::Path<i32>,
+) -> Result<Json<ClaimDetails>, ErrorResponse> {
+    let config = app_state.config.for_organization(&organization.settings);
+    let mut conn = app_state.pool.acquire().await.map_err(|_| DATABASE_ERROR)?;
+    let details = claim_details(
+        &mut conn,
+        &config,
+        organization.id,
+        (&user, &permissions),
+        claim_id,
+    )
+    .await?;
+    Ok(Json(details))
+}
+
+/// Printable expense report of a claim, for the owner and its reviewers
+pub async fn claim_report(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(claim_id): extract::Path<i32>,
+) -> Result<Response<axum::body::Full<axum::body::Bytes>>, ErrorResponse> {
+    let config = app_state.config.for_organization(&organization.settings);
+    let mut conn = app_state.pool.acquire().await.map_err(|_| DATABASE_ERROR)?;
+    let details = claim_details(
+        &mut conn,
+        &config,
+        organization.id,
+        (&user, &permissions),
+        claim_id,
+    )
+    .await?;
+    let employee = sqlx::query_scalar!(
+        "SELECT username FROM users WHERE id = $1",
+        details.claim.user_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    let hashes: Vec<String> = details
+        .items
+        .iter()
+        .filter_map(|details| details.item.receipt_hash.clone())
+        .collect();
+    let receipts: HashMap<String, Vec<u8>> = sqlx::query!(
+        "SELECT hash, data FROM receipts
+        WHERE organization_id = $1 AND hash = ANY($2) AND content_type = 'image/jpeg'",
+        organization.id,
+        &hashes
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .into_iter()
+    .map(|receipt| (receipt.hash, receipt.data))
+    .collect();
+    drop(conn);
+
+    let pdf = claim_report::render(&claim_report::ClaimReport {
+        company: &config.debtor_name,
+        employee: &employee,
+        currency: &config.payment_currency,
+        details: &details,
+        receipts: &receipts,
+        generated_at: Utc::now().naive_utc(),
+    })
+    .map_err(|_| error_response!(StatusCode::INTERNAL_SERVER_ERROR, "Could not write PDF"))?;
+
+    let mut response = Response::new(axum::body::Full::from(pdf));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, "application/pdf".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"claim-{}.pdf\"", claim_id)
+            .parse()
+            .unwrap(),
+    );
+    Ok(response)
+}
+
+/// Receipt files that can be uploaded, only JPEG receipts are shown in claim reports
+const RECEIPT_CONTENT_TYPES: [&str; 3] = ["image/jpeg", "image/png", "application/pdf"];
+
+/// Stores a receipt file of up to 2 MB, the default body limit. Items refer to it by the
+/// returned hex encoded SHA-256 of the file, uploading the same file again changes nothing.
+pub async fn upload_receipt(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            RECEIPT_CONTENT_TYPES
+                .into_iter()
+                .find(|content_type| *content_type == value)
+        })
+        .ok_or(error_response!(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Receipts must be JPEG, PNG or PDF files"
+        ))?;
+    if body.is_empty() {
+        return Err(bad_request!("Receipt is empty"));
+    }
+
+    let hash: String = Sha256::digest(&body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    sqlx::query!(
+        "INSERT INTO receipts ( organization_id, hash, content_type, data, uploaded_by )
+        VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+        organization.id,
+        hash,
+        content_type,
+        &body[..],
+        user.id
+    )
+    .execute(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(json!({ "hash": hash })))
+}
+
+/// Delegators of the caller, fails unless the caller can approve anything at all
+async fn approver_delegators<'e>(
+    executor: impl PgExecutor<'e>,
+    manager: &User,
+    permissions: &Permissions,
+) -> Result<Vec<(i32, Permissions)>, ErrorResponse> {
+    let delegators = active_delegators(executor, manager.id).await?;
+
+    if !approvals::is_approver(permissions) && delegators.is_empty() {
+        return Err(error_response!(
+            StatusCode::FORBIDDEN,
+            "You must be a manager to perform this action"
+        ));
+    }
+    Ok(delegators)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApproveClaimQuery {
+    accept: bool,
+    reason: Option<String>,
+}
+
+pub async fn approve_claim(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(manager): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(claim_id): extract::Path<i32>,
+    extract::Query(query): extract::Query<ApproveClaimQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let decision = forms::DecisionForm {
+        claim_id,
+        accept: query.accept,
+        reason: query.reason,
+    };
+    decision
+        .validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let config = app_state.config.for_organization(&organization.settings);
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+    let delegators = approver_delegators(&mut *transaction, &manager, &permissions).await?;
+
+    let outcome = decide_claim(
+        &mut transaction,
+        &config,
+        organization.id,
+        (&manager, &permissions),
+        &delegators,
+        &decision,
+    )
+    .await?;
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(outcome))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkDecisionResult {
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    pub outcome: Option<DecisionOutcome>,
+    pub error: Option<&'static str>,
+}
+
+/// Applies many decisions at once, either all or nothing (`atomic`) or each on its own
+pub async fn decide_claims_bulk(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(manager): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::BulkDecisionForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+    let config = app_state.config.for_organization(&organization.settings);
+
+    let delegators = approver_delegators(&app_state.pool, &manager, &permissions).await?;
+
+    let mut results = Vec::with_capacity(body.decisions.len());
+    let committed = if body.atomic {
+        let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+        let mut failed = false;
+        for decision in &body.decisions {
+            let result = if failed {
+                Err(error_response!(
+                    StatusCode::CONFLICT,
+                    "Skipped after an earlier failure"
+                ))
+            } else {
+                decide_claim(
+                    &mut transaction,
+                    &config,
+                    organization.id,
+                    (&manager, &permissions),
+                    &delegators,
+                    decision,
+                )
+                .await
+            };
+            failed |= result.is_err();
+            results.push((decision.claim_id, result));
+        }
+        if !failed {
+            transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+        }
+        !failed
+    } else {
+        for decision in &body.decisions {
+            let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+            let result = decide_claim(
+                &mut transaction,
+                &config,
+                organization.id,
+                (&manager, &permissions),
+                &delegators,
+                decision,
+            )
+            .await;
+            if result.is_ok() {
+                transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+            }
+            results.push((decision.claim_id, result));
+        }
+        results.iter().any(|(_, result)| result.is_ok())
+    };
+
+    let results: Vec<BulkDecisionResult> = results
+        .into_iter()
+        .map(|(claim_id, result)| match result {
+            Ok(outcome) => BulkDecisionResult {
+                claim_id,
+                outcome: Some(outcome),
+                error: None,
+            },
+            Err((_, message)) => BulkDecisionResult {
+                claim_id,
+                outcome: None,
+                error: Some(message),
+            },
+        })
+        .collect();
+
+    Ok(success_response!(json!({
+        "committed": committed,
+        "results": results
+    })))
+}
+
+async fn with_review_details(
+    app_state: &AppState,
+    claims: Vec<Claim>,
+) -> Result<Vec<PendingClaim>, ErrorResponse> {
+    let claim_ids: Vec<i32> = claims.iter().map(|claim| claim.id).collect();
+    let flags = sqlx::query_as!(
+        ClaimFlag,
+        r#"SELECT id, claim_id, item_id, kind AS "kind: FlagKind", reason FROM claim_flags
+        WHERE claim_id = ANY($1) ORDER BY id ASC"#,
+        &claim_ids
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    let steps = sqlx::query_as!(
+        ClaimStep,
+        r#"SELECT id, claim_id, position, kind AS "kind: StepKind" FROM claim_steps
+        WHERE claim_id = ANY($1) ORDER BY position ASC"#,
+        &claim_ids
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    let decisions = sqlx::query_as!(
+        ClaimDecision,
+        "SELECT * FROM claim_decisions WHERE claim_id = ANY($1) ORDER BY id ASC",
+        &claim_ids
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    Ok(claims
+        .into_iter()
+        .map(|claim| PendingClaim {
+            flags: flags
+                .iter()
+                .filter(|flag| flag.claim_id == claim.id)
+                .cloned()
+                .collect(),
+            steps: steps
+                .iter()
+                .filter(|step| step.claim_id == claim.id)
+                .cloned()
+                .collect(),
+            decisions: decisions
+                .iter()
+                .filter(|decision| decision.claim_id == claim.id)
+                .cloned()
+                .collect(),
+            claim,
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimSearchQuery {
+    status: Option<ClaimStatus>,
+    /// Creation date range
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    /// Total cost range
+    min_amount: Option<Decimal>,
+    max_amount: Option<Decimal>,
+    category_id: Option<i32>,
+    user_id: Option<i32>,
+    /// Only the caller's own claims
+    mine: Option<bool>,
+    /// Charged to the code on the claim itself or on one of its items
+    cost_center_id: Option<i32>,
+    project_id: Option<i32>,
+    /// Text contained in the description
+    q: Option<String>,
+    /// Only claims waiting on a step the caller can approve,
+    /// either directly or on behalf of someone who delegated to them
+    awaiting_approval: Option<bool>,
+    /// Line manager steps of indirect reports count as waiting on the caller
+    indirect: Option<bool>,
+    sort: Option<String>,
+    order: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Conditions shared by the page and the total count. Callers who can view all claims see
+/// every claim of the organisation, approvers the claims of the people reporting to them and everyone else
+/// their own claims.
+fn push_claim_filters(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    config: &Config,
+    organization_id: i32,
+    (caller, permissions): (&User, &Permissions),
+    delegators: &[(i32, Permissions)],
+    query: &ClaimSearchQuery,
+) {
+    builder
+        .push(" AND claims.organization_id = ")
+        .push_bind(organization_id);
+    if query.awaiting_approval.unwrap_or(false) {
+        builder
+            .push(" AND claims.status = ")
+            .push_bind(ClaimStatus::Pending.to_string());
+        builder.push(" AND (FALSE");
+        let principals = std::iter::once((caller.id, permissions))
+            .chain(delegators.iter().map(|(id, p)| (*id, p)));
+        for (principal_id, principal_permissions) in principals {
+            let unscoped_steps: Vec<String> = approvals::unscoped_step_kinds(principal_permissions)
+                .iter()
+                .map(|kind| kind.to_string())
+                .collect();
+            // Line manager steps are limited to the principal's reports,
+            // employees without a manager are handled by the fallback approver
+            builder
+                .push(" OR (claims.user_id <> ")
+                .push_bind(principal_id)
+                .push(" AND claims.user_id <> ")
+                .push_bind(caller.id)
+                .push(" AND NOT EXISTS (SELECT 1 FROM claim_decisions WHERE claim_decisions.claim_id = claims.id AND (")
+                .push_bind(principal_id)
+                .push(" IN (approver_id, on_behalf_of) OR ")
+                .push_bind(caller.id)
+                .push(" IN (approver_id, on_behalf_of)))")
+                .push(" AND EXISTS (SELECT 1 FROM claim_steps JOIN users ON users.id = claims.user_id")
+                .push(" WHERE claim_steps.claim_id = claims.id AND claim_steps.position = claims.current_step")
+                .push(" AND (claim_steps.kind = ANY(")
+                .push_bind(unscoped_steps)
+                .push(") OR (claim_steps.kind = ")
+                .push_bind(StepKind::LineManager.to_string())
+                .push(" AND (claims.user_id IN (WITH RECURSIVE reports AS (SELECT id FROM users WHERE manager_id = ")
+                .push_bind(principal_id)
+                .push(" UNION SELECT users.id FROM users JOIN reports ON users.manager_id = reports.id WHERE ")
+                .push_bind(query.indirect.unwrap_or(false))
+                .push(") SELECT id FROM reports) OR (users.manager_id IS NULL AND ")
+                .push_bind(config.fallback_approver_id)
+                .push(" = ")
+                .push_bind(principal_id)
+                .push("))))))");
+        }
+        builder.push(")");
+    } else if permissions.has(Permission::ClaimsApprove) {
+        if !permissions.has(Permission::ClaimsViewAll) {
+            builder
+                .push(" AND (claims.user_id = ")
+                .push_bind(caller.id)
+                .push(" OR claims.user_id IN (WITH RECURSIVE reports AS (SELECT id FROM users WHERE manager_id = ")
+                .push_bind(caller.id)
+                .push(" UNION SELECT users.id FROM users JOIN reports ON users.manager_id = reports.id")
+                .push(") SELECT id FROM reports))");
+        }
+    } else if !permissions.has(Permission::ClaimsViewAll) {
+        builder.push(" AND claims.user_id = ").push_bind(caller.id);
+    }
+
+    if query.mine.unwrap_or(false) {
+        builder.push(" AND claims.user_id = ").push_bind(caller.id);
+    }
+    if let Some(user_id) = query.user_id {
+        builder.push(" AND claims.user_id = ").push_bind(user_id);
+    }
+    if let Some(status) = &query.status {
+        builder
+            .push(" AND claims.status = ")
+            .push_bind(status.to_string());
+    }
+    if let Some(from) = query.from {
+        builder
+            .push(" AND claims.created_at::date >= ")
+            .push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder
+            .push(" AND claims.created_at::date <= ")
+            .push_bind(to);
+    }
+    if let Some(min_amount) = query.min_amount {
+        builder
+            .push(" AND COALESCE(claims.total_cost, 0) >= ")
+            .push_bind(min_amount);
+    }
+    if let Some(max_amount) = query.max_amount {
+        builder
+            .push(" AND COALESCE(claims.total_cost, 0) <= ")
+            .push_bind(max_amount);
+    }
+    if let Some(category_id) = query.category_id {
+        builder
+            .push(" AND EXISTS (SELECT 1 FROM items WHERE items.claim_id = claims.id AND items.category_id = ")
+            .push_bind(category_id)
+            .push(")");
+    }
+    if let Some(cost_center_id) = query.cost_center_id {
+        builder
+            .push(" AND (claims.cost_center_id = ")
+            .push_bind(cost_center_id)
+            .push(" OR EXISTS (SELECT 1 FROM items WHERE items.claim_id = claims.id AND items.cost_center_id = ")
+            .push_bind(cost_center_id)
+            .push("))");
+    }
+    if let Some(project_id) = query.project_id {
+        builder
+            .push(" AND (claims.project_id = ")
+            .push_bind(project_id)
+            .push(" OR EXISTS (SELECT 1 FROM items WHERE items.claim_id = claims.id AND items.project_id = ")
+            .push_bind(project_id)
+            .push("))");
+    }
+    if let Some(text) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        builder
+            .push(" AND claims.description ILIKE ")
+            .push_bind(search::contains_pattern(text));
+    }
+}
+
+/// People the caller approves for when searching for claims awaiting approval
+async fn search_delegators<'e>(
+    executor: impl PgExecutor<'e>,
+    user: &User,
+    permissions: &Permissions,
+    query: &ClaimSearchQuery,
+) -> Result<Vec<(i32, Permissions)>, ErrorResponse> {
+    if !query.awaiting_approval.unwrap_or(false) {
+        return Ok(vec![]);
+    }
+    let delegators = active_delegators(executor, user.id).await?;
+    if !approvals::is_approver(permissions) && delegators.is_empty() {
+        return Err(error_response!(
+            StatusCode::FORBIDDEN,
+            "You must be a manager to perform this action"
+        ));
+    }
+    Ok(delegators)
+}
+
+/// Claims visible to the caller matching the filters, one page at a time. The cursor
+/// of the next page is only valid with the same sort column and order.
+pub async fn search_claims(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ClaimSearchQuery>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    let config = app_state.config.for_organization(&organization.settings);
+    let awaiting_approval = query.awaiting_approval.unwrap_or(false);
+    let delegators = search_delegators(&app_state.pool, &user, &permissions, &query).await?;
+
+    let sort: search::SortKey = query
+        .sort
+        .as_deref()
+        .unwrap_or("id")
+        .parse()
+        .map_err(|e| bad_request!(e))?;
+    let descending = match query.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(_) => return Err(bad_request!("Order must be asc or desc")),
+    };
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|cursor| search::decode_cursor(sort, cursor))
+        .transpose()
+        .map_err(|e| bad_request!(e))?;
+    let limit = query
+        .limit
+        .unwrap_or(search::DEFAULT_PAGE_SIZE)
+        .clamp(1, search::MAX_PAGE_SIZE);
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*) FROM claims WHERE TRUE");
+    push_claim_filters(
+        &mut count,
+        &config,
+        organization.id,
+        (&user, &permissions),
+        &delegators,
+        &query,
+    );
+    let total: i64 = count
+        .build_query_scalar()
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+
+    let mut page = QueryBuilder::new("SELECT claims.* FROM claims WHERE TRUE");
+    push_claim_filters(
+        &mut page,
+        &config,
+        organization.id,
+        (&user, &permissions),
+        &delegators,
+        &query,
+    );
+    let direction = if descending { "DESC" } else { "ASC" };
+    if let Some(cursor) = cursor {
+        page.push(format!(
+            " AND ({}, claims.id) {} (",
+            sort.expression(),
+            if descending { "<" } else { ">" }
+        ));
+        match cursor.value {
+            search::SortValue::Integer(value) => page.push_bind(value),
+            search::SortValue::Decimal(value) => page.push_bind(value),
+            search::SortValue::Timestamp(value) => page.push_bind(value),
+            search::SortValue::Text(value) => page.push_bind(value),
+        };
+        page.push(", ").push_bind(cursor.id).push(")");
+    }
+    page.push(format!(
+        " ORDER BY {} {direction}, claims.id {direction} LIMIT ",
+        sort.expression()
+    ))
+    .push_bind(limit + 1);
+
+    let mut claims: Vec<Claim> = page
+        .build_query_as()
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+    let next_cursor = if claims.len() as i64 > limit {
+        claims.truncate(limit as usize);
+        claims
+            .last()
+            .map(|claim| search::encode_cursor(sort, claim))
+    } else {
+        None
+    };
+
+    let results = if awaiting_approval {
+        json!(with_review_details(&app_state, claims).await?)
+    } else {
+        json!(claims)
+    };
+    Ok(Json(json!({
+        "results": results,
+        "total": total,
+        "nextCursor": next_cursor
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    format: export::ExportFormat,
+}
+
+/// Every item of the claims matching the search filters, streamed from the database
+/// while the file is written. The number of running exports is limited since each one
+/// keeps a connection of the pool busy until the download is finished.
+pub async fn export_claims(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ClaimSearchQuery>,
+    extract::Query(ExportQuery { format }): extract::Query<ExportQuery>,
+) -> Result<axum::response::Response, ErrorResponse> {
+    let config = app_state.config.for_organization(&organization.settings);
+    let delegators = search_delegators(&app_state.pool, &user, &permissions, &query).await?;
+    let permit = app_state
+        .export_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| {
+            error_response!(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many exports running, try again later"
+            )
+        })?;
+
+    let (sender, receiver) = tokio::sync::mpsc::channel::<std::io::Result<Vec<u8>>>(16);
+    // The task outlives the request, it keeps acting for the organisation
+    tokio::spawn(tenant::scope(organization.id, async move {
+        let _permit = permit;
+        let mut builder = QueryBuilder::new(
+            r#"SELECT claims.id AS claim_id, claims.status, claims.created_at, claims.description,
+                users.id AS user_id, users.username, items.id AS item_id, items.expense_date,
+                categories.name AS category, items.cost, items.reimbursement, items.net_cost,
+                items.tax_amount, cost_centers.code AS cost_center, projects.code AS project
+            FROM claims
+                JOIN items ON items.claim_id = claims.id
+                JOIN users ON users.id = claims.user_id
+                JOIN categories ON categories.id = items.category_id
+                LEFT JOIN charge_codes cost_centers
+                    ON cost_centers.id = COALESCE(items.cost_center_id, claims.cost_center_id)
+                LEFT JOIN charge_codes projects
+                    ON projects.id = COALESCE(items.project_id, claims.project_id)
+            WHERE TRUE"#,
+        );
+        push_claim_filters(
+            &mut builder,
+            &config,
+            organization.id,
+            (&user, &permissions),
+            &delegators,
+            &query,
+        );
+        builder.push(" ORDER BY claims.id, items.id");
+
+        let (mut writer, start) = match export::ExportWriter::start(format) {
+            Ok(start) => start,
+            Err(e) => {
+                let _ = sender.send(Err(e)).await;
+                return;
+            }
+        };
+        if sender.send(Ok(start)).await.is_err() {
+            return;
+        }
+
+        let mut rows = builder
+            .build_query_as::<export::ExportRow>()
+            .fetch(&app_state.pool);
+        loop {
+            let chunk = match rows.try_next().await {
+                Ok(Some(row)) => writer.row(&row),
+                Ok(None) => break,
+                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            };
+            let failed = chunk.is_err();
+            // Stop reading when the download was cancelled
+            if sender.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+        let _ = sender.send(writer.finish()).await;
+    }));
+
+    let chunks = futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"claims.{}\"", format.extension()),
+            ),
+        ],
+        axum::body::StreamBody::new(chunks),
+    )
+        .into_response())
+}
+
+pub async fn create_payment_batch(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::PaymentBatchForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let mut claim_ids = body.claim_ids;
+    claim_ids.sort_unstable();
+    claim_ids.dedup();
+
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    let claims = sqlx::query_as!(
+        Claim,
+        "SELECT * FROM claims WHERE id = ANY($1) AND status = $2 AND payment_batch_id IS NULL
+        AND organization_id = $3 FOR UPDATE",
+        &claim_ids,
+        ClaimStatus::Accepted.to_string(),
+        organization.id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    if claims.len() != claim_ids.len() {
+        return Err(error_response!(
+            StatusCode::CONFLICT,
+            "Only accepted claims that are not in a batch yet can be paid"
+        ));
+    }
+
+    let total: Decimal = claims
+        .iter()
+        .map(|claim| claim.reimbursement.unwrap_or_default())
+        .sum();
+
+    let batch = sqlx::query_as!(
+        PaymentBatch,
+        r#"INSERT INTO payment_batches ( total, created_by, organization_id ) VALUES ($1, $2, $3)
+        RETURNING id, status AS "status: PaymentBatchStatus", total, created_by, created_at, exported_at, paid_at,
+        reference, organization_id"#,
+        total,
+        user.id,
+        organization.id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    sqlx::query!(
+        "UPDATE claims SET payment_batch_id = $1, updated_at = CURRENT_TIMESTAMP WHERE id = ANY($2)",
+        batch.id,
+        &claim_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(batch))
+}
+
+pub async fn list_payment_batches(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+) -> Result<Json<Vec<PaymentBatch>>, ErrorResponse> {
+    let batches = sqlx::query_as!(
+        PaymentBatch,
+        r#"SELECT id, status AS "status: PaymentBatchStatus", total, created_by, created_at, exported_at, paid_at,
+        reference, organization_id
+        FROM payment_batches WHERE organization_id = $1 ORDER BY id DESC"#,
+        organization.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(batches))
+}
+
+pub async fn list_payment_batch_claims(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(batch_id): extract::Path<i32>,
+) -> Result<Json<Vec<Claim>>, ErrorResponse> {
+    let claims = sqlx::query_as!(
+        Claim,
+        "SELECT * FROM claims WHERE payment_batch_id = $1 AND organization_id = $2 ORDER BY id ASC",
+        batch_id,
+        organization.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(claims))
+}
+
+pub async fn export_payment_batch(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(batch_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let batch = sqlx::query_as!(
+        PaymentBatch,
+        r#"UPDATE payment_batches SET status = $1, exported_at = CURRENT_TIMESTAMP
+        WHERE id = $2 AND status = $3 AND organization_id = $4
+        RETURNING id, status AS "status: PaymentBatchStatus", total, created_by, created_at, exported_at, paid_at,
+        reference, organization_id"#,
+        PaymentBatchStatus::Exported.to_string(),
+        batch_id,
+        PaymentBatchStatus::Open.to_string(),
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::CONFLICT,
+        "Only open payment batches can be exported"
+    ))?;
+
+    Ok(success_response!(batch))
+}
+
+pub async fn pay_payment_batch(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(batch_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::PaymentForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    let batch = sqlx::query_as!(
+        PaymentBatch,
+        r#"UPDATE payment_batches SET status = $1, paid_at = CURRENT_TIMESTAMP, reference = $2
+        WHERE id = $3 AND status = $4 AND organization_id = $5
+        RETURNING id, status AS "status: PaymentBatchStatus", total, created_by, created_at, exported_at, paid_at,
+        reference, organization_id"#,
+        PaymentBatchStatus::Paid.to_string(),
+        body.reference,
+        batch_id,
+        PaymentBatchStatus::Exported.to_string(),
+        organization.id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::CONFLICT,
+        "Only exported payment batches can be marked as paid"
+    ))?;
+
+    sqlx::query!(
+        "UPDATE claims SET status = $1, paid_at = $2, payment_reference = $3, updated_at = CURRENT_TIMESTAMP WHERE payment_batch_id = $4",
+        ClaimStatus::Paid.to_string(),
+        batch.paid_at,
+        body.reference,
+        batch.id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(batch))
+}
+
+pub async fn sepa_payment_batch(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(batch_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let batch = sqlx::query_as!(
+        PaymentBatch,
+        r#"SELECT id, status AS "status: PaymentBatchStatus", total, created_by, created_at, exported_at, paid_at,
+        reference, organization_id
+        FROM payment_batches WHERE id = $1 AND organization_id = $2"#,
+        batch_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Payment batch not found"
+    ))?;
+
+    if batch.status == PaymentBatchStatus::Paid {
+        return Err(error_response!(
+            StatusCode::CONFLICT,
+            "This payment batch was already paid"
+        ));
+    }
+
+    // One transfer per employee, covering all their claims in the batch
+    let payees = sqlx::query!(
+        r#"SELECT claims.user_id, bank_details.account_holder AS "account_holder?",
+        bank_details.iban AS "iban?", bank_details.bic,
+        SUM(COALESCE(claims.reimbursement, 0)) AS "amount!",
+        array_agg(claims.id ORDER BY claims.id) AS "claim_ids!"
+        FROM claims
+        LEFT JOIN bank_details ON bank_details.user_id = claims.user_id
+        WHERE claims.payment_batch_id = $1
+        GROUP BY claims.user_id, bank_details.user_id
+        ORDER BY claims.user_id ASC"#,
+        batch.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let mut transfers = Vec::new();
+    for payee in payees
+        .into_iter()
+        .filter(|payee| payee.amount > Decimal::from(0))
+    {
+        let (Some(account_holder), Some(iban)) = (payee.account_holder, payee.iban) else {
+            return Err(error_response!(
+                StatusCode::CONFLICT,
+                "Every employee in the batch needs bank details"
+            ));
+        };
+        transfers.push(sepa::Transfer {
+            user_id: payee.user_id,
+            account_holder,
+            iban,
+            bic: payee.bic,
+            amount: payee.amount,
+            claim_ids: payee.claim_ids,
+        });
+    }
+    if transfers.is_empty() {
+        return Err(error_response!(
+            StatusCode::CONFLICT,
+            "There is nothing to pay in this batch"
+        ));
+    }
+
+    let now = Utc::now().naive_utc();
+    let config = app_state.config.for_organization(&organization.settings);
+    let xml = sepa::credit_transfer_initiation(&config, batch.id, &transfers, now, now.date());
+
+    let mut response = Response::new(xml);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, "application/xml".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"payment-batch-{}.xml\"", batch.id)
+            .parse()
+            .unwrap(),
+    );
+    Ok(response)
+}
+
+pub async fn get_bank_details(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(user_id): extract::Path<i32>,
+) -> Result<Json<BankDetailsInfo>, ErrorResponse> {
+    if user.id != user_id && !permissions.has(Permission::BankDetailsManage) {
+        return Err(error_response!(
+            StatusCode::FORBIDDEN,
+            "You cannot access these bank details"
+        ));
+    }
+
+    let details = sqlx::query_as!(
+        BankDetails,
+        "SELECT * FROM bank_details WHERE user_id = $1
+        AND (user_id = $2 OR user_id IN (SELECT id FROM users WHERE organization_id = $3))",
+        user_id,
+        user.id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "No bank details found"
+    ))?;
+    Ok(Json(details.into()))
+}
+
+pub async fn set_bank_details(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(user_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::BankDetailsForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if user.id != user_id && !permissions.has(Permission::BankDetailsManage) {
+        return Err(error_response!(
+            StatusCode::FORBIDDEN,
+            "You cannot change these bank details"
+        ));
+    }
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+    if !is_password_correct(&user, &body.password) {
+        return Err(error_response!(
+            StatusCode::UNAUTHORIZED,
+            "Password is incorrect"
+        ));
+    }
+
+    let owner = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE id = $1 AND (id = $2 OR organization_id = $3)",
+        user_id,
+        user.id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(StatusCode::NOT_FOUND, "User not found"))?;
+
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    let details = sqlx::query_as!(
+        BankDetails,
+        "INSERT INTO bank_details ( user_id, account_holder, iban, bic ) VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET account_holder = EXCLUDED.account_holder,
+        iban = EXCLUDED.iban, bic = EXCLUDED.bic, updated_at = CURRENT_TIMESTAMP
+        RETURNING *",
+        owner.id,
+        body.account_holder.trim(),
+        bank::normalize_iban(&body.iban),
+        body.bic
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    // The owner is always told, so a change they did not make does not go unnoticed
+    let email_instance = email::Email::new(
+        owner,
+        app_state.config.frontend_origin.to_owned(),
+        app_state.config.clone(),
+    );
+    email_instance
+        .send_bank_details_changed()
+        .await
+        .map_err(|e| {
+            println!("{:?}", e);
+            error_response!(StatusCode::INTERNAL_SERVER_ERROR, "Could not send email")
+        })?;
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    Ok(success_response!(BankDetailsInfo::from(details)))
+}
+
+fn booking_amounts(
+    accounts: Vec<Option<String>>,
+    amounts: Vec<Decimal>,
+    taxes: Vec<Decimal>,
+    cost_centers: Vec<Option<String>>,
+    projects: Vec<Option<String>>,
+) -> Vec<accounting::BookingAmount> {
+    accounts
+        .into_iter()
+        .zip(amounts)
+        .zip(taxes)
+        .zip(cost_centers.into_iter().zip(projects))
+        .map(
+            |(((account, amount), tax), (cost_center, project))| accounting::BookingAmount {
+                account,
+                amount,
+                tax,
+                cost_center,
+                project,
+            },
+        )
+        .collect()
+}
+
+/// Books the accepted and paid claims of the export's organisation and period
+/// that were not exported yet
+async fn book_period(
+    conn: &mut PgConnection,
+    config: &Config,
+    export: &AccountingExport,
+) -> Result<(), sqlx::Error> {
+    let accruals = sqlx::query!(
+        r#"SELECT claims.id, claims.user_id, decided.decided_at::date AS "date!",
+        array_agg(categories.gl_account ORDER BY items.id) AS "accounts!: Vec<Option<String>>",
+        array_agg(items.reimbursement ORDER BY items.id) AS "amounts!",
+        array_agg(items.reimbursed_tax ORDER BY items.id) AS "taxes!",
+        array_agg(cost_centers.code ORDER BY items.id) AS "cost_centers!: Vec<Option<String>>",
+        array_agg(projects.code ORDER BY items.id) AS "projects!: Vec<Option<String>>"
+        FROM claims
+        JOIN (
+            SELECT claim_id, MAX(created_at) AS decided_at FROM claim_decisions
+            WHERE accepted GROUP BY claim_id
+        ) decided ON decided.claim_id = claims.id
+        JOIN items ON items.claim_id = claims.id
+        JOIN categories ON categories.id = items.category_id
+        LEFT JOIN charge_codes cost_centers
+            ON cost_centers.id = COALESCE(items.cost_center_id, claims.cost_center_id)
+        LEFT JOIN charge_codes projects
+            ON projects.id = COALESCE(items.project_id, claims.project_id)
+        WHERE claims.status = ANY($1) AND decided.decided_at::date BETWEEN $2 AND $3
+        AND claims.organization_id = $5
+        AND NOT EXISTS (
+            SELECT 1 FROM accounting_export_entries entries
+            WHERE entries.claim_id = claims.id AND entries.kind = $4
+        )
+        GROUP BY claims.id, decided.decided_at"#,
+        &[
+            ClaimStatus::Accepted.to_string(),
+            ClaimStatus::Paid.to_string()
+        ],
+        export.period_start,
+        export.period_end,
+        JournalEntryKind::Accrual.to_string(),
+        export.organization_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| accounting::Booking {
+        claim_id: row.id,
+        user_id: row.user_id,
+        kind: JournalEntryKind::Accrual,
+        date: row.date,
+        amounts: booking_amounts(
+            row.accounts,
+            row.amounts,
+            row.taxes,
+            row.cost_centers,
+            row.projects,
+        ),
+    });
+
+    let payments = sqlx::query!(
+        r#"SELECT claims.id, claims.user_id, claims.paid_at::date AS "date!",
+        array_agg(categories.gl_account ORDER BY items.id) AS "accounts!: Vec<Option<String>>",
+        array_agg(items.reimbursement ORDER BY items.id) AS "amounts!",
+        array_agg(items.reimbursed_tax ORDER BY items.id) AS "taxes!",
+        array_agg(cost_centers.code ORDER BY items.id) AS "cost_centers!: Vec<Option<String>>",
+        array_agg(projects.code ORDER BY items.id) AS "projects!: Vec<Option<String>>"
+        FROM claims
+        JOIN items ON items.claim_id = claims.id
+        JOIN categories ON categories.id = items.category_id
+        LEFT JOIN charge_codes cost_centers
+            ON cost_centers.id = COALESCE(items.cost_center_id, claims.cost_center_id)
+        LEFT JOIN charge_codes projects
+            ON projects.id = COALESCE(items.project_id, claims.project_id)
+        WHERE claims.status = $1 AND claims.paid_at::date BETWEEN $2 AND $3
+        AND claims.organization_id = $5
+        AND NOT EXISTS (
+            SELECT 1 FROM accounting_export_entries entries
+            WHERE entries.claim_id = claims.id AND entries.kind = $4
+        )
+        GROUP BY claims.id"#,
+        ClaimStatus::Paid.to_string(),
+        export.period_start,
+        export.period_end,
+        JournalEntryKind::Payment.to_string(),
+        export.organization_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| accounting::Booking {
+        claim_id: row.id,
+        user_id: row.user_id,
+        kind: JournalEntryKind::Payment,
+        date: row.date,
+        amounts: booking_amounts(
+            row.accounts,
+            row.amounts,
+            row.taxes,
+            row.cost_centers,
+            row.projects,
+        ),
+    });
+
+    for booking in accruals.chain(payments) {
+        let entry = accounting::journal_entry(config, &booking);
+        sqlx::query!(
+            "INSERT INTO accounting_export_entries ( claim_id, kind, export_id, entry_date, description )
+            VALUES ($1, $2, $3, $4, $5)",
+            booking.claim_id,
+            booking.kind.to_string(),
+            export.id,
+            entry.date,
+            entry.description
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        for (position, line) in entry.lines.iter().enumerate() {
+            sqlx::query!(
+                "INSERT INTO accounting_export_lines ( claim_id, kind, position, account, debit, credit, cost_center, project )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                booking.claim_id,
+                booking.kind.to_string(),
+                position as i32,
+                line.account,
+                line.debit,
+                line.credit,
+                line.cost_center,
+                line.project
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Journal entries recorded for the export, in booking order
+async fn recorded_entries(
+    conn: &mut PgConnection,
+    export_id: i32,
+) -> Result<Vec<accounting::JournalEntry>, sqlx::Error> {
+    let entries = sqlx::query!(
+        r#"SELECT claim_id, kind AS "kind: JournalEntryKind", entry_date, description
+        FROM accounting_export_entries WHERE export_id = $1
+        ORDER BY entry_date ASC, claim_id ASC, kind ASC"#,
+        export_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut lines: HashMap<(i32, JournalEntryKind), Vec<accounting::JournalLine>> = HashMap::new();
+    for line in sqlx::query!(
+        r#"SELECT lines.claim_id, lines.kind AS "kind: JournalEntryKind", lines.account, lines.debit, lines.credit,
+        lines.cost_center, lines.project
+        FROM accounting_export_lines lines
+        JOIN accounting_export_entries entries USING (claim_id, kind)
+        WHERE entries.export_id = $1
+        ORDER BY lines.claim_id ASC, lines.kind ASC, lines.position ASC"#,
+        export_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        lines
+            .entry((line.claim_id, line.kind))
+            .or_default()
+            // Zero loses its scale in the database
+            .push(accounting::JournalLine {
+                account: line.account,
+                debit: accounting::cents(line.debit),
+                credit: accounting::cents(line.credit),
+                cost_center: line.cost_center,
+                project: line.project,
+            });
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| accounting::JournalEntry {
+            id: accounting::entry_id(entry.claim_id, entry.kind),
+            date: entry.entry_date,
+            claim_id: entry.claim_id,
+            description: entry.description,
+            lines: lines
+                .remove(&(entry.claim_id, entry.kind))
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+pub async fn export_accounting(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::AccountingExportForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+    // Claims can still be accepted or paid during an open period
+    if body.to >= Utc::now().date_naive() {
+        return Err(bad_request!("Only past periods can be exported"));
+    }
+
+    let mut transaction = app_state.pool.begin().await.map_err(|_| DATABASE_ERROR)?;
+
+    // Concurrent exports would book the same claims twice
+    sqlx::query!("LOCK TABLE accounting_exports IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+
+    // Running the export again returns what was recorded the first time
+    let export = match sqlx::query_as!(
+        AccountingExport,
+        "SELECT * FROM accounting_exports
+        WHERE period_start = $1 AND period_end = $2 AND organization_id = $3",
+        body.from,
+        body.to,
+        organization.id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    {
+        Some(export) => export,
+        None => {
+            let export = sqlx::query_as!(
+                AccountingExport,
+                "INSERT INTO accounting_exports ( period_start, period_end, created_by, organization_id )
+                VALUES ($1, $2, $3, $4) RETURNING *",
+                body.from,
+                body.to,
+                user.id,
+                organization.id
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(|_| DATABASE_ERROR)?;
+            let config = app_state.config.for_organization(&organization.settings);
+            book_period(&mut transaction, &config, &export)
+                .await
+                .map_err(|_| DATABASE_ERROR)?;
+            export
+        }
+    };
+
+    let entries = recorded_entries(&mut transaction, export.id)
+        .await
+        .map_err(|_| DATABASE_ERROR)?;
+
+    transaction.commit().await.map_err(|_| DATABASE_ERROR)?;
+
+    let (content_type, extension, body) = match body.format {
+        forms::AccountingFormat::Csv => (
+            "text/csv",
+            "csv",
+            accounting::to_csv(&entries).map_err(|_| {
+                error_response!(StatusCode::INTERNAL_SERVER_ERROR, "Could not write CSV")
+            })?,
+        ),
+        forms::AccountingFormat::Json => (
+            "application/json",
+            "json",
+            json!({
+                "exportId": export.id,
+                "periodStart": export.period_start,
+                "periodEnd": export.period_end,
+                "entries": entries
+            })
+            .to_string(),
+        ),
+    };
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!(
+            "attachment; filename=\"journal-{}-{}.{}\"",
+            export.period_start, export.period_end, extension
+        )
+        .parse()
+        .unwrap(),
+    );
+    Ok(response)
+}
+
+pub async fn list_accounting_exports(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+) -> Result<Json<Vec<AccountingExport>>, ErrorResponse> {
+    let exports = sqlx::query_as!(
+        AccountingExport,
+        "SELECT * FROM accounting_exports WHERE organization_id = $1
+        ORDER BY period_start DESC, id DESC",
+        organization.id
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+    Ok(Json(exports))
+}
+
+#[derive(Debug, Clone, Default)]
+struct BudgetUsage {
+    approved: Decimal,
+    pending: Decimal,
+    /// Share of the given claim, which is left out of the other amounts
+    claim: Decimal,
+}
+
+/// Reimbursements charged to the budgets, items count against a budget when their
+/// cost center, category and expense date match it
+async fn budget_usage(
+    conn: &mut PgConnection,
+    budget_ids: &[i32],
+    claim_id: Option<i32>,
+) -> Result<HashMap<i32, BudgetUsage>, ErrorResponse> {
+    Ok(sqlx::query!(
+        r#"SELECT budgets.id,
+        COALESCE(SUM(items.reimbursement) FILTER (
+            WHERE claims.status = ANY($2) AND claims.id IS DISTINCT FROM $4
+        ), 0) AS "approved!",
+        COALESCE(SUM(items.reimbursement) FILTER (
+            WHERE claims.status = $3 AND claims.id IS DISTINCT FROM $4
+        ), 0) AS "pending!",
+        COALESCE(SUM(items.reimbursement) FILTER (WHERE claims.id = $4), 0) AS "claim!"
+        FROM budgets
+        LEFT JOIN (items JOIN claims ON claims.id = items.claim_id)
+            ON claims.organization_id = budgets.organization_id
+            AND COALESCE(items.cost_center_id, claims.cost_center_id) = budgets.cost_center_id
+            AND items.expense_date BETWEEN budgets.period_start AND budgets.period_end
+            AND (budgets.category_id IS NULL OR items.category_id = budgets.category_id)
+        WHERE budgets.id = ANY($1)
+        GROUP BY budgets.id"#,
+        budget_ids,
+        &[
+            ClaimStatus::Accepted.to_string(),
+            ClaimStatus::Paid.to_string()
+        ],
+        ClaimStatus::Pending.to_string(),
+        claim_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            BudgetUsage {
+                approved: row.approved,
+                pending: row.pending,
+                claim: row.claim,
+            },
+        )
+    })
+    .collect())
+}
+
+/// Budgets the items of the claim are charged to, locked until the decision is recorded
+async fn claim_budget_charges(
+    conn: &mut PgConnection,
+    claim_id: i32,
+) -> Result<Vec<budgets::ClaimCharge>, ErrorResponse> {
+    let charged = sqlx::query_as!(
+        Budget,
+        r#"SELECT id, cost_center_id, category_id, period_start, period_end, amount,
+        policy AS "policy: BudgetPolicy", created_by, created_at, organization_id
+        FROM budgets WHERE EXISTS (
+            SELECT 1 FROM items JOIN claims ON claims.id = items.claim_id
+            WHERE claims.id = $1
+            AND COALESCE(items.cost_center_id, claims.cost_center_id) = budgets.cost_center_id
+            AND items.expense_date BETWEEN budgets.period_start AND budgets.period_end
+            AND (budgets.category_id IS NULL OR items.category_id = budgets.category_id)
+        )
+        ORDER BY id ASC
+        FOR UPDATE"#,
+        claim_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let ids: Vec<i32> = charged.iter().map(|budget| budget.id).collect();
+    let mut usage = budget_usage(conn, &ids, Some(claim_id)).await?;
+    Ok(charged
+        .into_iter()
+        .map(|budget| {
+            let usage = usage.remove(&budget.id).unwrap_or_default();
+            budgets::ClaimCharge {
+                budget,
+                approved: usage.approved,
+                amount: usage.claim,
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetsQuery {
+    cost_center_id: Option<i32>,
+    /// Only budgets whose period contains this date
+    date: Option<NaiveDate>,
+}
+
+pub async fn list_budgets(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<BudgetsQuery>,
+) -> Result<Json<Vec<budgets::BudgetStatus>>, ErrorResponse> {
+    let mut conn = app_state.pool.acquire().await.map_err(|_| DATABASE_ERROR)?;
+    let list = sqlx::query_as!(
+        Budget,
+        r#"SELECT id, cost_center_id, category_id, period_start, period_end, amount,
+        policy AS "policy: BudgetPolicy", created_by, created_at, organization_id
+        FROM budgets
+        WHERE organization_id = $1 AND ($2::INTEGER IS NULL OR cost_center_id = $2)
+        AND ($3::DATE IS NULL OR $3 BETWEEN period_start AND period_end)
+        ORDER BY period_start DESC, cost_center_id ASC, id ASC"#,
+        organization.id,
+        query.cost_center_id,
+        query.date
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let ids: Vec<i32> = list.iter().map(|budget| budget.id).collect();
+    let mut usage = budget_usage(&mut conn, &ids, None).await?;
+    Ok(Json(
+        list.into_iter()
+            .map(|budget| {
+                let usage = usage.remove(&budget.id).unwrap_or_default();
+                budgets::BudgetStatus::new(budget, usage.approved, usage.pending)
+            })
+            .collect(),
+    ))
+}
+
+pub async fn create_budget(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Json(body): extract::Json<forms::BudgetForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let mut conn = app_state.pool.acquire().await.map_err(|_| DATABASE_ERROR)?;
+    check_charge_codes(
+        &mut conn,
+        organization.id,
+        &[(body.cost_center_id, ChargeCodeKind::CostCenter)],
+    )
+    .await?;
+
+    // The category has to belong to the organisation as well
+    let budget = sqlx::query_as!(
+        Budget,
+        r#"INSERT INTO budgets ( cost_center_id, category_id, period_start, period_end, amount, policy, created_by, organization_id )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8
+        WHERE $2::INTEGER IS NULL OR EXISTS (SELECT 1 FROM categories WHERE id = $2 AND organization_id = $8)
+        RETURNING id, cost_center_id, category_id, period_start, period_end, amount,
+        policy AS "policy: BudgetPolicy", created_by, created_at, organization_id"#,
+        body.cost_center_id,
+        body.category_id,
+        body.period_start,
+        body.period_end,
+        body.amount,
+        body.policy.unwrap_or(BudgetPolicy::Warn).to_string(),
+        user.id,
+        organization.id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(bad_request!("Category with this id does not exist"))?;
+
+    Ok(success_response!(budget))
+}
+
+pub async fn update_budget(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(budget_id): extract::Path<i32>,
+    extract::Json(body): extract::Json<forms::BudgetUpdateForm>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    body.validate()
+        .map_err(|_| bad_request!("Form failed validation"))?;
+
+    let budget = sqlx::query_as!(
+        Budget,
+        r#"UPDATE budgets SET amount = COALESCE($1, amount), policy = COALESCE($2, policy)
+        WHERE id = $3 AND organization_id = $4
+        RETURNING id, cost_center_id, category_id, period_start, period_end, amount,
+        policy AS "policy: BudgetPolicy", created_by, created_at, organization_id"#,
+        body.amount,
+        body.policy.map(|policy| policy.to_string()),
+        budget_id,
+        organization.id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .ok_or(error_response!(
+        StatusCode::NOT_FOUND,
+        "Budget with this id does not exist"
+    ))?;
+
+    Ok(success_response!(budget))
+}
+
+pub async fn delete_budget(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Path(budget_id): extract::Path<i32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let deleted = sqlx::query!(
+        "DELETE FROM budgets WHERE id = $1 AND organization_id = $2",
+        budget_id,
+        organization.id
+    )
+    .execute(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(error_response!(
+            StatusCode::NOT_FOUND,
+            "Budget with this id does not exist"
+        ));
+    }
+
+    Ok(success_response!())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportingQuery {
+    from: NaiveDate,
+    to: NaiveDate,
+    /// Comma separated dimensions, e.g. `user,month`
+    group_by: Option<String>,
+    /// Only claims with this status. Without it rejected claims are left out unless the
+    /// report is grouped by status.
+    status: Option<ClaimStatus>,
+}
+
+/// Totals of cost, reimbursement and tax of the items expensed in the date range, rejected
+/// claims only count when asked for. Callers who cannot view all claims only see the claims of the people reporting to them,
+/// directly or not.
+pub async fn report_totals(
+    extract::State(app_state): extract::State<Arc<AppState>>,
+    extract::Extension(user): extract::Extension<User>,
+    extract::Extension(permissions): extract::Extension<Permissions>,
+    extract::Extension(organization): extract::Extension<Organization>,
+    extract::Query(query): extract::Query<ReportingQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if query.from > query.to {
+        return Err(bad_request!("Range cannot end before it starts"));
+    }
+    let grouping = reporting::parse_grouping(query.group_by.as_deref().unwrap_or(""))
+        .map_err(|e| bad_request!(e))?;
+
+    let rows = sqlx::query!(
+        r#"WITH RECURSIVE reports AS (
+            SELECT id FROM users WHERE manager_id = $1
+            UNION
+            SELECT users.id FROM users JOIN reports ON users.manager_id = reports.id
+        ),
+        grouped AS (
+            SELECT
+                CASE WHEN $2 THEN claims.user_id END AS user_id,
+                CASE WHEN $2 THEN users.username END AS username,
+                CASE WHEN $3 THEN items.category_id END AS category_id,
+                CASE WHEN $3 THEN categories.name END AS category,
+                CASE WHEN $4 THEN claims.status END AS status,
+                date_trunc($5::TEXT, items.expense_date)::date AS period,
+                claims.id AS claim_id, items.cost, items.reimbursement, items.tax_amount
+            FROM items
+            JOIN claims ON claims.id = items.claim_id
+            JOIN users ON users.id = claims.user_id
+            JOIN categories ON categories.id = items.category_id
+            WHERE items.expense_date BETWEEN $6 AND $7 AND claims.organization_id = $9
+            AND ($8 OR claims.user_id IN (SELECT id FROM reports))
+            AND (claims.status = $11 OR ($11 IS NULL AND ($4 OR claims.status <> 'Rejected')))
+        )
+        SELECT user_id AS "user_id?", username AS "username?", category_id AS "category_id?",
+        category AS "category?", status AS "status?", period AS "period?",
+        GROUPING(user_id, username, category_id, category, status, period) > 0 AS "total!",
+        COUNT(DISTINCT claim_id) AS "claims!", COUNT(*) AS "items!",
+        COALESCE(SUM(cost), 0) AS "cost!",
+        COALESCE(SUM(reimbursement), 0) AS "reimbursement!",
+        COALESCE(SUM(tax_amount), 0) AS "tax!"
+        FROM grouped
+        GROUP BY GROUPING SETS ((user_id, username, category_id, category, status, period), ())
+        HAVING $10 OR GROUPING(user_id, username, category_id, category, status, period) > 0
+        ORDER BY period ASC, username ASC, category ASC, status ASC"#,
+        user.id,
+        grouping.user,
+        grouping.category,
+        grouping.status,
+        grouping.period.map(|period| period.unit()),
+        query.from,
+        query.to,
+        permissions.has(Permission::ClaimsViewAll),
+        organization.id,
+        // Without dimensions the grouped set would repeat the grand total
+        !grouping.is_empty(),
+        query.status.map(|status| status.to_string())
+    )
+    .fetch_all(&app_state.pool)
+    .await
+    .map_err(|_| DATABASE_ERROR)?;
+
+    let mut total = reporting::Totals::default();
+    let mut groups = Vec::with_capacity(rows.len());
+    for row in rows {
+        let totals = reporting::Totals {
+            claims: row.claims,
+            items: row.items,
+            cost: row.cost,
+            reimbursement: row.reimbursement,
+            tax: row.tax,
+        };
+        if row.total {
+            total = totals;
+            continue;
+        }
+        groups.push(reporting::ReportRow {
+            user_id: row.user_id,
+            username: row.username,
+            category_id: row.category_id,
+            category: row.category,
+            status: row.status.map(ClaimStatus::from),
+            period: grouping
+                .period
+                .zip(row.period)
+                .map(|(period, start)| period.label(start)),
+            totals,
+        });
+    }
+
+    Ok(success_response!(json!({
+        "from": query.from,
+        "to": query.to,
+        "groups": groups,
+        "total": total
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    async fn batch_status(pool: &sqlx::PgPool, claim_id: i32) -> (Option<i32>, String) {
+        let claim = sqlx::query!(
+            "SELECT payment_batch_id, status FROM claims WHERE id = $1",
+            claim_id
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        (claim.payment_batch_id, claim.status)
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_payment_batch_lifecycle(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let finance = testing::user(&mut conn, organization.id, "finance", "Finance", None).await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let first = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Accepted,
+            Decimal::from(10),
+        )
+        .await;
+        let second = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Accepted,
+            Decimal::from(5),
+        )
+        .await;
+
+        create_payment_batch(
+            extract::State(app_state.clone()),
+            extract::Extension(finance.clone()),
+            extract::Extension(organization.clone()),
+            extract::Json(forms::PaymentBatchForm {
+                claim_ids: vec![first.id, second.id, first.id],
+            }),
+        )
+        .await
+        .unwrap();
+        let (batch_id, status) = batch_status(&pool, first.id).await;
+        let batch_id = batch_id.unwrap();
+        assert_eq!(status, "Accepted");
+        assert_eq!(batch_status(&pool, second.id).await.0, Some(batch_id));
+        let total =
+            sqlx::query_scalar!("SELECT total FROM payment_batches WHERE id = $1", batch_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(total, Decimal::from(15));
+
+        let pay = |reference: &str| {
+            pay_payment_batch(
+                extract::State(app_state.clone()),
+                extract::Extension(organization.clone()),
+                extract::Path(batch_id),
+                extract::Json(forms::PaymentForm {
+                    reference: reference.to_string(),
+                }),
+            )
+        };
+        let (status, _) = pay("TRANSFER-1").await.err().unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        export_payment_batch(
+            extract::State(app_state.clone()),
+            extract::Extension(organization.clone()),
+            extract::Path(batch_id),
+        )
+        .await
+        .unwrap();
+        let (status, _) = export_payment_batch(
+            extract::State(app_state.clone()),
+            extract::Extension(organization.clone()),
+            extract::Path(batch_id),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        pay("TRANSFER-1").await.unwrap();
+        assert_eq!(
+            batch_status(&pool, first.id).await,
+            (Some(batch_id), "Paid".to_string())
+        );
+        let (status, _) = pay("TRANSFER-2").await.err().unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_create_claim_skips_archived_default_cost_center(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let category = testing::category(&mut conn, organization.id, "Travel").await;
+        let cost_center = sqlx::query_scalar!(
+            "INSERT INTO charge_codes ( kind, code, name, archived, organization_id )
+            VALUES ('CostCenter', 'CC-1', 'Old', TRUE, $1) RETURNING id",
+            organization.id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        let employee = User {
+            default_cost_center_id: Some(cost_center),
+            ..employee
+        };
+
+        let form: forms::ClaimForm = serde_json::from_value(json!({
+            "userId": employee.id,
+            "items": [{ "categoryId": category, "cost": "20" }]
+        }))
+        .unwrap();
+        create_claim(
+            extract::State(app_state),
+            extract::Extension(employee.clone()),
+            extract::Extension(organization),
+            extract::Json(form),
+        )
+        .await
+        .unwrap();
+
+        let claim_cost_center = sqlx::query_scalar!(
+            "SELECT cost_center_id FROM claims WHERE user_id = $1",
+            employee.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(claim_cost_center, None);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_report_totals_without_grouping(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let admin = testing::user(&mut conn, organization.id, "admin", "Admin", None).await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let category = testing::category(&mut conn, organization.id, "Travel").await;
+        let claim = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Pending,
+            Decimal::from(20),
+        )
+        .await;
+        sqlx::query!(
+            "INSERT INTO items ( claim_id, category_id, cost, net_cost, reimbursement, expense_date )
+            VALUES ($1, $2, 20, 20, 20, '2023-10-02')",
+            claim.id,
+            category
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        // Rejected claims are only counted when asked for or grouped by status
+        let rejected = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Rejected,
+            Decimal::from(50),
+        )
+        .await;
+        sqlx::query!(
+            "INSERT INTO items ( claim_id, category_id, cost, net_cost, reimbursement, expense_date )
+            VALUES ($1, $2, 50, 50, 50, '2023-10-03')",
+            rejected.id,
+            category
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        let permissions = permissions::for_role(&pool, organization.id, "Admin")
+            .await
+            .unwrap();
+
+        let report = |group_by: Option<&str>, status: Option<ClaimStatus>| {
+            report_totals(
+                extract::State(app_state.clone()),
+                extract::Extension(admin.clone()),
+                extract::Extension(permissions.clone()),
+                extract::Extension(organization.clone()),
+                extract::Query(ReportingQuery {
+                    from: NaiveDate::from_ymd_opt(2023, 10, 1).unwrap(),
+                    to: NaiveDate::from_ymd_opt(2023, 10, 31).unwrap(),
+                    group_by: group_by.map(str::to_string),
+                    status,
+                }),
+            )
+        };
+        let body = testing::json_body(report(None, None).await.unwrap()).await;
+        assert_eq!(body["data"]["groups"], json!([]));
+        assert_eq!(body["data"]["total"]["items"], json!(1));
+        assert_eq!(body["data"]["total"]["reimbursement"], json!("20"));
+
+        let body = testing::json_body(report(Some("user"), None).await.unwrap()).await;
+        assert_eq!(body["data"]["groups"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"]["groups"][0]["username"], json!("employee"));
+        assert_eq!(body["data"]["total"]["items"], json!(1));
+
+        let body =
+            testing::json_body(report(None, Some(ClaimStatus::Rejected)).await.unwrap()).await;
+        assert_eq!(body["data"]["total"]["items"], json!(1));
+        assert_eq!(body["data"]["total"]["reimbursement"], json!("50"));
+
+        let body = testing::json_body(report(Some("status"), None).await.unwrap()).await;
+        assert_eq!(body["data"]["groups"].as_array().unwrap().len(), 2);
+        assert_eq!(body["data"]["total"]["items"], json!(2));
+    }
+
+    async fn search(
+        app_state: &Arc<AppState>,
+        user: &User,
+        organization: &Organization,
+        query: serde_json::Value,
+    ) -> (Vec<i32>, i64, Option<String>) {
+        let permissions = permissions::for_role(
+            &app_state.pool,
+            user.organization_id,
+            &user.role.to_string(),
+        )
+        .await
+        .unwrap();
+        let Json(body) = search_claims(
+            extract::State(app_state.clone()),
+            extract::Extension(user.clone()),
+            extract::Extension(permissions),
+            extract::Extension(organization.clone()),
+            extract::Query(serde_json::from_value(query).unwrap()),
+        )
+        .await
+        .unwrap();
+        let ids = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|claim| claim["id"].as_i64().unwrap() as i32)
+            .collect();
+        let next_cursor = body["nextCursor"].as_str().map(str::to_string);
+        (ids, body["total"].as_i64().unwrap(), next_cursor)
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_search_claims_visibility(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let other = testing::organization(&mut conn, "second").await;
+        let admin = testing::user(&mut conn, organization.id, "admin", "Admin", None).await;
+        let manager = testing::user(&mut conn, organization.id, "manager", "Manager", None).await;
+        let lead = testing::user(
+            &mut conn,
+            organization.id,
+            "lead",
+            "Manager",
+            Some(manager.id),
+        )
+        .await;
+        let employee = testing::user(
+            &mut conn,
+            organization.id,
+            "employee",
+            "User",
+            Some(lead.id),
+        )
+        .await;
+        let loner = testing::user(&mut conn, organization.id, "loner", "User", None).await;
+        let stranger = testing::user(&mut conn, other.id, "stranger", "User", None).await;
+        let mut claims = vec![];
+        for user in [&manager, &lead, &employee, &loner, &stranger] {
+            claims.push(
+                testing::claim(&mut conn, user, ClaimStatus::Pending, Decimal::from(10))
+                    .await
+                    .id,
+            );
+        }
+        let [manager_claim, lead_claim, employee_claim, loner_claim, _] = claims[..] else {
+            unreachable!()
+        };
+
+        let visible = |user: &User, query: serde_json::Value| {
+            let (app_state, user, organization) =
+                (app_state.clone(), user.clone(), organization.clone());
+            async move { search(&app_state, &user, &organization, query).await.0 }
+        };
+        assert_eq!(visible(&employee, json!({})).await, vec![employee_claim]);
+        assert_eq!(
+            visible(&manager, json!({ "sort": "id", "order": "asc" })).await,
+            vec![manager_claim, lead_claim, employee_claim]
+        );
+        assert_eq!(
+            visible(&admin, json!({ "sort": "id", "order": "asc" })).await,
+            vec![manager_claim, lead_claim, employee_claim, loner_claim]
+        );
+        assert_eq!(
+            visible(&employee, json!({ "user_id": lead.id })).await,
+            Vec::<i32>::new()
+        );
+
+        assert_eq!(
+            visible(&manager, json!({ "awaiting_approval": true })).await,
+            vec![lead_claim]
+        );
+        assert_eq!(
+            visible(
+                &manager,
+                json!({ "awaiting_approval": true, "indirect": true, "sort": "id", "order": "asc" })
+            )
+            .await,
+            vec![lead_claim, employee_claim]
+        );
+        assert_eq!(
+            visible(
+                &admin,
+                json!({ "awaiting_approval": true, "sort": "id", "order": "asc" })
+            )
+            .await,
+            vec![manager_claim, lead_claim, employee_claim, loner_claim]
+        );
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_search_claims_pagination(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let admin = testing::user(&mut conn, organization.id, "admin", "Admin", None).await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let mut claims = vec![];
+        for reimbursement in [30, 10, 20, 10, 40] {
+            let claim = testing::claim(
+                &mut conn,
+                &employee,
+                ClaimStatus::Pending,
+                Decimal::from(reimbursement),
+            )
+            .await;
+            claims.push((reimbursement, claim.id));
+        }
+        claims.sort();
+
+        let mut seen = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let (ids, total, next_cursor) = search(
+                &app_state,
+                &admin,
+                &organization,
+                json!({ "sort": "total_cost", "order": "asc", "limit": 2, "cursor": cursor }),
+            )
+            .await;
+            assert_eq!(total, 5);
+            assert!(ids.len() <= 2);
+            seen.extend(ids);
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, claims.iter().map(|(_, id)| *id).collect::<Vec<_>>());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_payment_batch_rejects_unpayable_claims(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let other = testing::organization(&mut conn, "second").await;
+        let finance = testing::user(&mut conn, organization.id, "finance", "Finance", None).await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let stranger = testing::user(&mut conn, other.id, "stranger", "User", None).await;
+        let accepted = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Accepted,
+            Decimal::from(10),
+        )
+        .await;
+        let pending = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Pending,
+            Decimal::from(10),
+        )
+        .await;
+        let foreign = testing::claim(
+            &mut conn,
+            &stranger,
+            ClaimStatus::Accepted,
+            Decimal::from(10),
+        )
+        .await;
+
+        let create = |claim_ids: Vec<i32>| {
+            create_payment_batch(
+                extract::State(app_state.clone()),
+                extract::Extension(finance.clone()),
+                extract::Extension(organization.clone()),
+                extract::Json(forms::PaymentBatchForm { claim_ids }),
+            )
+        };
+        for claim_ids in [vec![accepted.id, pending.id], vec![accepted.id, foreign.id]] {
+            let (status, _) = create(claim_ids).await.err().unwrap();
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+        assert_eq!(batch_status(&pool, accepted.id).await.0, None);
+
+        create(vec![accepted.id]).await.unwrap();
+        let (status, _) = create(vec![accepted.id]).await.err().unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_get_claim_visibility(pool: sqlx::PgPool) {
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let other = testing::organization(&mut conn, "second").await;
+        let manager = testing::user(&mut conn, organization.id, "manager", "Manager", None).await;
+        let lead = testing::user(
+            &mut conn,
+            organization.id,
+            "lead",
+            "Manager",
+            Some(manager.id),
+        )
+        .await;
+        let employee = testing::user(
+            &mut conn,
+            organization.id,
+            "employee",
+            "User",
+            Some(lead.id),
+        )
+        .await;
+        let finance = testing::user(&mut conn, organization.id, "finance", "Finance", None).await;
+        let outsider = testing::user(&mut conn, organization.id, "outsider", "Manager", None).await;
+        let stranger = testing::user(&mut conn, other.id, "stranger", "Admin", None).await;
+        let claim = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Pending,
+            Decimal::from(20),
+        )
+        .await;
+        let category = testing::category(&mut conn, organization.id, "Travel").await;
+        sqlx::query!(
+            "INSERT INTO items ( claim_id, category_id, cost, net_cost, reimbursement, expense_date )
+            VALUES ($1, $2, 20, 20, 20, '2023-10-02')",
+            claim.id,
+            category
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+        let get = |user: &User, organization: &Organization| {
+            let (app_state, user, organization) =
+                (app_state.clone(), user.clone(), organization.clone());
+            async move {
+                let permissions = permissions::for_role(
+                    &app_state.pool,
+                    user.organization_id,
+                    &user.role.to_string(),
+                )
+                .await
+                .unwrap();
+                get_claim(
+                    extract::State(app_state),
+                    extract::Extension(user),
+                    extract::Extension(permissions),
+                    extract::Extension(organization),
+                    extract::Path(claim.id),
+                )
+                .await
+                .map(|Json(details)| details)
+            }
+        };
+        let details = get(&employee, &organization).await.unwrap();
+        assert_eq!(details.items.len(), 1);
+        assert_eq!(details.items[0].category.as_ref().unwrap().id, category);
+        for reader in [&lead, &manager, &finance] {
+            assert!(get(reader, &organization).await.is_ok());
+        }
+        let (status, _) = get(&outsider, &organization).await.err().unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get(&stranger, &other).await.err().unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Deciding on a claim keeps it readable once it is no longer pending
+        sqlx::query!(
+            "INSERT INTO claim_decisions ( claim_id, approver_id, accepted ) VALUES ($1, $2, TRUE)",
+            claim.id,
+            outsider.id
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "UPDATE claims SET status = 'Accepted' WHERE id = $1",
+            claim.id
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        assert!(get(&outsider, &organization).await.is_ok());
+        let (status, _) = get(&stranger, &other).await.err().unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_claim_report_receipts(pool: sqlx::PgPool) {
+        use axum::body::HttpBody;
+
+        let app_state = testing::app_state(pool.clone());
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let claim = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Pending,
+            Decimal::from(20),
+        )
+        .await;
+        let category = testing::category(&mut conn, organization.id, "Travel").await;
+
+        let upload = |content_type: &'static str, data: &'static [u8]| {
+            let (app_state, employee, organization) =
+                (app_state.clone(), employee.clone(), organization.clone());
+            async move {
+                let mut headers = HeaderMap::new();
+                headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+                upload_receipt(
+                    extract::State(app_state),
+                    extract::Extension(employee),
+                    extract::Extension(organization),
+                    headers,
+                    axum::body::Bytes::from_static(data),
+                )
+                .await
+            }
+        };
+        // Frame header of a 640x480 color JPEG, enough for the report to embed it
+        let jpeg: &[u8] = &[
+            0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01, 0xE0, 0x02, 0x80, 0x03, 0x01, 0x22,
+            0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01, 0xFF, 0xD9,
+        ];
+        let body = testing::json_body(upload("image/jpeg", jpeg).await.unwrap()).await;
+        let hash = body["data"]["hash"].as_str().unwrap().to_string();
+        assert_eq!(hash.len(), 64);
+        let body = testing::json_body(upload("image/jpeg", jpeg).await.unwrap()).await;
+        assert_eq!(body["data"]["hash"], json!(hash));
+        let (status, _) = upload("text/plain", b"receipt").await.err().unwrap();
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        sqlx::query!(
+            "INSERT INTO items ( claim_id, category_id, cost, net_cost, reimbursement, expense_date, receipt_hash )
+            VALUES ($1, $2, 20, 20, 20, '2023-10-02', $3)",
+            claim.id,
+            category,
+            hash
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        let permissions = permissions::for_role(&pool, organization.id, "User")
+            .await
+            .unwrap();
+        let response = claim_report(
+            extract::State(app_state.clone()),
+            extract::Extension(employee.clone()),
+            extract::Extension(permissions),
+            extract::Extension(organization.clone()),
+            extract::Path(claim.id),
+        )
+        .await
+        .unwrap();
+        let mut body = response.into_body();
+        let mut pdf = vec![];
+        while let Some(chunk) = body.data().await {
+            pdf.extend_from_slice(&chunk.unwrap());
+        }
+        assert!(pdf.starts_with(b"%PDF"));
+        assert!(pdf.windows(9).any(|window| window == b"DCTDecode"));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_import_categories_conflict(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+
+        // A category created while the import runs is only noticed by the insert
+        let mut other = pool.begin().await.unwrap();
+        sqlx::query!(
+            "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, organization_id )
+            VALUES ('Travel', 100, 1000, $1)",
+            organization.id
+        )
+        .execute(&mut *other)
+        .await
+        .unwrap();
+        let import = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                import::import_categories(
+                    &pool,
+                    organization.id,
+                    "name,percentage,cap\nMeals,100,50\nTravel,80,500\n",
+                    false,
+                )
+                .await
+            }
+        });
+        while !sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM pg_stat_activity WHERE datname = current_database() AND wait_event_type = 'Lock') AS "waiting!""#
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        other.commit().await.unwrap();
+
+        let report = import.await.unwrap().unwrap();
+        assert!(!report.applied);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].line, 3);
+        let names = sqlx::query_scalar!(
+            "SELECT name FROM categories WHERE organization_id = $1",
+            organization.id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        assert_eq!(names, vec!["Travel".to_string()]);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_custom_roles_per_organization(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let first = testing::organization(&mut conn, "first").await;
+        let second = testing::organization(&mut conn, "second").await;
+        let app_state = testing::app_state(pool.clone());
+        let admin = permissions::for_role(&pool, first.id, "Admin")
+            .await
+            .unwrap();
+        let create = |organization: &Organization, name: &str, permissions: Vec<Permission>| {
+            create_role(
+                extract::State(app_state.clone()),
+                extract::Extension(admin.clone()),
+                extract::Extension(organization.clone()),
+                extract::Json(forms::CustomRoleForm {
+                    name: name.to_string(),
+                    permissions,
+                }),
+            )
+        };
+
+        // Both organisations can use the same name without seeing each other's role
+        assert!(create(&first, "Auditor", vec![Permission::ReportsView])
+            .await
+            .is_ok());
+        assert!(create(&second, "Auditor", vec![Permission::ClaimsViewAll])
+            .await
+            .is_ok());
+        let result = create(&first, "Auditor", vec![]).await;
+        assert_eq!(result.err().unwrap().0, StatusCode::CONFLICT);
+        let result = create(&second, "Admin", vec![]).await;
+        assert_eq!(result.err().unwrap().0, StatusCode::CONFLICT);
+
+        let auditor = testing::user(&mut conn, first.id, "auditor", "Auditor", None).await;
+        let permissions = permissions::for_role(&pool, first.id, "Auditor")
+            .await
+            .unwrap();
+        assert!(permissions.has(Permission::ReportsView));
+        assert!(!permissions.has(Permission::ClaimsViewAll));
+        assert!(
+            permissions::user_has(&pool, auditor.id, Permission::ReportsView)
+                .await
+                .unwrap()
+        );
+
+        // Deleting the role of one organisation keeps the other one
+        let result = delete_role(
+            extract::State(app_state.clone()),
+            extract::Extension(second.clone()),
+            extract::Path("Auditor".to_string()),
+        )
+        .await;
+        assert!(result.is_ok());
+        let permissions = permissions::for_role(&pool, first.id, "Auditor")
+            .await
+            .unwrap();
+        assert!(permissions.has(Permission::ReportsView));
+
+        // Users can only have the roles of their own organisation
+        let stranger = testing::user(&mut conn, second.id, "stranger", "User", None).await;
+        let result = sqlx::query!(
+            "UPDATE users SET role = 'Auditor' WHERE id = $1",
+            stranger.id
+        )
+        .execute(&mut *conn)
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_register_user_closed_organization(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        testing::organization(&mut conn, "closed").await;
+        let app_state = testing::app_state(pool.clone());
+
+        for organization in [None, Some("closed"), Some("missing")] {
+            let result = register_user(
+                extract::State(app_state.clone()),
+                extract::Json(forms::SignupForm {
+                    mail: "new@example.com".to_string(),
+                    username: "newcomer".to_string(),
+                    password: "Secret-123".to_string(),
+                    organization: organization.map(str::to_string),
+                }),
+            )
+            .await;
+            assert_eq!(result.err().unwrap().0, StatusCode::FORBIDDEN);
+        }
+        let registered = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM users WHERE username = 'newcomer') AS "exists!""#
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        assert!(!registered);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_row_level_security(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let first = testing::organization(&mut conn, "first").await;
+        let second = testing::organization(&mut conn, "second").await;
+        let alice = testing::user(&mut conn, first.id, "alice", "User", None).await;
+        let bob = testing::user(&mut conn, second.id, "bob", "User", None).await;
+        let claim =
+            testing::claim(&mut conn, &alice, ClaimStatus::Pending, Decimal::from(100)).await;
+        testing::claim(&mut conn, &bob, ClaimStatus::Pending, Decimal::from(100)).await;
+        testing::category(&mut conn, second.id, "Travel").await;
+        let restricted = testing::restricted_pool(&mut conn, &pool).await;
+
+        let bypasses = sqlx::query_scalar!(
+            r#"SELECT rolsuper OR rolbypassrls AS "bypasses!" FROM pg_roles WHERE rolname = current_user"#
+        )
+        .fetch_one(&restricted)
+        .await
+        .unwrap();
+        assert!(!bypasses);
+
+        // Queries without a filter on the organisation only see its rows
+        tenant::scope(first.id, async {
+            let usernames = sqlx::query_scalar!("SELECT username FROM users")
+                .fetch_all(&restricted)
+                .await
+                .unwrap();
+            assert_eq!(usernames, vec!["alice".to_string()]);
+            let claims = sqlx::query_scalar!("SELECT id FROM claims")
+                .fetch_all(&restricted)
+                .await
+                .unwrap();
+            assert_eq!(claims, vec![claim.id]);
+            let steps = sqlx::query_scalar!("SELECT claim_id FROM claim_steps")
+                .fetch_all(&restricted)
+                .await
+                .unwrap();
+            assert_eq!(steps, vec![claim.id]);
+            let categories = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM categories"#
+            )
+            .fetch_one(&restricted)
+            .await
+            .unwrap();
+            assert_eq!(categories, 0);
+
+            let updated = sqlx::query!("UPDATE users SET verified = FALSE WHERE id = $1", bob.id)
+                .execute(&restricted)
+                .await
+                .unwrap();
+            assert_eq!(updated.rows_affected(), 0);
+            let result = sqlx::query!(
+                "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, organization_id )
+                VALUES ('Meals', 100, 100, $1)",
+                second.id
+            )
+            .execute(&restricted)
+            .await;
+            assert!(result.is_err());
+        })
+        .await;
+
+        // Background jobs act for every organisation
+        let users = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM users WHERE organization_id IN ($1, $2)"#,
+            first.id,
+            second.id
+        )
+        .fetch_one(&restricted)
+        .await
+        .unwrap();
+        assert_eq!(users, 2);
+
+        testing::drop_restricted_pool(&mut conn, restricted).await;
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_claim_budget_charges_unknown_policy(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        let category = testing::category(&mut conn, organization.id, "Travel").await;
+        let claim = testing::claim(
+            &mut conn,
+            &employee,
+            ClaimStatus::Pending,
+            Decimal::from(20),
+        )
+        .await;
+        let cost_center = sqlx::query_scalar!(
+            "INSERT INTO charge_codes ( kind, code, name, organization_id )
+            VALUES ('CostCenter', 'CC-1', 'Sales', $1) RETURNING id",
+            organization.id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO items ( claim_id, category_id, cost, net_cost, reimbursement, expense_date, cost_center_id )
+            VALUES ($1, $2, 20, 20, 20, '2023-10-02', $3)",
+            claim.id,
+            category,
+            cost_center
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        let budget = sqlx::query_scalar!(
+            "INSERT INTO budgets ( cost_center_id, period_start, period_end, amount, policy, created_by, organization_id )
+            VALUES ($1, '2023-10-01', '2023-10-31', 10, 'Block', $2, $3) RETURNING id",
+            cost_center,
+            employee.id,
+            organization.id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+
+        let charges = claim_budget_charges(&mut conn, claim.id).await.unwrap();
+        assert_eq!(charges.len(), 1);
+        assert_eq!(charges[0].budget.policy, BudgetPolicy::Block);
+
+        // A policy this version does not know must not let approvals through as a warning
+        sqlx::query!("UPDATE budgets SET policy = 'Freeze' WHERE id = $1", budget)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        let result = claim_budget_charges(&mut conn, claim.id).await;
+        assert_eq!(result.err().unwrap().0, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    async fn session_version(conn: &mut PgConnection, user_id: i32) -> i32 {
+        sqlx::query_scalar!("SELECT session_version FROM users WHERE id = $1", user_id)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_change_role(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let other = testing::organization(&mut conn, "second").await;
+        let admin = testing::user(&mut conn, organization.id, "admin", "Admin", None).await;
+        let manager = testing::user(&mut conn, organization.id, "manager", "Manager", None).await;
+        let employee = testing::user(&mut conn, organization.id, "employee", "User", None).await;
+        // Admins of other organisations do not count
+        testing::user(&mut conn, other.id, "stranger", "Admin", None).await;
+        let admin_permissions = permissions::for_role(&pool, organization.id, "Admin")
+            .await
+            .unwrap();
+        let manager_permissions = permissions::for_role(&pool, organization.id, "Manager")
+            .await
+            .unwrap();
+
+        let result =
+            roles::change_role(&mut conn, organization.id, admin.id, &Role::User, None).await;
+        assert!(matches!(result, Err(roles::RoleChangeError::LastAdmin)));
+        let result = roles::change_role(
+            &mut conn,
+            organization.id,
+            employee.id,
+            &Role::Admin,
+            Some((manager.id, &manager_permissions)),
+        )
+        .await;
+        assert!(matches!(result, Err(roles::RoleChangeError::NotPermitted)));
+        let result =
+            roles::change_role(&mut conn, other.id, employee.id, &Role::Manager, None).await;
+        assert!(matches!(result, Err(roles::RoleChangeError::UserNotFound)));
+        let result =
+            roles::change_role(&mut conn, organization.id, employee.id, &Role::User, None).await;
+        assert!(matches!(result, Err(roles::RoleChangeError::Unchanged)));
+        assert_eq!(session_version(&mut conn, employee.id).await, 0);
+
+        let old_role = roles::change_role(
+            &mut conn,
+            organization.id,
+            employee.id,
+            &Role::Admin,
+            Some((admin.id, &admin_permissions)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(old_role, Role::User);
+        // Tokens issued before the change are no longer accepted
+        assert_eq!(session_version(&mut conn, employee.id).await, 1);
+        let change = sqlx::query!("SELECT * FROM role_changes WHERE user_id = $1", employee.id)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(change.changed_by, Some(admin.id));
+        assert_eq!(
+            (change.old_role.as_str(), change.new_role.as_str()),
+            ("User", "Admin")
+        );
+
+        roles::change_role(&mut conn, organization.id, admin.id, &Role::User, None)
+            .await
+            .unwrap();
+        let result =
+            roles::change_role(&mut conn, organization.id, employee.id, &Role::User, None).await;
+        assert!(matches!(result, Err(roles::RoleChangeError::LastAdmin)));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a database"]
+    async fn test_change_role_concurrent_demotions(pool: sqlx::PgPool) {
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::organization(&mut conn, "first").await;
+        let first = testing::user(&mut conn, organization.id, "first", "Admin", None).await;
+        let second = testing::user(&mut conn, organization.id, "second", "Admin", None).await;
+
+        let mut transaction = pool.begin().await.unwrap();
+        roles::change_role(
+            &mut transaction,
+            organization.id,
+            first.id,
+            &Role::User,
+            None,
+        )
+        .await
+        .unwrap();
+        // The second demotion waits for the admins locked by the first one
+        let demotion = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                let mut transaction = pool.begin().await.unwrap();
+                let result = roles::change_role(
+                    &mut transaction,
+                    organization.id,
+                    second.id,
+                    &Role::User,
+                    None,
+                )
+                .await;
+                transaction.commit().await.unwrap();
+                result
+            }
+        });
+        while !demotion.is_finished()
+            && !sqlx::query_scalar!(
+                r#"SELECT EXISTS (
+                SELECT 1 FROM pg_stat_activity
+                WHERE datname = current_database() AND wait_event_type = 'Lock'
+            ) AS "waiting!""#
+            )
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        transaction.commit().await.unwrap();
+
+        let result = demotion.await.unwrap();
+        assert!(matches!(result, Err(roles::RoleChangeError::LastAdmin)));
+        let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1", second.id)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(role, "Admin");
+    }
 }
@@ -0,0 +1,591 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use validator::{Validate, ValidationErrors};
+
+use crate::forms::{CategoryForm, SignupForm};
+use crate::models::{ReimbursementBase, Role};
+use crate::permissions::{self, Permission, Permissions};
+use crate::utils::{generate_random_string, hash_password};
+
+/// Problems of one line of the file, the header is line 1
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    pub line: u64,
+    pub errors: Vec<String>,
+}
+
+/// Nothing is written unless every row is valid and it is not a dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport<T> {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    pub applied: bool,
+    pub rows: Vec<T>,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CategoryRecord {
+    name: String,
+    percentage: Decimal,
+    cap: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedCategory {
+    pub line: u64,
+    pub name: String,
+    #[serde(rename = "reimbursementPercentage")]
+    pub reimbursement_percentage: Decimal,
+    #[serde(rename = "maxReimbursement")]
+    pub max_reimbursement: Decimal,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct UserRecord {
+    mail: String,
+    username: String,
+    #[serde(default)]
+    role: String,
+    /// Username of an existing user or of another row
+    #[serde(default)]
+    manager: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedUser {
+    pub line: u64,
+    pub mail: String,
+    pub username: String,
+    pub role: Role,
+    pub manager: Option<String>,
+    /// Only known right after the import, users should change it
+    #[serde(rename = "temporaryPassword", skip_serializing_if = "Option::is_none")]
+    pub temporary_password: Option<String>,
+}
+
+/// Records of the file with their line number, or why the line could not be read
+fn parse_records<T: DeserializeOwned>(data: &str) -> Vec<(u64, Result<T, String>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return vec![(1, Err(e.to_string()))],
+    };
+    reader
+        .records()
+        .enumerate()
+        .map(|(index, record)| {
+            let line = index as u64 + 2;
+            match record {
+                Ok(record) => (
+                    record.position().map_or(line, |position| position.line()),
+                    record
+                        .deserialize(Some(&headers))
+                        .map_err(|e| match e.kind() {
+                            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                            _ => e.to_string(),
+                        }),
+                ),
+                Err(e) => (line, Err(e.to_string())),
+            }
+        })
+        .collect()
+}
+
+fn validation_messages(errors: &ValidationErrors) -> Vec<String> {
+    let mut messages: Vec<String> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| match &error.message {
+                Some(message) => format!("{}: {}", field, message),
+                None => format!("{}: {}", field, error.code),
+            })
+        })
+        .collect();
+    messages.sort();
+    messages
+}
+
+fn push_error(errors: &mut Vec<RowError>, line: u64, error: String) {
+    match errors.iter_mut().find(|row| row.line == line) {
+        Some(row) => row.errors.push(error),
+        None => errors.push(RowError {
+            line,
+            errors: vec![error],
+        }),
+    }
+}
+
+/// Rows checked against the `CategoryForm` rules and against each other
+fn check_categories(data: &str) -> (Vec<ImportedCategory>, Vec<RowError>) {
+    let mut rows = vec![];
+    let mut errors = vec![];
+    let mut names = HashSet::new();
+    for (line, record) in parse_records::<CategoryRecord>(data) {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                push_error(&mut errors, line, e);
+                continue;
+            }
+        };
+        let form = CategoryForm {
+            name: record.name,
+            reimbursement_percentage: record.percentage,
+            max_reimbursement: record.cap,
+            gl_account: None,
+            default_tax_rate: None,
+            reimbursement_base: None,
+        };
+        if let Err(e) = form.validate() {
+            for message in validation_messages(&e) {
+                push_error(&mut errors, line, message);
+            }
+        }
+        if !names.insert(form.name.clone()) {
+            push_error(&mut errors, line, "Duplicate category name".to_string());
+        }
+        rows.push(ImportedCategory {
+            line,
+            name: form.name,
+            reimbursement_percentage: form.reimbursement_percentage,
+            max_reimbursement: form.max_reimbursement,
+        });
+    }
+    (rows, errors)
+}
+
+/// Rows checked against the `SignupForm` rules, the existing roles and against each other.
+/// Managers that are not in the file are left to the caller.
+fn check_users(
+    data: &str,
+    roles: &HashMap<Role, Permissions>,
+) -> (Vec<ImportedUser>, Vec<RowError>) {
+    let mut rows: Vec<ImportedUser> = vec![];
+    let mut errors = vec![];
+    for (line, record) in parse_records::<UserRecord>(data) {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                push_error(&mut errors, line, e);
+                continue;
+            }
+        };
+        // Imported users get a generated password, only the other fields come from the file
+        let form = SignupForm {
+            mail: record.mail,
+            username: record.username,
+            password: temporary_password(),
+            organization: None,
+        };
+        if let Err(e) = form.validate() {
+            for message in validation_messages(&e) {
+                push_error(&mut errors, line, message);
+            }
+        }
+        let role = Role::from(record.role);
+        if !roles.contains_key(&role) {
+            push_error(&mut errors, line, format!("Unknown role {}", role));
+        }
+        if rows
+            .iter()
+            .any(|row| row.username.eq_ignore_ascii_case(&form.username))
+        {
+            push_error(&mut errors, line, "Duplicate username".to_string());
+        }
+        rows.push(ImportedUser {
+            line,
+            mail: form.mail.to_ascii_lowercase(),
+            username: form.username,
+            role,
+            manager: Some(record.manager).filter(|manager| !manager.is_empty()),
+            temporary_password: None,
+        });
+    }
+
+    let index: HashMap<String, usize> = rows
+        .iter()
+        .enumerate()
+        .map(|(position, row)| (row.username.to_ascii_lowercase(), position))
+        .collect();
+    for row in &rows {
+        let Some(manager) = &row.manager else {
+            continue;
+        };
+        let Some(&position) = index.get(&manager.to_ascii_lowercase()) else {
+            continue;
+        };
+        if !can_approve(roles, &rows[position].role) {
+            push_error(
+                &mut errors,
+                row.line,
+                format!("Manager {} must be a manager", manager),
+            );
+        }
+        // Walk up the managers in the file, coming back to the row is a cycle
+        let mut seen = HashSet::from([row.username.to_ascii_lowercase()]);
+        let mut current = Some(position);
+        while let Some(position) = current {
+            if !seen.insert(rows[position].username.to_ascii_lowercase()) {
+                push_error(
+                    &mut errors,
+                    row.line,
+                    "User cannot report to one of their own reports".to_string(),
+                );
+                break;
+            }
+            current = rows[position]
+                .manager
+                .as_ref()
+                .and_then(|manager| index.get(&manager.to_ascii_lowercase()).copied());
+        }
+    }
+    errors.sort_by_key(|row| row.line);
+    (rows, errors)
+}
+
+/// Only users allowed to approve claims can be assigned as managers
+fn can_approve(roles: &HashMap<Role, Permissions>, role: &Role) -> bool {
+    roles
+        .get(role)
+        .is_some_and(|permissions| permissions.has(Permission::ClaimsApprove))
+}
+
+/// Meets the password rules so imported users can log in until they pick their own
+fn temporary_password() -> String {
+    format!("{}-Aa1", generate_random_string(16))
+}
+
+/// Unique violations of rows that were created by someone else after they were checked
+fn is_conflict(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .is_some_and(|error| error.is_unique_violation())
+}
+
+fn report<T>(dry_run: bool, rows: Vec<T>, mut errors: Vec<RowError>) -> ImportReport<T> {
+    errors.sort_by_key(|row| row.line);
+    ImportReport {
+        dry_run,
+        applied: !dry_run && errors.is_empty(),
+        rows,
+        errors,
+    }
+}
+
+pub async fn import_categories(
+    pool: &PgPool,
+    organization_id: i32,
+    data: &str,
+    dry_run: bool,
+) -> Result<ImportReport<ImportedCategory>, anyhow::Error> {
+    let (rows, mut errors) = check_categories(data);
+
+    let mut transaction = pool.begin().await?;
+    let names: Vec<String> = rows.iter().map(|row| row.name.clone()).collect();
+    let existing = sqlx::query_scalar!(
+        "SELECT name FROM categories WHERE organization_id = $1 AND name = ANY($2)",
+        organization_id,
+        &names
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for row in rows.iter().filter(|row| existing.contains(&row.name)) {
+        push_error(
+            &mut errors,
+            row.line,
+            "Category with this name already exists".to_string(),
+        );
+    }
+    if dry_run || !errors.is_empty() {
+        return Ok(report(dry_run, rows, errors));
+    }
+
+    let mut conflict = None;
+    for row in &rows {
+        let inserted = sqlx::query!(
+            "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, reimbursement_base, organization_id )
+            VALUES ($1, $2, $3, $4, $5)",
+            row.name,
+            row.reimbursement_percentage,
+            row.max_reimbursement,
+            ReimbursementBase::Gross.to_string(),
+            organization_id
+        )
+        .execute(&mut *transaction)
+        .await;
+        match inserted {
+            Err(e) if is_conflict(&e) => {
+                conflict = Some(row.line);
+                break;
+            }
+            inserted => inserted?,
+        };
+    }
+    if let Some(line) = conflict {
+        push_error(
+            &mut errors,
+            line,
+            "Category with this name already exists".to_string(),
+        );
+        return Ok(report(dry_run, rows, errors));
+    }
+    transaction.commit().await?;
+    Ok(report(dry_run, rows, errors))
+}
+
+/// Users are created in the organisation. With a `granter`, roles allowing more than the
+/// granter has are treated as unknown.
+pub async fn import_users(
+    pool: &PgPool,
+    organization_id: i32,
+    granter: Option<&Permissions>,
+    data: &str,
+    dry_run: bool,
+) -> Result<ImportReport<ImportedUser>, anyhow::Error> {
+    let roles: HashMap<Role, Permissions> = permissions::list_roles(pool, organization_id)
+        .await?
+        .into_iter()
+        .map(|role| (role.name, role.permissions.into_iter().collect()))
+        .filter(|(_, permissions)| granter.map_or(true, |granter| granter.includes(permissions)))
+        .collect();
+    let (mut rows, mut errors) = check_users(data, &roles);
+
+    let mut transaction = pool.begin().await?;
+    let usernames: Vec<String> = rows
+        .iter()
+        .map(|row| row.username.to_ascii_lowercase())
+        .collect();
+    // Usernames are unique across organisations, they are used to log in
+    let taken = sqlx::query_scalar!(
+        "SELECT LOWER(username) FROM users WHERE LOWER(username) = ANY($1)",
+        &usernames
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    for row in &rows {
+        if taken.contains(&Some(row.username.to_ascii_lowercase())) {
+            push_error(&mut errors, row.line, "User already exists".to_string());
+        }
+    }
+
+    let external: Vec<String> = rows
+        .iter()
+        .filter_map(|row| row.manager.as_ref())
+        .map(|manager| manager.to_ascii_lowercase())
+        .filter(|manager| !usernames.contains(manager))
+        .collect();
+    let managers: HashMap<String, (i32, Role)> = sqlx::query!(
+        "SELECT id, LOWER(username) AS \"username!\", role FROM users
+        WHERE organization_id = $1 AND LOWER(username) = ANY($2)",
+        organization_id,
+        &external
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|row| (row.username, (row.id, Role::from(row.role))))
+    .collect();
+    for row in &rows {
+        let Some(manager) = &row.manager else {
+            continue;
+        };
+        let key = manager.to_ascii_lowercase();
+        if usernames.contains(&key) {
+            continue;
+        }
+        match managers.get(&key) {
+            None => push_error(
+                &mut errors,
+                row.line,
+                format!("Manager {} does not exist", manager),
+            ),
+            Some((_, role)) if !can_approve(&roles, role) => push_error(
+                &mut errors,
+                row.line,
+                format!("Manager {} must be a manager", manager),
+            ),
+            Some(_) => {}
+        }
+    }
+
+    if dry_run || !errors.is_empty() {
+        return Ok(report(dry_run, rows, errors));
+    }
+
+    let mut ids: HashMap<String, i32> = managers
+        .into_iter()
+        .map(|(username, (id, _))| (username, id))
+        .collect();
+    let mut passwords = vec![];
+    let mut conflict = None;
+    for row in &rows {
+        let password = temporary_password();
+        let password_hash = hash_password(&password).map_err(|e| anyhow::anyhow!(e))?;
+        // Accounts created by an admin do not need their mail address verified
+        let inserted = sqlx::query_scalar!(
+            "INSERT INTO users ( mail, username, password_hash, role, verified, organization_id )
+            VALUES ($1, $2, $3, $4, true, $5) RETURNING id",
+            row.mail,
+            row.username,
+            password_hash,
+            row.role.to_string(),
+            organization_id
+        )
+        .fetch_one(&mut *transaction)
+        .await;
+        let id = match inserted {
+            Err(e) if is_conflict(&e) => {
+                conflict = Some(row.line);
+                break;
+            }
+            inserted => inserted?,
+        };
+        ids.insert(row.username.to_ascii_lowercase(), id);
+        passwords.push(password);
+    }
+    if let Some(line) = conflict {
+        push_error(&mut errors, line, "User already exists".to_string());
+        return Ok(report(dry_run, rows, errors));
+    }
+    for row in &rows {
+        if let Some(manager) = &row.manager {
+            sqlx::query!(
+                "UPDATE users SET manager_id = $1 WHERE id = $2",
+                ids[&manager.to_ascii_lowercase()],
+                ids[&row.username.to_ascii_lowercase()]
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+    transaction.commit().await?;
+    for (row, password) in rows.iter_mut().zip(passwords) {
+        row.temporary_password = Some(password);
+    }
+    Ok(report(dry_run, rows, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles() -> HashMap<Role, Permissions> {
+        HashMap::from([
+            (Role::User, Permissions::default()),
+            (
+                Role::Manager,
+                [Permission::ClaimsApprove].into_iter().collect(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_check_categories() {
+        let (rows, errors) = check_categories(
+            "name,percentage,cap\n\
+            Travel, 80, 500\n\
+            Meals,120,50\n\
+            ,50,10\n\
+            Travel,50,abc\n\
+            Travel,50,100\n",
+        );
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].name, "Travel");
+        assert_eq!(rows[0].reimbursement_percentage, Decimal::from(80));
+        assert_eq!(
+            errors,
+            vec![
+                RowError {
+                    line: 3,
+                    errors: vec![
+                        "reimbursementPercentage: Percentage must be between 0 and 100"
+                            .to_string()
+                    ]
+                },
+                RowError {
+                    line: 4,
+                    errors: vec!["name: Can not be empty".to_string()]
+                },
+                RowError {
+                    line: 5,
+                    errors: vec![
+                        "invalid value: string \"abc\", expected a Decimal type representing a fixed-point number"
+                            .to_string()
+                    ]
+                },
+                RowError {
+                    line: 6,
+                    errors: vec!["Duplicate category name".to_string()]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_categories_missing_column() {
+        let (rows, errors) = check_categories("name,percentage\nTravel,80\n");
+        assert!(rows.is_empty());
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].errors, vec!["missing field `cap`".to_string()]);
+    }
+
+    #[test]
+    fn test_check_users() {
+        let (rows, errors) = check_users(
+            "mail,username,role,manager\n\
+            boss@example.com,Boss,Manager,\n\
+            Alice@Example.com,alice,,boss\n\
+            bob@example.com,bob,User,alice\n\
+            invalid,carol,Chief,someone\n\
+            dave@example.com,ALICE,User,\n",
+            &roles(),
+        );
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[1].mail, "alice@example.com");
+        assert_eq!(rows[1].role, Role::User);
+        assert_eq!(rows[1].manager.as_deref(), Some("boss"));
+        assert_eq!(rows[3].manager.as_deref(), Some("someone"));
+        assert_eq!(
+            errors,
+            vec![
+                RowError {
+                    line: 4,
+                    errors: vec!["Manager alice must be a manager".to_string()]
+                },
+                RowError {
+                    line: 5,
+                    errors: vec!["mail: email".to_string(), "Unknown role Chief".to_string()]
+                },
+                RowError {
+                    line: 6,
+                    errors: vec!["Duplicate username".to_string()]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_users_cycle() {
+        let (_, errors) = check_users(
+            "mail,username,role,manager\n\
+            a@example.com,a,Manager,b\n\
+            b@example.com,b,Manager,a\n\
+            c@example.com,c,Manager,c\n",
+            &roles(),
+        );
+        let lines: Vec<u64> = errors.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert!(errors
+            .iter()
+            .all(|row| row.errors
+                == vec!["User cannot report to one of their own reports".to_string()]));
+    }
+}
@@ -11,10 +11,12 @@ use axum_extra::extract::{
     CookieJar,
 };
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use sqlx::types::Json;
 
 use crate::{
-    models::{JWTokenClaims, User},
-    AppState,
+    models::{JWTokenClaims, Organization, OrganizationSettings, User},
+    permissions::{self, Permission},
+    tenant, AppState,
 };
 
 const BEARER_START: &str = "Bearer ";
@@ -57,12 +59,47 @@ pub async fn auth<B>(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
         .ok_or(StatusCode::UNAUTHORIZED)?;
+    if claims.ver != user.session_version {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let permissions = permissions::for_role(
+        &app_state.pool,
+        user.organization_id,
+        &user.role.to_string(),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Only super-admins can act in another organisation than their own
+    let organization_id = claims.org.unwrap_or(user.organization_id);
+    if organization_id != user.organization_id && !permissions.has(Permission::OrganizationsManage)
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let organization = sqlx::query_as!(
+        Organization,
+        r#"SELECT id, slug, name, settings AS "settings: Json<OrganizationSettings>", created_at
+        FROM organizations WHERE id = $1"#,
+        organization_id
+    )
+    .fetch_optional(&app_state.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::UNAUTHORIZED)?;
 
     req.extensions_mut().insert(user);
-    Ok(next.run(req).await)
+    req.extensions_mut().insert(permissions);
+    req.extensions_mut().insert(organization);
+    Ok(tenant::scope(organization_id, next.run(req)).await)
 }
 
-pub fn create_new_auth_token(jwt_secret: String, user_id: i32) -> Result<String, StatusCode> {
+pub fn create_new_auth_token(
+    jwt_secret: String,
+    user_id: i32,
+    organization_id: i32,
+    session_version: i32,
+) -> Result<String, StatusCode> {
     let now = chrono::Utc::now();
     let iat = now.timestamp() as usize;
     let exp = (now + chrono::Duration::minutes(60)).timestamp() as usize;
@@ -70,6 +107,8 @@ pub fn create_new_auth_token(jwt_secret: String, user_id: i32) -> Result<String,
         sub: user_id,
         exp,
         iat,
+        org: Some(organization_id),
+        ver: session_version,
     };
 
     let token = jsonwebtoken::encode(
@@ -0,0 +1,39 @@
+mod accounting;
+mod approvals;
+mod bank;
+mod budgets;
+mod claim_report;
+pub mod config;
+pub mod email;
+pub mod escalation;
+mod export;
+mod flags;
+pub mod forms;
+mod handlers;
+pub mod import;
+mod jwt_auth;
+pub mod models;
+pub mod permissions;
+mod reporting;
+pub mod roles;
+pub mod route;
+mod search;
+mod sepa;
+mod tax;
+pub mod tenant;
+#[cfg(test)]
+mod testing;
+pub mod utils;
+mod xlsx;
+
+use std::sync::Arc;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub pool: sqlx::PgPool,
+    pub config: Config,
+    /// Limits the exports holding a database connection at the same time
+    pub export_permits: Arc<tokio::sync::Semaphore>,
+}
@@ -1,33 +1,16 @@
-pub mod config;
-pub mod email;
-pub mod forms;
-mod handlers;
-mod jwt_auth;
-pub mod models;
-mod route;
-pub mod utils;
-
 use std::sync::Arc;
 
 use axum::http::{header, HeaderValue, Method};
-use sqlx::postgres::PgPoolOptions;
+use axum_backend::{config::Config, escalation, route::create_router, tenant, AppState};
 use tower_http::cors::CorsLayer;
 
-use crate::{config::Config, route::create_router};
-
-#[derive(Debug, Clone)]
-pub struct AppState {
-    pub pool: sqlx::PgPool,
-    pub config: Config,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let _ = dotenv::from_filename(".env.priv");
     let config = Config::init()?;
     println!("Config {:?}", &config);
 
-    let pool = PgPoolOptions::new()
+    let pool = tenant::pool_options()
         .max_connections(10)
         .connect(&config.database_url)
         .await?;
@@ -38,14 +21,23 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let cors = CorsLayer::new()
         .allow_origin(origin)
-        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
         .allow_credentials(true)
         .allow_headers([header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE]);
 
     let app_state = Arc::new(AppState {
         pool,
         config: config.clone(),
+        export_permits: Arc::new(tokio::sync::Semaphore::new(config.max_concurrent_exports)),
     });
+    tokio::spawn(escalation::run(app_state.clone()));
+
     let app = create_router(app_state).layer(cors);
 
     let addr = format!("0.0.0.0:{}", config.port);
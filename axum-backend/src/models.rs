@@ -1,31 +1,54 @@
+use std::fmt;
 use std::str::FromStr;
 
 use chrono::prelude::*;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::FromRow;
 use strum::EnumString;
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize, EnumString)]
+use crate::bank;
+
+/// Role of a user, what it allows is stored in `role_permissions`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, EnumString)]
+#[serde(from = "String", into = "String")]
 pub enum Role {
     User,
     Manager,
+    Finance,
     Admin,
+    /// Admin of every organisation
+    SuperAdmin,
+    /// Role created by an admin at runtime
+    #[strum(default)]
+    Custom(String),
 }
 
 impl From<String> for Role {
     fn from(value: String) -> Self {
-        // HACK: This might change later
-        Role::from_str(value.as_str()).unwrap_or(Role::User)
+        if value.is_empty() {
+            return Role::User;
+        }
+        Role::from_str(value.as_str()).unwrap_or(Role::Custom(value))
+    }
+}
+
+impl From<Role> for String {
+    fn from(role: Role) -> Self {
+        role.to_string()
     }
 }
 
-impl ToString for Role {
-    fn to_string(&self) -> String {
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Role::User => "User".to_string(),
-            Role::Manager => "Manager".to_string(),
-            Role::Admin => "Admin".to_string(),
+            Role::User => write!(f, "User"),
+            Role::Manager => write!(f, "Manager"),
+            Role::Finance => write!(f, "Finance"),
+            Role::Admin => write!(f, "Admin"),
+            Role::SuperAdmin => write!(f, "SuperAdmin"),
+            Role::Custom(name) => write!(f, "{}", name),
         }
     }
 }
@@ -43,6 +66,15 @@ pub struct User {
     pub verification_code: Option<String>,
     #[serde(rename = "createdAt")]
     pub created_at: NaiveDateTime,
+    #[serde(rename = "managerId")]
+    pub manager_id: Option<i32>,
+    #[serde(rename = "defaultCostCenterId")]
+    pub default_cost_center_id: Option<i32>,
+    /// Tokens issued for an older version are rejected
+    #[serde(rename = "sessionVersion")]
+    pub session_version: i32,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -53,6 +85,10 @@ pub struct BasicUserInfo {
     pub verified: bool,
     #[serde(rename = "createdAt")]
     pub created_at: NaiveDateTime,
+    #[serde(rename = "managerId")]
+    pub manager_id: Option<i32>,
+    #[serde(rename = "defaultCostCenterId")]
+    pub default_cost_center_id: Option<i32>,
 }
 
 impl From<User> for BasicUserInfo {
@@ -63,6 +99,8 @@ impl From<User> for BasicUserInfo {
             role: user.role,
             verified: user.verified,
             created_at: user.created_at,
+            manager_id: user.manager_id,
+            default_cost_center_id: user.default_cost_center_id,
         }
     }
 }
@@ -75,6 +113,12 @@ pub struct JWTokenClaims {
     pub iat: usize,
     /// Expiration time
     pub exp: usize,
+    /// Organisation the token acts in, only super-admins can act outside of their own
+    #[serde(default)]
+    pub org: Option<i32>,
+    /// Session version of the user when the token was issued
+    #[serde(default)]
+    pub ver: i32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,6 +129,34 @@ pub struct Category {
     pub reimbursement_percentage: Decimal,
     #[serde(rename = "maxReimburstment")]
     pub max_reimbursement: Decimal,
+    /// General ledger account the reimbursements are booked on
+    #[serde(rename = "glAccount")]
+    pub gl_account: Option<String>,
+    /// Tax rate in percent used for items that do not specify their tax
+    #[serde(rename = "defaultTaxRate")]
+    pub default_tax_rate: Decimal,
+    #[serde(rename = "reimbursementBase")]
+    pub reimbursement_base: ReimbursementBase,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ReimbursementBase {
+    /// The reimbursement percentage applies to the amount including tax
+    Gross,
+    /// The reimbursement percentage applies to the amount excluding tax
+    Net,
+}
+
+impl fmt::Display for ReimbursementBase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReimbursementBase::Gross => write!(f, "Gross"),
+            ReimbursementBase::Net => write!(f, "Net"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,6 +168,27 @@ pub struct Item {
     pub category_id: i32,
     pub cost: Decimal,
     pub reimbursement: Decimal,
+    #[serde(rename = "expenseDate")]
+    pub expense_date: NaiveDate,
+    #[serde(rename = "receiptHash")]
+    pub receipt_hash: Option<String>,
+    #[serde(rename = "netCost")]
+    pub net_cost: Decimal,
+    #[serde(rename = "taxRate")]
+    pub tax_rate: Decimal,
+    #[serde(rename = "taxAmount")]
+    pub tax_amount: Decimal,
+    /// Part of the reimbursement that is reclaimable tax
+    #[serde(rename = "reimbursedTax")]
+    pub reimbursed_tax: Decimal,
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: Option<i32>,
+    #[serde(rename = "projectId")]
+    pub project_id: Option<i32>,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: NaiveDateTime,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, EnumString)]
@@ -103,6 +196,7 @@ pub enum ClaimStatus {
     Pending,
     Accepted,
     Rejected,
+    Paid,
 }
 
 impl From<String> for ClaimStatus {
@@ -112,12 +206,13 @@ impl From<String> for ClaimStatus {
     }
 }
 
-impl ToString for ClaimStatus {
-    fn to_string(&self) -> String {
+impl fmt::Display for ClaimStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ClaimStatus::Pending => "Pending".to_string(),
-            ClaimStatus::Accepted => "Accepted".to_string(),
-            ClaimStatus::Rejected => "Rejected".to_string(),
+            ClaimStatus::Pending => write!(f, "Pending"),
+            ClaimStatus::Accepted => write!(f, "Accepted"),
+            ClaimStatus::Rejected => write!(f, "Rejected"),
+            ClaimStatus::Paid => write!(f, "Paid"),
         }
     }
 }
@@ -130,9 +225,434 @@ pub struct Claim {
     #[serde(rename = "totalCost")]
     pub total_cost: Option<Decimal>,
     pub reimbursement: Option<Decimal>,
+    #[sqlx(try_from = "String")]
     pub status: ClaimStatus,
+    #[serde(rename = "currentStep")]
+    pub current_step: i32,
+    #[serde(rename = "stepStartedAt")]
+    pub step_started_at: NaiveDateTime,
+    #[serde(rename = "paymentBatchId")]
+    pub payment_batch_id: Option<i32>,
+    #[serde(rename = "paidAt")]
+    pub paid_at: Option<NaiveDateTime>,
+    #[serde(rename = "paymentReference")]
+    pub payment_reference: Option<String>,
+    #[serde(rename = "totalTax")]
+    pub total_tax: Option<Decimal>,
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: Option<i32>,
+    #[serde(rename = "projectId")]
+    pub project_id: Option<i32>,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+    /// Submitted for approval, claims are currently submitted when they are created
+    #[serde(rename = "submittedAt")]
+    pub submitted_at: Option<NaiveDateTime>,
+    /// Accepted or rejected on the last step
+    #[serde(rename = "decidedAt")]
+    pub decided_at: Option<NaiveDateTime>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: NaiveDateTime,
+    pub description: Option<String>,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum PaymentBatchStatus {
+    Open,
+    Exported,
+    Paid,
+}
+
+impl fmt::Display for PaymentBatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentBatchStatus::Open => write!(f, "Open"),
+            PaymentBatchStatus::Exported => write!(f, "Exported"),
+            PaymentBatchStatus::Paid => write!(f, "Paid"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PaymentBatch {
+    pub id: i32,
+    pub status: PaymentBatchStatus,
+    pub total: Decimal,
+    #[serde(rename = "createdBy")]
+    pub created_by: i32,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+    #[serde(rename = "exportedAt")]
+    pub exported_at: Option<NaiveDateTime>,
+    #[serde(rename = "paidAt")]
+    pub paid_at: Option<NaiveDateTime>,
+    pub reference: Option<String>,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum StepKind {
+    /// Claimant's manager, or the fallback approver
+    LineManager,
+    /// Any manager
+    Manager,
+    Finance,
+}
+
+impl fmt::Display for StepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepKind::LineManager => write!(f, "LineManager"),
+            StepKind::Manager => write!(f, "Manager"),
+            StepKind::Finance => write!(f, "Finance"),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ReminderLevel {
+    /// Sent to the approvers of the current step
+    Reminder,
+    /// Sent to the approvers' managers, or admins
+    Escalation,
+}
+
+impl fmt::Display for ReminderLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReminderLevel::Reminder => write!(f, "Reminder"),
+            ReminderLevel::Escalation => write!(f, "Escalation"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ClaimStep {
+    pub id: i32,
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    pub position: i32,
+    pub kind: StepKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ClaimDecision {
+    pub id: i32,
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    #[serde(rename = "approverId")]
+    pub approver_id: i32,
+    pub accepted: bool,
+    pub step: i32,
+    /// Delegator when the decision was made through a delegation
+    #[serde(rename = "onBehalfOf")]
+    pub on_behalf_of: Option<i32>,
+    pub reason: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Delegation {
+    pub id: i32,
+    #[serde(rename = "delegatorId")]
+    pub delegator_id: i32,
+    #[serde(rename = "delegateId")]
+    pub delegate_id: i32,
+    #[serde(rename = "startsOn")]
+    pub starts_on: NaiveDate,
+    #[serde(rename = "endsOn")]
+    pub ends_on: NaiveDate,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum FlagKind {
+    DuplicateExpense,
+    ReusedReceipt,
+    RoundAmount,
+}
+
+impl fmt::Display for FlagKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlagKind::DuplicateExpense => write!(f, "DuplicateExpense"),
+            FlagKind::ReusedReceipt => write!(f, "ReusedReceipt"),
+            FlagKind::RoundAmount => write!(f, "RoundAmount"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ClaimFlag {
+    pub id: i32,
+    #[serde(rename = "claimId")]
+    pub claim_id: i32,
+    #[serde(rename = "itemId")]
+    pub item_id: Option<i32>,
+    pub kind: FlagKind,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingClaim {
+    #[serde(flatten)]
+    pub claim: Claim,
+    pub flags: Vec<ClaimFlag>,
+    pub steps: Vec<ClaimStep>,
+    pub decisions: Vec<ClaimDecision>,
+}
+
+/// Item with the category and the reimbursement policy it was estimated with
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemDetails {
+    #[serde(flatten)]
+    pub item: Item,
+    /// None when the category no longer exists
+    pub category: Option<Category>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionDetails {
+    #[serde(flatten)]
+    pub decision: ClaimDecision,
+    #[serde(rename = "approverName")]
+    pub approver_name: String,
+    #[serde(rename = "onBehalfOfName")]
+    pub on_behalf_of_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClaimDetails {
+    #[serde(flatten)]
+    pub claim: Claim,
+    pub items: Vec<ItemDetails>,
+    /// Line manager of the claimant, or the fallback approver when there is none
+    pub reviewer: Option<BasicUserInfo>,
+    pub steps: Vec<ClaimStep>,
+    pub decisions: Vec<DecisionDetails>,
+    /// Only shown to reviewers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<ClaimFlag>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RoleChange {
+    pub id: i32,
+    #[serde(rename = "userId")]
+    pub user_id: i32,
+    #[serde(rename = "changedBy")]
+    pub changed_by: Option<i32>,
+    #[serde(rename = "oldRole")]
+    pub old_role: Role,
+    #[serde(rename = "newRole")]
+    pub new_role: Role,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize, FromRow, Clone)]
+pub struct BankDetails {
+    pub user_id: i32,
+    pub account_holder: String,
+    pub iban: String,
+    pub bic: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Bank details as shown in API responses, with the IBAN masked
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BankDetailsInfo {
+    #[serde(rename = "userId")]
+    pub user_id: i32,
+    #[serde(rename = "accountHolder")]
+    pub account_holder: String,
+    pub iban: String,
+    pub bic: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<BankDetails> for BankDetailsInfo {
+    fn from(details: BankDetails) -> Self {
+        BankDetailsInfo {
+            user_id: details.user_id,
+            account_holder: details.account_holder,
+            iban: bank::mask_iban(&details.iban),
+            bic: details.bic,
+            updated_at: details.updated_at,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, EnumString, sqlx::Type,
+)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum JournalEntryKind {
+    /// Expense against the payables account, booked when the claim is accepted
+    Accrual,
+    /// Payables against the bank account, booked when the claim is paid
+    Payment,
+}
+
+impl fmt::Display for JournalEntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEntryKind::Accrual => write!(f, "Accrual"),
+            JournalEntryKind::Payment => write!(f, "Payment"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, FromRow, Clone)]
+pub struct AccountingExport {
+    pub id: i32,
+    #[serde(rename = "periodStart")]
+    pub period_start: NaiveDate,
+    #[serde(rename = "periodEnd")]
+    pub period_end: NaiveDate,
+    #[serde(rename = "createdBy")]
+    pub created_by: i32,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum ChargeCodeKind {
+    CostCenter,
+    Project,
+}
+
+impl fmt::Display for ChargeCodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChargeCodeKind::CostCenter => write!(f, "CostCenter"),
+            ChargeCodeKind::Project => write!(f, "Project"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, FromRow, Clone)]
+pub struct ChargeCode {
+    pub id: i32,
+    pub kind: ChargeCodeKind,
+    pub code: String,
+    pub name: String,
+    pub archived: bool,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumString, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")]
+pub enum BudgetPolicy {
+    /// Approvals over the budget go through with a warning
+    Warn,
+    /// Approvals over the budget are refused
+    Block,
+}
+
+impl fmt::Display for BudgetPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetPolicy::Warn => write!(f, "Warn"),
+            BudgetPolicy::Block => write!(f, "Block"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, FromRow, Clone)]
+pub struct Budget {
+    pub id: i32,
+    #[serde(rename = "costCenterId")]
+    pub cost_center_id: i32,
+    /// Budget for all categories when `None`
+    #[serde(rename = "categoryId")]
+    pub category_id: Option<i32>,
+    #[serde(rename = "periodStart")]
+    pub period_start: NaiveDate,
+    #[serde(rename = "periodEnd")]
+    pub period_end: NaiveDate,
+    pub amount: Decimal,
+    pub policy: BudgetPolicy,
+    #[serde(rename = "createdBy")]
+    pub created_by: i32,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+    #[serde(rename = "organizationId")]
+    pub organization_id: i32,
+}
+
+/// Overrides of the server configuration for one organisation, missing values keep
+/// the configured ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrganizationSettings {
+    #[serde(rename = "debtorName", skip_serializing_if = "Option::is_none")]
+    pub debtor_name: Option<String>,
+    #[serde(rename = "debtorIban", skip_serializing_if = "Option::is_none")]
+    pub debtor_iban: Option<String>,
+    #[serde(rename = "debtorBic", skip_serializing_if = "Option::is_none")]
+    pub debtor_bic: Option<String>,
+    #[serde(rename = "paymentCurrency", skip_serializing_if = "Option::is_none")]
+    pub payment_currency: Option<String>,
+    /// Same format as `APPROVAL_WORKFLOW`, e.g. `LineManager:0,Finance:500`
+    #[serde(rename = "approvalWorkflow", skip_serializing_if = "Option::is_none")]
+    pub approval_workflow: Option<String>,
+    #[serde(
+        rename = "requireAssignedManager",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub require_assigned_manager: Option<bool>,
+    #[serde(rename = "fallbackApproverId", skip_serializing_if = "Option::is_none")]
+    pub fallback_approver_id: Option<i32>,
+    /// Anyone who knows the slug can register, otherwise admins create the accounts
+    #[serde(rename = "openRegistration", skip_serializing_if = "Option::is_none")]
+    pub open_registration: Option<bool>,
+    #[serde(
+        rename = "accountingExpenseAccount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub accounting_expense_account: Option<String>,
+    #[serde(
+        rename = "accountingPayableAccount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub accounting_payable_account: Option<String>,
+    #[serde(
+        rename = "accountingBankAccount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub accounting_bank_account: Option<String>,
+    #[serde(
+        rename = "accountingTaxAccount",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub accounting_tax_account: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Organization {
+    pub id: i32,
+    pub slug: String,
+    pub name: String,
+    pub settings: Json<OrganizationSettings>,
+    #[serde(rename = "createdAt")]
+    pub created_at: NaiveDateTime,
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -140,8 +660,14 @@ mod tests {
     fn test_role_from_string() {
         assert_eq!(Role::from("User".to_string()), Role::User);
         assert_eq!(Role::from("Manager".to_string()), Role::Manager);
+        assert_eq!(Role::from("Finance".to_string()), Role::Finance);
         assert_eq!(Role::from("Admin".to_string()), Role::Admin);
+        assert_eq!(Role::from("SuperAdmin".to_string()), Role::SuperAdmin);
         assert_eq!(Role::from("".to_string()), Role::User);
+        assert_eq!(
+            Role::from("Auditor".to_string()),
+            Role::Custom("Auditor".to_string())
+        );
     }
 
     #[test]
@@ -149,8 +675,10 @@ mod tests {
         assert!(Role::User == Role::User);
         assert!(Role::Manager == Role::Manager);
         assert!(Role::Admin == Role::Admin);
-
-        assert!(Role::User < Role::Manager);
-        assert!(Role::Manager < Role::Admin);
+        assert!(Role::Admin != Role::Custom("Admin2".to_string()));
+        assert_eq!(
+            Role::Custom("Auditor".to_string()).to_string(),
+            "Auditor".to_string()
+        );
     }
 }
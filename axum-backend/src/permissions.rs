@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::{
+    extract::{Extension, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+
+use crate::models::Role;
+
+/// Named permission granted to roles through the `role_permissions` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Permission {
+    /// Decide on manager steps and on line manager steps of the caller's reports
+    ClaimsApprove,
+    /// Decide on line manager steps of any claimant
+    ClaimsApproveAny,
+    ClaimsApproveFinance,
+    /// Read every claim instead of only the caller's own and those of their reports
+    ClaimsViewAll,
+    ReportsView,
+    BudgetsManage,
+    PaymentsManage,
+    AccountingExport,
+    /// Read and change the bank details of other users
+    BankDetailsManage,
+    CategoriesManage,
+    ChargeCodesManage,
+    UsersManage,
+    /// Remove delegations of other users
+    DelegationsManage,
+    RolesManage,
+    /// Change the settings of the caller's organisation
+    SettingsManage,
+    /// Create organisations and act in any of them
+    OrganizationsManage,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 16] = [
+        Permission::ClaimsApprove,
+        Permission::ClaimsApproveAny,
+        Permission::ClaimsApproveFinance,
+        Permission::ClaimsViewAll,
+        Permission::ReportsView,
+        Permission::BudgetsManage,
+        Permission::PaymentsManage,
+        Permission::AccountingExport,
+        Permission::BankDetailsManage,
+        Permission::CategoriesManage,
+        Permission::ChargeCodesManage,
+        Permission::UsersManage,
+        Permission::DelegationsManage,
+        Permission::RolesManage,
+        Permission::SettingsManage,
+        Permission::OrganizationsManage,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::ClaimsApprove => "claims.approve",
+            Permission::ClaimsApproveAny => "claims.approve_any",
+            Permission::ClaimsApproveFinance => "claims.approve_finance",
+            Permission::ClaimsViewAll => "claims.view_all",
+            Permission::ReportsView => "reports.view",
+            Permission::BudgetsManage => "budgets.manage",
+            Permission::PaymentsManage => "payments.manage",
+            Permission::AccountingExport => "accounting.export",
+            Permission::BankDetailsManage => "bank_details.manage",
+            Permission::CategoriesManage => "categories.manage",
+            Permission::ChargeCodesManage => "charge_codes.manage",
+            Permission::UsersManage => "users.manage",
+            Permission::DelegationsManage => "delegations.manage",
+            Permission::RolesManage => "roles.manage",
+            Permission::SettingsManage => "settings.manage",
+            Permission::OrganizationsManage => "organizations.manage",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.name() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown permission {}", value))
+    }
+}
+
+impl TryFrom<String> for Permission {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Permission::from_str(&value)
+    }
+}
+
+impl From<Permission> for String {
+    fn from(permission: Permission) -> Self {
+        permission.name().to_string()
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Permissions of the caller, inserted next to the user by the auth middleware
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions(HashSet<Permission>);
+
+impl Permissions {
+    /// Names that are not known to this version are ignored
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Self {
+        Permissions(
+            names
+                .iter()
+                .filter_map(|name| Permission::from_str(name.as_ref()).ok())
+                .collect(),
+        )
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.0.contains(&permission)
+    }
+
+    /// Whether every permission of `other` is also one of these
+    pub fn includes(&self, other: &Permissions) -> bool {
+        other.0.is_subset(&self.0)
+    }
+
+    pub fn sorted(&self) -> Vec<Permission> {
+        let mut permissions: Vec<Permission> = self.0.iter().copied().collect();
+        permissions.sort_by_key(|permission| permission.name());
+        permissions
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), (StatusCode, &'static str)> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(FORBIDDEN)
+        }
+    }
+}
+
+impl FromIterator<Permission> for Permissions {
+    fn from_iter<I: IntoIterator<Item = Permission>>(iter: I) -> Self {
+        Permissions(iter.into_iter().collect())
+    }
+}
+
+const FORBIDDEN: (StatusCode, &str) = (
+    StatusCode::FORBIDDEN,
+    "You do not have the permission to perform this action",
+);
+
+/// Role with what it allows, built-in roles cannot be changed
+#[derive(Debug, Clone, Serialize)]
+pub struct RoleDefinition {
+    pub name: Role,
+    pub builtin: bool,
+    pub permissions: Vec<Permission>,
+}
+
+/// Built-in roles and the custom roles of the organisation
+pub async fn list_roles<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: i32,
+) -> Result<Vec<RoleDefinition>, sqlx::Error> {
+    let roles = sqlx::query!(
+        r#"SELECT name, builtin,
+        ARRAY(
+            SELECT permission FROM role_permissions
+            WHERE role = roles.name AND organization_id IS NOT DISTINCT FROM roles.organization_id
+            ORDER BY permission
+        ) AS "permissions!"
+        FROM roles WHERE organization_id IS NULL OR organization_id = $1
+        ORDER BY builtin DESC, name ASC"#,
+        organization_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(roles
+        .into_iter()
+        .map(|role| RoleDefinition {
+            name: Role::from(role.name),
+            builtin: role.builtin,
+            permissions: role
+                .permissions
+                .iter()
+                .filter_map(|name| Permission::from_str(name).ok())
+                .collect(),
+        })
+        .collect())
+}
+
+/// Permissions of a built-in role or a custom role of the organisation
+pub async fn for_role<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: i32,
+    role: &str,
+) -> Result<Permissions, sqlx::Error> {
+    let names = sqlx::query_scalar!(
+        "SELECT permission FROM role_permissions
+        WHERE role = $1 AND (organization_id IS NULL OR organization_id = $2)",
+        role,
+        organization_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(Permissions::from_names(&names))
+}
+
+pub async fn user_has<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    permission: Permission,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM users JOIN role_permissions ON role_permissions.role = users.role
+            AND (role_permissions.organization_id IS NULL OR role_permissions.organization_id = users.organization_id)
+            WHERE users.id = $1 AND role_permissions.permission = $2
+        ) AS "exists!""#,
+        user_id,
+        permission.name()
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Route layer rejecting callers without the permission, runs after the auth middleware
+pub async fn require<B>(
+    State(permission): State<Permission>,
+    Extension(permissions): Extension<Permissions>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    permissions.require(permission)?;
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_names() {
+        for permission in Permission::ALL {
+            assert_eq!(Permission::from_str(permission.name()).unwrap(), permission);
+        }
+        Permission::from_str("claims.delete").unwrap_err();
+    }
+
+    #[test]
+    fn test_permissions_from_names() {
+        let permissions = Permissions::from_names(&["claims.approve", "claims.delete"]);
+        assert!(permissions.has(Permission::ClaimsApprove));
+        assert!(!permissions.has(Permission::ClaimsApproveAny));
+        permissions.require(Permission::ClaimsApprove).unwrap();
+        permissions.require(Permission::RolesManage).unwrap_err();
+    }
+
+    #[test]
+    fn test_permissions_includes() {
+        let admin = Permissions::from_names(&["users.manage", "roles.manage"]);
+        assert!(admin.includes(&Permissions::from_names(&["users.manage"])));
+        assert!(admin.includes(&Permissions::default()));
+        assert!(!admin.includes(&Permissions::from_names(&["organizations.manage"])));
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::ClaimStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Month,
+    Quarter,
+}
+
+impl Period {
+    /// Field name for `date_trunc`
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Period::Month => "month",
+            Period::Quarter => "quarter",
+        }
+    }
+
+    /// Label of the period starting at the date, e.g. `2023-10` or `2023-Q4`
+    pub fn label(&self, start: NaiveDate) -> String {
+        match self {
+            Period::Month => start.format("%Y-%m").to_string(),
+            Period::Quarter => format!("{}-Q{}", start.year(), start.month0() / 3 + 1),
+        }
+    }
+}
+
+/// Dimensions the totals are grouped by, all of them left out means one grand total
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Grouping {
+    pub user: bool,
+    pub category: bool,
+    pub status: bool,
+    pub period: Option<Period>,
+}
+
+impl Grouping {
+    pub fn is_empty(&self) -> bool {
+        *self == Grouping::default()
+    }
+}
+
+/// Parses a comma separated list of `user`, `category`, `status`, `month` and `quarter`
+pub fn parse_grouping(value: &str) -> Result<Grouping, &'static str> {
+    let mut grouping = Grouping::default();
+    for dimension in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let period = match dimension {
+            "user" => {
+                grouping.user = true;
+                None
+            }
+            "category" => {
+                grouping.category = true;
+                None
+            }
+            "status" => {
+                grouping.status = true;
+                None
+            }
+            "month" => Some(Period::Month),
+            "quarter" => Some(Period::Quarter),
+            _ => return Err("Unknown grouping"),
+        };
+        if period.is_some() {
+            if grouping.period.is_some() {
+                return Err("Group by either month or quarter");
+            }
+            grouping.period = period;
+        }
+    }
+    Ok(grouping)
+}
+
+/// Totals of one group, dimensions the report is not grouped by are left out
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportRow {
+    #[serde(rename = "userId", skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(rename = "categoryId", skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ClaimStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<String>,
+    pub totals: Totals,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub claims: i64,
+    pub items: i64,
+    pub cost: Decimal,
+    pub reimbursement: Decimal,
+    pub tax: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_grouping() {
+        assert!(parse_grouping("").unwrap().is_empty());
+        assert!(!parse_grouping("month").unwrap().is_empty());
+        assert_eq!(
+            parse_grouping("user, quarter,status").unwrap(),
+            Grouping {
+                user: true,
+                category: false,
+                status: true,
+                period: Some(Period::Quarter),
+            }
+        );
+        parse_grouping("month,quarter").unwrap_err();
+        parse_grouping("user,week").unwrap_err();
+    }
+
+    #[test]
+    fn test_period_label() {
+        let date = NaiveDate::from_ymd_opt(2023, 10, 1).unwrap();
+        assert_eq!(Period::Month.label(date), "2023-10");
+        assert_eq!(Period::Quarter.label(date), "2023-Q4");
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        assert_eq!(Period::Quarter.label(date), "2024-Q1");
+    }
+}
@@ -0,0 +1,102 @@
+use sqlx::PgConnection;
+
+use crate::models::Role;
+use crate::permissions::{self, Permission, Permissions};
+
+#[derive(Debug)]
+pub enum RoleChangeError {
+    UserNotFound,
+    UnknownRole,
+    /// The old or the new role allows something the caller is not allowed to do
+    NotPermitted,
+    Unchanged,
+    LastAdmin,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RoleChangeError {
+    fn from(error: sqlx::Error) -> Self {
+        RoleChangeError::Database(error)
+    }
+}
+
+/// Sets the role of a user of the organisation and records who changed it. The sessions
+/// of the user are invalidated so the new permissions apply to the next request. Should
+/// run in a transaction, the users who manage users are locked until it ends.
+pub async fn change_role(
+    conn: &mut PgConnection,
+    organization_id: i32,
+    user_id: i32,
+    role: &Role,
+    changed_by: Option<(i32, &Permissions)>,
+) -> Result<Role, RoleChangeError> {
+    let old_role: Role = sqlx::query_scalar!(
+        "SELECT role FROM users WHERE id = $1 AND organization_id = $2 FOR UPDATE",
+        user_id,
+        organization_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(RoleChangeError::UserNotFound)?
+    .into();
+    if old_role == *role {
+        return Err(RoleChangeError::Unchanged);
+    }
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM roles WHERE name = $1 AND (organization_id IS NULL OR organization_id = $2)
+        ) AS "exists!""#,
+        role.to_string(),
+        organization_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if !exists {
+        return Err(RoleChangeError::UnknownRole);
+    }
+    let new_permissions =
+        permissions::for_role(&mut *conn, organization_id, &role.to_string()).await?;
+    if let Some((_, granter)) = changed_by {
+        // Admins of an organisation cannot hand out or take away more than they have,
+        // such as making someone a super-admin
+        let old_permissions =
+            permissions::for_role(&mut *conn, organization_id, &old_role.to_string()).await?;
+        if !granter.includes(&old_permissions) || !granter.includes(&new_permissions) {
+            return Err(RoleChangeError::NotPermitted);
+        }
+    }
+
+    // Someone has to keep the right to change roles. Locking the users who have it
+    // keeps two concurrent demotions from both seeing the other one.
+    let admins = sqlx::query_scalar!(
+        "SELECT users.id FROM users JOIN role_permissions ON role_permissions.role = users.role
+        AND (role_permissions.organization_id IS NULL OR role_permissions.organization_id = users.organization_id)
+        WHERE role_permissions.permission = $1 AND users.organization_id = $2 FOR UPDATE OF users",
+        Permission::UsersManage.name(),
+        organization_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let keeps_admin = new_permissions.has(Permission::UsersManage);
+    if !keeps_admin && admins == [user_id] {
+        return Err(RoleChangeError::LastAdmin);
+    }
+
+    sqlx::query!(
+        "UPDATE users SET role = $1, session_version = session_version + 1 WHERE id = $2",
+        role.to_string(),
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO role_changes ( user_id, changed_by, old_role, new_role ) VALUES ($1, $2, $3, $4)",
+        user_id,
+        changed_by.map(|(id, _)| id),
+        old_role.to_string(),
+        role.to_string()
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(old_role)
+}
@@ -2,11 +2,16 @@ use std::sync::Arc;
 
 use axum::{
     middleware,
-    routing::{delete, get, patch, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 
-use crate::{handlers, jwt_auth::auth, AppState};
+use crate::{
+    handlers,
+    jwt_auth::auth,
+    permissions::{self, Permission},
+    AppState,
+};
 
 #[inline]
 pub fn create_router(app_state: Arc<AppState>) -> Router {
@@ -15,6 +20,14 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
             $route.route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
         };
     }
+    macro_rules! permitted {
+        ($route: expr, $permission: expr) => {
+            authorized!($route.route_layer(middleware::from_fn_with_state(
+                $permission,
+                permissions::require
+            )))
+        };
+    }
 
     Router::new()
         .route("/ping", get(handlers::health))
@@ -22,42 +35,252 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
         .route("/auth/verifyemail/:code", get(handlers::verify_email))
         .route("/auth/login", post(handlers::login_user))
         .route("/auth/logout", authorized!(get(handlers::logout_user)))
-        .route("/users/count", get(handlers::users_count))
-        .route("/users/list", get(handlers::users_list))
-        .route("/users/startswith", get(handlers::users_startswith))
+        .route("/users/count", authorized!(get(handlers::users_count)))
+        .route("/users/list", authorized!(get(handlers::users_list)))
+        .route(
+            "/users/startswith",
+            authorized!(get(handlers::users_startswith)),
+        )
         .route("/users/me", authorized!(get(handlers::users_me)))
-        .route("/users/withid", get(handlers::get_user_by_id))
+        .route("/users/withid", authorized!(get(handlers::get_user_by_id)))
         .route(
             "/users/delete_account",
             authorized!(delete(handlers::users_delete_account)),
         )
         .route(
-            "/users/make_manager/:user_id",
-            authorized!(get(handlers::make_manager)),
+            "/users/:user_id/role",
+            permitted!(patch(handlers::set_role), Permission::UsersManage),
+        )
+        .route("/roles/list", authorized!(get(handlers::list_roles)))
+        .route(
+            "/roles/create",
+            permitted!(post(handlers::create_role), Permission::RolesManage),
+        )
+        .route(
+            "/roles/update/:name",
+            permitted!(patch(handlers::update_role), Permission::RolesManage),
+        )
+        .route(
+            "/roles/delete/:name",
+            permitted!(delete(handlers::delete_role), Permission::RolesManage),
+        )
+        .route(
+            "/organizations/list",
+            permitted!(
+                get(handlers::list_organizations),
+                Permission::OrganizationsManage
+            ),
+        )
+        .route(
+            "/organizations/create",
+            permitted!(
+                post(handlers::create_organization),
+                Permission::OrganizationsManage
+            ),
+        )
+        .route(
+            "/organizations/update/:organization_id",
+            permitted!(
+                patch(handlers::rename_organization),
+                Permission::OrganizationsManage
+            ),
+        )
+        .route(
+            "/organizations/switch/:organization_id",
+            permitted!(
+                post(handlers::switch_organization),
+                Permission::OrganizationsManage
+            ),
+        )
+        .route(
+            "/organizations/settings",
+            permitted!(
+                put(handlers::set_organization_settings),
+                Permission::SettingsManage
+            ),
+        )
+        .route(
+            "/users/set_manager/:user_id",
+            permitted!(patch(handlers::set_manager), Permission::UsersManage),
+        )
+        .route(
+            "/users/set_cost_center/:user_id",
+            permitted!(patch(handlers::set_cost_center), Permission::UsersManage),
+        )
+        .route("/users/reports", authorized!(get(handlers::list_reports)))
+        .route(
+            "/users/import",
+            permitted!(post(handlers::import_users), Permission::UsersManage),
         )
         .route(
             "/categories/create",
-            authorized!(post(handlers::create_category)),
+            permitted!(
+                post(handlers::create_category),
+                Permission::CategoriesManage
+            ),
+        )
+        .route(
+            "/categories/import",
+            permitted!(
+                post(handlers::import_categories),
+                Permission::CategoriesManage
+            ),
         )
         .route(
             "/categories/delete/:category_id",
-            authorized!(delete(handlers::delete_category)),
+            permitted!(
+                delete(handlers::delete_category),
+                Permission::CategoriesManage
+            ),
+        )
+        .route(
+            "/categories/list",
+            authorized!(get(handlers::list_categories)),
         )
-        .route("/categories/list", get(handlers::list_categories))
         .route(
             "/categories/update/:category_id",
-            patch(handlers::update_category),
+            permitted!(
+                patch(handlers::update_category),
+                Permission::CategoriesManage
+            ),
+        )
+        .route(
+            "/charge_codes/create",
+            permitted!(
+                post(handlers::create_charge_code),
+                Permission::ChargeCodesManage
+            ),
+        )
+        .route(
+            "/charge_codes/list",
+            authorized!(get(handlers::list_charge_codes)),
         )
-        .route("/claims/my", authorized!(get(handlers::list_my_claims)))
         .route(
-            "/claims/pending",
-            authorized!(get(handlers::list_pedning_claims)),
+            "/charge_codes/update/:code_id",
+            permitted!(
+                patch(handlers::update_charge_code),
+                Permission::ChargeCodesManage
+            ),
+        )
+        .route("/claims/search", authorized!(get(handlers::search_claims)))
+        .route("/claims/export", authorized!(get(handlers::export_claims)))
+        .route("/claims/:claim_id", authorized!(get(handlers::get_claim)))
+        .route(
+            "/claims/report/:claim_id",
+            authorized!(get(handlers::claim_report)),
         )
         .route("/claims/create", authorized!(post(handlers::create_claim)))
         .route(
             "/claims/approve/:claim_id",
             authorized!(get(handlers::approve_claim)),
         )
-        .route("/claims/estimate_item", post(handlers::estimate_item))
+        .route(
+            "/claims/decide_bulk",
+            authorized!(post(handlers::decide_claims_bulk)),
+        )
+        .route(
+            "/claims/estimate_item",
+            authorized!(post(handlers::estimate_item)),
+        )
+        .route(
+            "/receipts/upload",
+            authorized!(post(handlers::upload_receipt)),
+        )
+        .route(
+            "/delegations/create",
+            permitted!(post(handlers::create_delegation), Permission::ClaimsApprove),
+        )
+        .route(
+            "/delegations/my",
+            authorized!(get(handlers::list_my_delegations)),
+        )
+        .route(
+            "/delegations/delete/:delegation_id",
+            authorized!(delete(handlers::delete_delegation)),
+        )
+        .route(
+            "/payments/create",
+            permitted!(
+                post(handlers::create_payment_batch),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/payments/list",
+            permitted!(
+                get(handlers::list_payment_batches),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/payments/claims/:batch_id",
+            permitted!(
+                get(handlers::list_payment_batch_claims),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/payments/export/:batch_id",
+            permitted!(
+                patch(handlers::export_payment_batch),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/payments/pay/:batch_id",
+            permitted!(
+                patch(handlers::pay_payment_batch),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/payments/sepa/:batch_id",
+            permitted!(
+                get(handlers::sepa_payment_batch),
+                Permission::PaymentsManage
+            ),
+        )
+        .route(
+            "/bank_details/get/:user_id",
+            authorized!(get(handlers::get_bank_details)),
+        )
+        .route(
+            "/bank_details/set/:user_id",
+            authorized!(put(handlers::set_bank_details)),
+        )
+        .route(
+            "/accounting/export",
+            permitted!(
+                post(handlers::export_accounting),
+                Permission::AccountingExport
+            ),
+        )
+        .route(
+            "/accounting/list",
+            permitted!(
+                get(handlers::list_accounting_exports),
+                Permission::AccountingExport
+            ),
+        )
+        .route(
+            "/reporting/totals",
+            permitted!(get(handlers::report_totals), Permission::ReportsView),
+        )
+        .route(
+            "/budgets/create",
+            permitted!(post(handlers::create_budget), Permission::BudgetsManage),
+        )
+        .route(
+            "/budgets/list",
+            permitted!(get(handlers::list_budgets), Permission::ReportsView),
+        )
+        .route(
+            "/budgets/update/:budget_id",
+            permitted!(patch(handlers::update_budget), Permission::BudgetsManage),
+        )
+        .route(
+            "/budgets/delete/:budget_id",
+            permitted!(delete(handlers::delete_budget), Permission::BudgetsManage),
+        )
         .with_state(app_state)
 }
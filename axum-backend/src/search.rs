@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+
+use crate::models::Claim;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 200;
+
+/// Columns claims can be sorted by, ties are broken by id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    TotalCost,
+    Reimbursement,
+    Status,
+    UserId,
+}
+
+impl FromStr for SortKey {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "id" => Ok(SortKey::Id),
+            "created_at" => Ok(SortKey::CreatedAt),
+            "updated_at" => Ok(SortKey::UpdatedAt),
+            "total_cost" => Ok(SortKey::TotalCost),
+            "reimbursement" => Ok(SortKey::Reimbursement),
+            "status" => Ok(SortKey::Status),
+            "user_id" => Ok(SortKey::UserId),
+            _ => Err("Unknown sort column"),
+        }
+    }
+}
+
+impl SortKey {
+    /// SQL expression sorted on, never NULL so it can be compared with the cursor
+    pub fn expression(&self) -> &'static str {
+        match self {
+            SortKey::Id => "claims.id",
+            SortKey::CreatedAt => "claims.created_at",
+            SortKey::UpdatedAt => "claims.updated_at",
+            SortKey::TotalCost => "COALESCE(claims.total_cost, 0)",
+            SortKey::Reimbursement => "COALESCE(claims.reimbursement, 0)",
+            SortKey::Status => "claims.status",
+            SortKey::UserId => "claims.user_id",
+        }
+    }
+
+    fn value(&self, claim: &Claim) -> SortValue {
+        match self {
+            SortKey::Id => SortValue::Integer(claim.id),
+            SortKey::CreatedAt => SortValue::Timestamp(claim.created_at),
+            SortKey::UpdatedAt => SortValue::Timestamp(claim.updated_at),
+            SortKey::TotalCost => SortValue::Decimal(claim.total_cost.unwrap_or_default()),
+            SortKey::Reimbursement => SortValue::Decimal(claim.reimbursement.unwrap_or_default()),
+            SortKey::Status => SortValue::Text(claim.status.to_string()),
+            SortKey::UserId => SortValue::Integer(claim.user_id),
+        }
+    }
+
+    fn parse_value(&self, value: &str) -> Option<SortValue> {
+        match self {
+            SortKey::Id | SortKey::UserId => value.parse().ok().map(SortValue::Integer),
+            SortKey::CreatedAt | SortKey::UpdatedAt => {
+                NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+                    .ok()
+                    .map(SortValue::Timestamp)
+            }
+            SortKey::TotalCost | SortKey::Reimbursement => {
+                value.parse().ok().map(SortValue::Decimal)
+            }
+            SortKey::Status => Some(SortValue::Text(value.to_owned())),
+        }
+    }
+}
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Integer(i32),
+    Decimal(Decimal),
+    Timestamp(NaiveDateTime),
+    Text(String),
+}
+
+/// Position after the last claim of a page
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub id: i32,
+    pub value: SortValue,
+}
+
+/// Encodes the position after the claim as `<id>:<sort value>`
+pub fn encode_cursor(key: SortKey, claim: &Claim) -> String {
+    let value = match key.value(claim) {
+        SortValue::Integer(value) => value.to_string(),
+        SortValue::Decimal(value) => value.to_string(),
+        SortValue::Timestamp(value) => value.format(TIMESTAMP_FORMAT).to_string(),
+        SortValue::Text(value) => value,
+    };
+    format!("{}:{}", claim.id, value)
+}
+
+/// Cursors are only valid for the sort column they were created with
+pub fn decode_cursor(key: SortKey, cursor: &str) -> Result<Cursor, &'static str> {
+    cursor
+        .split_once(':')
+        .and_then(|(id, value)| {
+            Some(Cursor {
+                id: id.parse().ok()?,
+                value: key.parse_value(value)?,
+            })
+        })
+        .ok_or("Invalid cursor")
+}
+
+/// Pattern matching the text anywhere, with the LIKE wildcards in it escaped
+pub fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::models::ClaimStatus;
+
+    fn claim() -> Claim {
+        let created_at = NaiveDate::from_ymd_opt(2023, 11, 2)
+            .unwrap()
+            .and_hms_micro_opt(9, 30, 15, 250)
+            .unwrap();
+        Claim {
+            id: 42,
+            user_id: 3,
+            total_cost: Some(Decimal::new(12050, 2)),
+            reimbursement: None,
+            status: ClaimStatus::Pending,
+            current_step: 0,
+            step_started_at: created_at,
+            payment_batch_id: None,
+            paid_at: None,
+            payment_reference: None,
+            total_tax: None,
+            cost_center_id: None,
+            project_id: None,
+            created_at,
+            submitted_at: Some(created_at),
+            decided_at: None,
+            updated_at: created_at,
+            description: None,
+            organization_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let claim = claim();
+        for key in [
+            SortKey::Id,
+            SortKey::CreatedAt,
+            SortKey::UpdatedAt,
+            SortKey::TotalCost,
+            SortKey::Reimbursement,
+            SortKey::Status,
+            SortKey::UserId,
+        ] {
+            let cursor = decode_cursor(key, &encode_cursor(key, &claim)).unwrap();
+            assert_eq!(cursor.id, 42);
+            assert_eq!(cursor.value, key.value(&claim));
+        }
+        assert_eq!(
+            encode_cursor(SortKey::CreatedAt, &claim),
+            "42:2023-11-02T09:30:15.000250"
+        );
+        assert_eq!(encode_cursor(SortKey::Reimbursement, &claim), "42:0");
+    }
+
+    #[test]
+    fn test_invalid_cursor() {
+        decode_cursor(SortKey::Id, "42").unwrap_err();
+        decode_cursor(SortKey::Id, "x:1").unwrap_err();
+        decode_cursor(SortKey::CreatedAt, "42:120.50").unwrap_err();
+        decode_cursor(SortKey::TotalCost, "42:2023-11-02T09:30:15").unwrap_err();
+    }
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!(contains_pattern("hotel"), "%hotel%");
+        assert_eq!(contains_pattern("100%_a\\b"), "%100\\%\\_a\\\\b%");
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::Write;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+
+use crate::config::Config;
+
+const NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:pain.001.001.03";
+
+/// Credit transfer to one employee, covering all their claims in the batch
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub user_id: i32,
+    pub account_holder: String,
+    pub iban: String,
+    pub bic: Option<String>,
+    pub amount: Decimal,
+    pub claim_ids: Vec<i32>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Escaped text limited to `max` characters, the schema limits apply before escaping
+fn text(value: &str, max: usize) -> String {
+    escape(&value.trim().chars().take(max).collect::<String>())
+}
+
+fn amount(value: Decimal) -> String {
+    format!("{:.2}", value.round_dp(2))
+}
+
+/// Builds a pain.001.001.03 customer credit transfer initiation for a payment batch,
+/// paid from the debtor account in the configuration
+pub fn credit_transfer_initiation(
+    config: &Config,
+    batch_id: i32,
+    transfers: &[Transfer],
+    created_at: NaiveDateTime,
+    execution_date: NaiveDate,
+) -> String {
+    let count = transfers.len();
+    let control_sum: Decimal = transfers
+        .iter()
+        .map(|transfer| transfer.amount.round_dp(2))
+        .sum();
+    let debtor_name = text(&config.debtor_name, 70);
+
+    let mut xml = String::new();
+    // Writing to a String cannot fail
+    let _ = write!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="{NAMESPACE}">
+  <CstmrCdtTrfInitn>
+    <GrpHdr>
+      <MsgId>RECALC-BATCH-{batch_id}</MsgId>
+      <CreDtTm>{created_at}</CreDtTm>
+      <NbOfTxs>{count}</NbOfTxs>
+      <CtrlSum>{control_sum}</CtrlSum>
+      <InitgPty>
+        <Nm>{debtor_name}</Nm>
+      </InitgPty>
+    </GrpHdr>
+    <PmtInf>
+      <PmtInfId>RECALC-PMT-{batch_id}</PmtInfId>
+      <PmtMtd>TRF</PmtMtd>
+      <NbOfTxs>{count}</NbOfTxs>
+      <CtrlSum>{control_sum}</CtrlSum>
+      <PmtTpInf>
+        <SvcLvl>
+          <Cd>SEPA</Cd>
+        </SvcLvl>
+      </PmtTpInf>
+      <ReqdExctnDt>{execution_date}</ReqdExctnDt>
+      <Dbtr>
+        <Nm>{debtor_name}</Nm>
+      </Dbtr>
+      <DbtrAcct>
+        <Id>
+          <IBAN>{debtor_iban}</IBAN>
+        </Id>
+      </DbtrAcct>
+      <DbtrAgt>
+        <FinInstnId>
+{debtor_agent}
+        </FinInstnId>
+      </DbtrAgt>
+      <ChrgBr>SLEV</ChrgBr>
+"#,
+        control_sum = amount(control_sum),
+        created_at = created_at.format("%Y-%m-%dT%H:%M:%S"),
+        execution_date = execution_date.format("%Y-%m-%d"),
+        debtor_iban = escape(&config.debtor_iban),
+        debtor_agent = match &config.debtor_bic {
+            Some(bic) => format!("          <BIC>{}</BIC>", escape(bic)),
+            // The BIC is optional for SEPA transfers since 2016
+            None =>
+                "          <Othr>\n            <Id>NOTPROVIDED</Id>\n          </Othr>".to_string(),
+        },
+    );
+
+    for transfer in transfers {
+        let claims = transfer
+            .claim_ids
+            .iter()
+            .map(i32::to_string)
+            .collect::<Vec<String>>()
+            .join(", ");
+        let _ = write!(
+            xml,
+            r#"      <CdtTrfTxInf>
+        <PmtId>
+          <EndToEndId>RECALC-{batch_id}-{user_id}</EndToEndId>
+        </PmtId>
+        <Amt>
+          <InstdAmt Ccy="{currency}">{amount}</InstdAmt>
+        </Amt>
+{creditor_agent}        <Cdtr>
+          <Nm>{name}</Nm>
+        </Cdtr>
+        <CdtrAcct>
+          <Id>
+            <IBAN>{iban}</IBAN>
+          </Id>
+        </CdtrAcct>
+        <RmtInf>
+          <Ustrd>{remittance}</Ustrd>
+        </RmtInf>
+      </CdtTrfTxInf>
+"#,
+            user_id = transfer.user_id,
+            currency = escape(&config.payment_currency),
+            amount = amount(transfer.amount),
+            creditor_agent = match &transfer.bic {
+                Some(bic) => format!(
+                    "        <CdtrAgt>\n          <FinInstnId>\n            <BIC>{}</BIC>\n          </FinInstnId>\n        </CdtrAgt>\n",
+                    escape(bic)
+                ),
+                None => String::new(),
+            },
+            name = text(&transfer.account_holder, 70),
+            iban = escape(&transfer.iban),
+            remittance = text(&format!("Expense claims {}", claims), 140),
+        );
+    }
+
+    xml.push_str("    </PmtInf>\n  </CstmrCdtTrfInitn>\n</Document>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libxml::parser::Parser;
+    use libxml::schemas::{SchemaParserContext, SchemaValidationContext};
+
+    fn validate(xml: &str) -> Result<(), Vec<String>> {
+        let document = Parser::default()
+            .parse_string(xml)
+            .expect("Generated document is not well formed");
+        let mut schema = SchemaParserContext::from_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/schemas/pain.001.001.03.xsd"
+        ));
+        let mut context = SchemaValidationContext::from_parser(&mut schema)
+            .expect("Could not load the pain.001 schema");
+        context.validate_document(&document).map_err(|errors| {
+            errors
+                .into_iter()
+                .filter_map(|error| error.message)
+                .collect()
+        })
+    }
+
+    fn transfers() -> Vec<Transfer> {
+        vec![
+            Transfer {
+                user_id: 2,
+                account_holder: "Jane <Doe> & Sons".to_string(),
+                iban: "FR1420041010050500013M02606".to_string(),
+                bic: Some("PSSTFRPPLIL".to_string()),
+                amount: Decimal::new(12345, 2),
+                claim_ids: vec![4, 7],
+            },
+            Transfer {
+                user_id: 3,
+                account_holder: "x".repeat(100),
+                iban: "NL91ABNA0417164300".to_string(),
+                bic: None,
+                amount: Decimal::new(10, 0),
+                claim_ids: (1..100).collect(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_credit_transfer_initiation() {
+        let config = Config::for_tests();
+        let created_at = NaiveDate::from_ymd_opt(2023, 10, 28)
+            .unwrap()
+            .and_hms_opt(12, 30, 0)
+            .unwrap();
+        let xml =
+            credit_transfer_initiation(&config, 1, &transfers(), created_at, created_at.date());
+        validate(&xml).unwrap();
+        assert!(xml.contains("<CtrlSum>133.45</CtrlSum>"));
+        assert!(xml.contains("<NbOfTxs>2</NbOfTxs>"));
+        assert!(xml.contains("Jane &lt;Doe&gt; &amp; Sons"));
+        assert!(xml.contains(r#"<InstdAmt Ccy="EUR">10.00</InstdAmt>"#));
+
+        let config = Config {
+            debtor_bic: None,
+            ..config
+        };
+        let xml =
+            credit_transfer_initiation(&config, 1, &transfers(), created_at, created_at.date());
+        validate(&xml).unwrap();
+
+        let mut invalid = transfers();
+        invalid[0].iban = "not an iban".to_string();
+        let xml = credit_transfer_initiation(&config, 1, &invalid, created_at, created_at.date());
+        validate(&xml).unwrap_err();
+
+        // At least one transaction is required
+        let xml = credit_transfer_initiation(&config, 1, &[], created_at, created_at.date());
+        validate(&xml).unwrap_err();
+    }
+}
@@ -0,0 +1,166 @@
+use rust_decimal::Decimal;
+
+use crate::models::{Category, ReimbursementBase};
+
+/// Largest difference accepted between net + tax and the gross amount
+const ROUNDING_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
+
+/// Gross amount of an item split into net amount and tax
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxBreakdown {
+    pub net: Decimal,
+    pub tax: Decimal,
+    /// Rate in percent of the net amount
+    pub rate: Decimal,
+}
+
+fn tax_from_rate(gross: Decimal, rate: Decimal) -> Decimal {
+    (gross * rate / (Decimal::from(100) + rate)).round_dp(2)
+}
+
+/// Splits the gross amount using whatever the item specifies, the tax amount taking
+/// precedence over the net amount and the rate. Items without any of them use the default rate.
+pub fn breakdown(
+    gross: Decimal,
+    tax_rate: Option<Decimal>,
+    tax_amount: Option<Decimal>,
+    net_amount: Option<Decimal>,
+    default_rate: Decimal,
+) -> Result<TaxBreakdown, &'static str> {
+    let tax = match (tax_amount, net_amount) {
+        (Some(tax), _) => tax,
+        (None, Some(net)) => gross - net,
+        (None, None) => tax_from_rate(gross, tax_rate.unwrap_or(default_rate)),
+    };
+    if tax < Decimal::from(0) || tax >= gross {
+        return Err("Tax must be between zero and the gross amount");
+    }
+
+    let net = net_amount.unwrap_or(gross - tax);
+    if (net + tax - gross).abs() > ROUNDING_TOLERANCE {
+        return Err("Net amount and tax do not add up to the gross amount");
+    }
+
+    let rate = match tax_rate {
+        Some(rate) if (tax_from_rate(gross, rate) - tax).abs() > ROUNDING_TOLERANCE => {
+            return Err("Tax amount does not match the tax rate")
+        }
+        Some(rate) => rate,
+        None if tax_amount.is_none() && net_amount.is_none() => default_rate,
+        None => (tax * Decimal::from(100) / net).round_dp(2),
+    };
+
+    Ok(TaxBreakdown { net, tax, rate })
+}
+
+/// Reimbursement of the item under the category policy, and the part of it that is tax.
+/// Reimbursing the net amount leaves the tax with the employee, so none of it is reclaimable.
+pub fn reimbursement(
+    category: &Category,
+    gross: Decimal,
+    breakdown: &TaxBreakdown,
+) -> (Decimal, Decimal) {
+    let base = match category.reimbursement_base {
+        ReimbursementBase::Gross => gross,
+        ReimbursementBase::Net => breakdown.net,
+    };
+    let reimbursement = (category.reimbursement_percentage * base / Decimal::from(100))
+        .min(category.max_reimbursement);
+    let tax = match category.reimbursement_base {
+        ReimbursementBase::Gross => (reimbursement * breakdown.tax / gross).round_dp(2),
+        ReimbursementBase::Net => Decimal::from(0),
+    };
+    (reimbursement, tax)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(base: ReimbursementBase) -> Category {
+        Category {
+            id: 1,
+            name: "Hotel".to_string(),
+            reimbursement_percentage: Decimal::from(50),
+            max_reimbursement: Decimal::from(1000),
+            gl_account: None,
+            default_tax_rate: Decimal::from(19),
+            reimbursement_base: base,
+            organization_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let gross = Decimal::from(119);
+        let expected = TaxBreakdown {
+            net: Decimal::from(100),
+            tax: Decimal::from(19),
+            rate: Decimal::from(19),
+        };
+        let default_rate = Decimal::from(19);
+        assert_eq!(
+            breakdown(gross, None, None, None, default_rate).unwrap(),
+            expected
+        );
+        assert_eq!(
+            breakdown(gross, Some(Decimal::from(19)), None, None, Decimal::from(7)).unwrap(),
+            expected
+        );
+        assert_eq!(
+            breakdown(gross, None, Some(Decimal::from(19)), None, Decimal::from(7)).unwrap(),
+            expected
+        );
+        assert_eq!(
+            breakdown(
+                gross,
+                None,
+                None,
+                Some(Decimal::from(100)),
+                Decimal::from(7)
+            )
+            .unwrap(),
+            expected
+        );
+        // Rounding differences of a cent are fine
+        breakdown(
+            gross,
+            None,
+            Some(Decimal::new(1901, 2)),
+            Some(Decimal::from(100)),
+            default_rate,
+        )
+        .unwrap();
+        breakdown(
+            gross,
+            None,
+            Some(Decimal::from(20)),
+            Some(Decimal::from(100)),
+            default_rate,
+        )
+        .unwrap_err();
+        breakdown(
+            gross,
+            Some(Decimal::from(7)),
+            Some(Decimal::from(19)),
+            None,
+            default_rate,
+        )
+        .unwrap_err();
+        breakdown(gross, None, Some(gross), None, default_rate).unwrap_err();
+    }
+
+    #[test]
+    fn test_reimbursement() {
+        let gross = Decimal::from(119);
+        let split = breakdown(gross, None, None, None, Decimal::from(19)).unwrap();
+        assert_eq!(
+            reimbursement(&category(ReimbursementBase::Gross), gross, &split),
+            (Decimal::new(595, 1), Decimal::new(950, 2))
+        );
+        assert_eq!(
+            reimbursement(&category(ReimbursementBase::Net), gross, &split),
+            (Decimal::from(50), Decimal::from(0))
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use std::future::Future;
+
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgConnection;
+
+tokio::task_local! {
+    static ORGANIZATION_ID: i32;
+}
+
+/// Runs the future on behalf of the organisation, connections it takes from the pool
+/// only see the rows of that organisation under row level security
+pub async fn scope<F: Future>(organization_id: i32, future: F) -> F::Output {
+    ORGANIZATION_ID.scope(organization_id, future).await
+}
+
+/// Organisation of the running request, `None` for background jobs and the admin CLI
+pub fn current() -> Option<i32> {
+    ORGANIZATION_ID.try_with(|id| *id).ok()
+}
+
+/// Sets the organisation the row level security policies compare against,
+/// an empty value lifts the restriction
+async fn apply(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let organization_id = current().map(|id| id.to_string()).unwrap_or_default();
+    sqlx::query("SELECT set_config('app.organization_id', $1, false)")
+        .bind(organization_id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Applies the organisation of the acquiring task to every connection handed out by the pool
+pub fn pool_options() -> PgPoolOptions {
+    PgPoolOptions::new()
+        .after_connect(|conn, _| Box::pin(apply(conn)))
+        .before_acquire(|conn, _| Box::pin(async move { apply(conn).await.map(|()| true) }))
+}
@@ -0,0 +1,161 @@
+//! Fixtures for the tests that run against Postgres. They are ignored by default, run them with
+//! `cargo test -- --include-ignored` and a `DATABASE_URL` allowed to create databases, every
+//! test gets a fresh database with all migrations applied.
+
+use std::sync::Arc;
+
+use axum::body::HttpBody;
+use axum::response::IntoResponse;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+
+use crate::config::Config;
+use crate::models::{Claim, ClaimStatus, Organization, OrganizationSettings, User};
+use crate::{tenant, AppState};
+
+pub fn app_state(pool: sqlx::PgPool) -> Arc<AppState> {
+    let config = Config::for_tests();
+    Arc::new(AppState {
+        pool,
+        export_permits: Arc::new(tokio::sync::Semaphore::new(config.max_concurrent_exports)),
+        config,
+    })
+}
+
+/// Name of the role made by `restricted_pool`
+fn restricted_role(database: &str) -> String {
+    format!("{}_app", database)
+}
+
+/// Pool of the test database connecting as a role that row level security applies to, like
+/// the role the server should use. The tests themselves connect as a superuser, who skips it.
+pub async fn restricted_pool(conn: &mut PgConnection, pool: &PgPool) -> PgPool {
+    let database: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    let role = restricted_role(&database);
+    // Roles belong to the whole server, a failed test may have left one behind
+    for statement in [
+        format!(r#"DROP ROLE IF EXISTS "{}""#, role),
+        format!(r#"CREATE ROLE "{}" NOSUPERUSER NOBYPASSRLS"#, role),
+        format!(r#"GRANT USAGE ON SCHEMA public TO "{}""#, role),
+        format!(
+            r#"GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO "{}""#,
+            role
+        ),
+        format!(
+            r#"GRANT USAGE ON ALL SEQUENCES IN SCHEMA public TO "{}""#,
+            role
+        ),
+    ] {
+        sqlx::query(&statement).execute(&mut *conn).await.unwrap();
+    }
+    let options = pool.connect_options().as_ref().clone();
+    tenant::pool_options()
+        .connect_with(options.options([("role", role.as_str())]))
+        .await
+        .unwrap()
+}
+
+/// Closes the pool made by `restricted_pool` and drops its role
+pub async fn drop_restricted_pool(conn: &mut PgConnection, pool: PgPool) {
+    pool.close().await;
+    let database: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    let role = restricted_role(&database);
+    for statement in [
+        format!(r#"DROP OWNED BY "{}""#, role),
+        format!(r#"DROP ROLE "{}""#, role),
+    ] {
+        sqlx::query(&statement).execute(&mut *conn).await.unwrap();
+    }
+}
+
+pub async fn organization(conn: &mut PgConnection, slug: &str) -> Organization {
+    sqlx::query_as!(
+        Organization,
+        r#"INSERT INTO organizations ( slug, name ) VALUES ($1, $1)
+        RETURNING id, slug, name, settings AS "settings: sqlx::types::Json<OrganizationSettings>", created_at"#,
+        slug
+    )
+    .fetch_one(conn)
+    .await
+    .unwrap()
+}
+
+/// Verified user, the password is not usable
+pub async fn user(
+    conn: &mut PgConnection,
+    organization_id: i32,
+    username: &str,
+    role: &str,
+    manager_id: Option<i32>,
+) -> User {
+    sqlx::query_as!(
+        User,
+        "INSERT INTO users ( mail, username, password_hash, role, verified, manager_id, organization_id )
+        VALUES ($1 || '@example.com', $1, '', $2, TRUE, $3, $4) RETURNING *",
+        username,
+        role,
+        manager_id,
+        organization_id
+    )
+    .fetch_one(conn)
+    .await
+    .unwrap()
+}
+
+/// Category reimbursing everything, returns its id
+pub async fn category(conn: &mut PgConnection, organization_id: i32, name: &str) -> i32 {
+    sqlx::query_scalar!(
+        "INSERT INTO categories ( name, reimbursement_percentage, max_reimbursement, organization_id )
+        VALUES ($1, 100, 1000, $2) RETURNING id",
+        name,
+        organization_id
+    )
+    .fetch_one(conn)
+    .await
+    .unwrap()
+}
+
+/// Claim of the user with the given status and a single approval step
+pub async fn claim(
+    conn: &mut PgConnection,
+    user: &User,
+    status: ClaimStatus,
+    reimbursement: Decimal,
+) -> Claim {
+    let claim = sqlx::query_as!(
+        Claim,
+        "INSERT INTO claims ( user_id, total_cost, reimbursement, status, submitted_at, organization_id )
+        VALUES ($1, $2, $2, $3, CURRENT_TIMESTAMP, $4) RETURNING *",
+        user.id,
+        reimbursement,
+        status.to_string(),
+        user.organization_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO claim_steps ( claim_id, position, kind ) VALUES ($1, 0, 'LineManager')",
+        claim.id
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    claim
+}
+
+/// Body of a successful handler response
+pub async fn json_body(response: impl IntoResponse) -> serde_json::Value {
+    let mut body = response.into_response().into_body();
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    serde_json::from_slice(&bytes).unwrap()
+}
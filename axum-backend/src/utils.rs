@@ -1,3 +1,6 @@
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use rand::rngs::OsRng;
 use rand::{distributions::Alphanumeric, Rng};
 
 #[inline]
@@ -11,3 +14,10 @@ pub fn generate_random_string(length: usize) -> String {
 
     random_string
 }
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
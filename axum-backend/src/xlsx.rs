@@ -0,0 +1,353 @@
+use std::io::{self, Write};
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rust_decimal::Decimal;
+
+/// Entries are written with data descriptors, so each part can be sent as soon as it is
+/// compressed. Without ZIP64 a part and the whole archive are limited to 4 GiB.
+struct ZipStream {
+    offset: u64,
+    entries: Vec<ZipEntry>,
+    current: Option<OpenEntry>,
+}
+
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+struct OpenEntry {
+    name: String,
+    offset: u64,
+    encoder: DeflateEncoder<Vec<u8>>,
+    hasher: crc32fast::Hasher,
+    size: u64,
+    compressed_size: u64,
+}
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const VERSION: u16 = 20;
+/// Sizes follow the data, names are UTF-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+const DEFLATE: u16 = 8;
+/// 1980-01-01 00:00 in MS-DOS format
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Archive exceeds 4 GiB")
+}
+
+fn to_u32(value: u64) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| too_large())
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+impl ZipStream {
+    fn new() -> Self {
+        ZipStream {
+            offset: 0,
+            entries: vec![],
+            current: None,
+        }
+    }
+
+    fn emit(&mut self, bytes: Vec<u8>) -> Vec<u8> {
+        self.offset += bytes.len() as u64;
+        bytes
+    }
+
+    fn start_file(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let mut header = self.finish_file()?;
+        let offset = self.offset;
+
+        let mut local = vec![];
+        put_u32(&mut local, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut local, VERSION);
+        put_u16(&mut local, FLAGS);
+        put_u16(&mut local, DEFLATE);
+        put_u16(&mut local, DOS_TIME);
+        put_u16(&mut local, DOS_DATE);
+        // CRC and sizes are in the data descriptor
+        put_u32(&mut local, 0);
+        put_u32(&mut local, 0);
+        put_u32(&mut local, 0);
+        put_u16(&mut local, name.len() as u16);
+        put_u16(&mut local, 0);
+        local.extend_from_slice(name.as_bytes());
+        self.offset += local.len() as u64;
+        header.extend(local);
+
+        self.current = Some(OpenEntry {
+            name: name.to_owned(),
+            offset,
+            encoder: DeflateEncoder::new(vec![], Compression::default()),
+            hasher: crc32fast::Hasher::new(),
+            size: 0,
+            compressed_size: 0,
+        });
+        Ok(header)
+    }
+
+    /// Compressed output available so far, may be empty
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(entry) = self.current.as_mut() else {
+            return Err(io::Error::new(io::ErrorKind::Other, "No open file"));
+        };
+        entry.encoder.write_all(data)?;
+        entry.hasher.update(data);
+        entry.size += data.len() as u64;
+        let compressed = std::mem::take(entry.encoder.get_mut());
+        entry.compressed_size += compressed.len() as u64;
+        Ok(self.emit(compressed))
+    }
+
+    fn finish_file(&mut self) -> io::Result<Vec<u8>> {
+        let Some(mut entry) = self.current.take() else {
+            return Ok(vec![]);
+        };
+        let mut bytes = entry.encoder.finish()?;
+        entry.compressed_size += bytes.len() as u64;
+
+        let finished = ZipEntry {
+            name: entry.name,
+            crc: entry.hasher.finalize(),
+            compressed_size: to_u32(entry.compressed_size)?,
+            size: to_u32(entry.size)?,
+            offset: to_u32(entry.offset)?,
+        };
+        put_u32(&mut bytes, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut bytes, finished.crc);
+        put_u32(&mut bytes, finished.compressed_size);
+        put_u32(&mut bytes, finished.size);
+        self.entries.push(finished);
+        Ok(self.emit(bytes))
+    }
+
+    fn finish(mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = self.finish_file()?;
+        let directory_offset = to_u32(self.offset)?;
+
+        let mut directory = vec![];
+        for entry in &self.entries {
+            put_u32(&mut directory, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut directory, VERSION);
+            put_u16(&mut directory, VERSION);
+            put_u16(&mut directory, FLAGS);
+            put_u16(&mut directory, DEFLATE);
+            put_u16(&mut directory, DOS_TIME);
+            put_u16(&mut directory, DOS_DATE);
+            put_u32(&mut directory, entry.crc);
+            put_u32(&mut directory, entry.compressed_size);
+            put_u32(&mut directory, entry.size);
+            put_u16(&mut directory, entry.name.len() as u16);
+            // Extra field, comment, disk number, internal and external attributes
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u32(&mut directory, 0);
+            put_u32(&mut directory, entry.offset);
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let count = self.entries.len() as u16;
+        let directory_size = to_u32(directory.len() as u64)?;
+        bytes.extend(directory);
+        put_u32(&mut bytes, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        put_u16(&mut bytes, 0);
+        put_u16(&mut bytes, 0);
+        put_u16(&mut bytes, count);
+        put_u16(&mut bytes, count);
+        put_u32(&mut bytes, directory_size);
+        put_u32(&mut bytes, directory_offset);
+        put_u16(&mut bytes, 0);
+        Ok(bytes)
+    }
+}
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const ROOT_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const SHEET_START: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#;
+
+const SHEET_END: &str = "</sheetData></worksheet>";
+
+/// Escapes the text and drops the control characters XML cannot represent
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Number(Decimal),
+    Integer(i64),
+    Empty,
+}
+
+/// Single sheet workbook written one row at a time. Every call returns the bytes
+/// of the file that are ready to be sent.
+pub struct SheetWriter {
+    zip: ZipStream,
+    rows: u32,
+}
+
+impl SheetWriter {
+    pub fn start(sheet_name: &str) -> io::Result<(Self, Vec<u8>)> {
+        let mut zip = ZipStream::new();
+        let workbook = format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            escape(sheet_name)
+        );
+
+        let mut bytes = vec![];
+        for (name, content) in [
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("_rels/.rels", ROOT_RELATIONSHIPS),
+            ("xl/workbook.xml", workbook.as_str()),
+            ("xl/_rels/workbook.xml.rels", WORKBOOK_RELATIONSHIPS),
+            ("xl/worksheets/sheet1.xml", SHEET_START),
+        ] {
+            bytes.extend(zip.start_file(name)?);
+            bytes.extend(zip.write(content.as_bytes())?);
+        }
+        Ok((SheetWriter { zip, rows: 0 }, bytes))
+    }
+
+    pub fn row(&mut self, cells: &[Cell]) -> io::Result<Vec<u8>> {
+        self.rows += 1;
+        let mut xml = format!(r#"<row r="{}">"#, self.rows);
+        for cell in cells {
+            match cell {
+                Cell::Text(value) => xml.push_str(&format!(
+                    r#"<c t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                    escape(value)
+                )),
+                Cell::Number(value) => xml.push_str(&format!("<c><v>{}</v></c>", value)),
+                Cell::Integer(value) => xml.push_str(&format!("<c><v>{}</v></c>", value)),
+                Cell::Empty => xml.push_str("<c/>"),
+            }
+        }
+        xml.push_str("</row>");
+        self.zip.write(xml.as_bytes())
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = self.zip.write(SHEET_END.as_bytes())?;
+        bytes.extend(self.zip.finish()?);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn read_file(archive: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_sheet_writer() {
+        let (mut writer, mut bytes) = SheetWriter::start("Claims & items").unwrap();
+        bytes.extend(
+            writer
+                .row(&[Cell::Text("id".to_string()), Cell::Text("cost".to_string())])
+                .unwrap(),
+        );
+        for id in 1..=1000 {
+            bytes.extend(
+                writer
+                    .row(&[
+                        Cell::Integer(id),
+                        Cell::Number(Decimal::new(id * 101, 2)),
+                        Cell::Text("<Hotel>\u{1}".to_string()),
+                        Cell::Empty,
+                    ])
+                    .unwrap(),
+            );
+        }
+        bytes.extend(writer.finish().unwrap());
+
+        let archive = zip::ZipArchive::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(archive.len(), 5);
+        assert!(read_file(&bytes, "xl/workbook.xml").contains(r#"name="Claims &amp; items""#));
+        let sheet = read_file(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(sheet.starts_with("<?xml"));
+        assert!(sheet.ends_with("</sheetData></worksheet>"));
+        assert!(sheet.contains(
+            r#"<row r="1001"><c><v>1000</v></c><c><v>1010.00</v></c><c t="inlineStr"><is><t xml:space="preserve">&lt;Hotel&gt;</t></is></c><c/></row>"#
+        ));
+    }
+
+    #[test]
+    fn test_sheet_writer_streams_rows() {
+        let (mut writer, mut bytes) = SheetWriter::start("Claims").unwrap();
+        let mut chunks = 0;
+        for id in 1..=20_000 {
+            let chunk = writer
+                .row(&[
+                    Cell::Integer(id),
+                    Cell::Text(format!("Claim {}", id * 7919 % 10_007)),
+                ])
+                .unwrap();
+            if !chunk.is_empty() {
+                chunks += 1;
+            }
+            bytes.extend(chunk);
+        }
+        // The compressed rows are sent long before the workbook is finished
+        assert!(chunks > 1);
+        let streamed = bytes.len();
+        let rest = writer.finish().unwrap();
+        assert!(rest.len() < streamed);
+        bytes.extend(rest);
+
+        let sheet = read_file(&bytes, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains(r#"<row r="20000"><c><v>20000</v></c>"#));
+    }
+}
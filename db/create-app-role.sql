@@ -0,0 +1,5 @@
+-- Runs once when the database container is first created. The server connects as `recalc`,
+-- which owns its database and runs the migrations, but is neither a superuser nor has
+-- BYPASSRLS, so the row level security policies apply to it.
+CREATE ROLE recalc LOGIN PASSWORD 'recalc' NOSUPERUSER NOBYPASSRLS;
+CREATE DATABASE recalc OWNER recalc;
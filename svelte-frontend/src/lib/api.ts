@@ -13,7 +13,9 @@ if (dev) {
 }
 
 export async function fetchUsersCount() {
-  const res = await fetch(`${API_URL}/users/count`);
+  const res = await fetch(`${API_URL}/users/count`, {
+    credentials: "include",
+  });
   const data = await res.json();
   return data;
 };
@@ -35,14 +37,19 @@ export async function login(username: string, password: string): Promise<boolean
   return false;
 }
 
-export async function signup(username: string, mail: string, password: string): Promise<boolean> {
+export async function signup(
+  username: string,
+  mail: string,
+  password: string,
+  organization: string
+): Promise<boolean> {
   const res = await fetch(`${API_URL}/auth/register`, {
     method: "POST",
     credentials: "include",
     headers: {
       "Content-Type": "application/json",
     },
-    body: JSON.stringify({ username, mail, password }),
+    body: JSON.stringify({ username, mail, password, organization }),
   });
 
   if (res.status === 200) {
@@ -102,9 +109,13 @@ export async function getUserById(id: number): Promise<User | null> {
 }
 
 export async function makeUserManager(userId: number): Promise<boolean> {
-  const res = await fetch(`${API_URL}/users/make_manager/${userId}`, {
-    method: "GET",
+  const res = await fetch(`${API_URL}/users/${userId}/role`, {
+    method: "PATCH",
     credentials: "include",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({ role: "Manager" }),
   });
   if (res.status === 200) {
     let data = await res.json();
@@ -201,29 +212,36 @@ export async function createClaim(userId: number, items: Item[]): Promise<boolea
   return false;
 }
 
-export async function getMyClaims(): Promise<Claim[]> {
-  const res = await fetch(`${API_URL}/claims/my`, {
-    method: "GET",
-    credentials: "include",
-  });
-  if (res.status === 200) {
+// Follows the cursor of the claim search until every page is loaded
+async function searchAllClaims(filters: Record<string, string>): Promise<Claim[]> {
+  const claims: Claim[] = [];
+  let cursor: string | null = null;
+  do {
+    const params = new URLSearchParams({ ...filters, limit: "200" });
+    if (cursor) {
+      params.set("cursor", cursor);
+    }
+    const res = await fetch(`${API_URL}/claims/search?${params}`, {
+      method: "GET",
+      credentials: "include",
+    });
+    if (res.status !== 200) {
+      break;
+    }
     let data = await res.json();
-    return data as Claim[];
-  }
-  return [];
+    claims.push(...(data.results as Claim[]));
+    cursor = data.nextCursor ?? null;
+  } while (cursor);
+  return claims;
+}
+
+export async function getMyClaims(): Promise<Claim[]> {
+  return searchAllClaims({ mine: "true" });
 }
 
 
 export async function getPendingClaims(): Promise<Claim[]> {
-  const res = await fetch(`${API_URL}/claims/pending`, {
-    method: "GET",
-    credentials: "include",
-  });
-  if (res.status === 200) {
-    let data = await res.json();
-    return data as Claim[];
-  }
-  return [];
+  return searchAllClaims({ awaiting_approval: "true" });
 }
 
 export async function approveClaim(claimId: number, accept: boolean): Promise<boolean> {
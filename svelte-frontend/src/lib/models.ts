@@ -5,6 +5,8 @@ export type User = {
   role: string;
   verified: boolean;
   createdAt: string;
+  /** Only sent for the current user */
+  permissions?: string[];
 };
 
 export type Category = {